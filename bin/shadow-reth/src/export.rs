@@ -0,0 +1,439 @@
+//! The `shadow-reth shadow export` command, which writes shadow logs to Parquet or CSV files for
+//! analysis in tools such as DuckDB or pandas.
+//!
+//! Files are partitioned by block range. Partitions are aligned to multiples of
+//! `--partition-size`, so that exporting the same blocks again overwrites the same files. The last
+//! exported block is recorded in `export.json` in the output directory, from which `--incremental`
+//! exports continue.
+
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use arrow_array::{ArrayRef, BooleanArray, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::{Field, Schema};
+use clap::{Args, ValueEnum};
+use eyre::{eyre, Result};
+use parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties};
+use reth_primitives::{Address, B256};
+use serde::{Deserialize, Serialize};
+use shadow_reth_common::{BlockFilter, LogFilter, ShadowLog, ShadowStorage, ToLowerHex};
+
+use crate::StorageArgs;
+
+/// Name of the file recording the progress of an export.
+const STATE_FILE: &str = "export.json";
+
+/// Columns of the exported files, in order.
+const COLUMNS: [&str; 14] = [
+    "block_number",
+    "block_hash",
+    "block_timestamp",
+    "transaction_index",
+    "transaction_hash",
+    "block_log_index",
+    "transaction_log_index",
+    "address",
+    "data",
+    "topic_0",
+    "topic_1",
+    "topic_2",
+    "topic_3",
+    "removed",
+];
+
+/// File formats supported by the export command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ExportFormat {
+    /// Apache Parquet, compressed with Snappy.
+    Parquet,
+    /// Comma-separated values with a header row.
+    Csv,
+}
+
+impl ExportFormat {
+    /// Returns the file extension of the format.
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Parquet => "parquet",
+            Self::Csv => "csv",
+        }
+    }
+}
+
+/// Arguments of `shadow-reth shadow export`.
+#[derive(Debug, Args)]
+pub(crate) struct ExportArgs {
+    /// Data directory of the reth node. The shadow database is read from its `db` directory.
+    #[arg(long, value_name = "DATA_DIR")]
+    datadir: Option<PathBuf>,
+
+    #[command(flatten)]
+    storage: StorageArgs,
+
+    /// Directory to which the exported files are written.
+    #[arg(long, short)]
+    output: PathBuf,
+
+    /// Format of the exported files.
+    #[arg(long, value_enum, default_value_t = ExportFormat::Parquet)]
+    format: ExportFormat,
+
+    /// Number of blocks per exported file.
+    #[arg(long, default_value_t = 10_000, value_parser = clap::value_parser!(u64).range(1..))]
+    partition_size: u64,
+
+    /// Only export logs emitted by this address. Can be repeated.
+    #[arg(long = "address", value_name = "ADDRESS")]
+    addresses: Vec<Address>,
+
+    /// Only export logs matching these topics, by position, e.g. `--topics 0xddf2...,0x0000...`.
+    #[arg(long, value_delimiter = ',', num_args = 1..=4)]
+    topics: Vec<B256>,
+
+    /// First block to export. Defaults to the first block which has not been pruned.
+    #[arg(long)]
+    from_block: Option<u64>,
+
+    /// Last block to export. Defaults to the last block processed by the ExEx.
+    #[arg(long)]
+    to_block: Option<u64>,
+
+    /// Continue after the last block exported to `--output` by a previous run with the same
+    /// format and filters.
+    #[arg(long)]
+    incremental: bool,
+}
+
+/// Progress of an export, stored in [`STATE_FILE`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ExportState {
+    /// Format of the exported files.
+    format: ExportFormat,
+    /// Number of blocks per exported file.
+    partition_size: u64,
+    /// Address filter of the export, as lowercase hex strings.
+    addresses: Vec<String>,
+    /// Topic filter of the export, as lowercase hex strings.
+    topics: Vec<String>,
+    /// Last block which has been exported.
+    last_block: u64,
+}
+
+impl ExportArgs {
+    /// Runs the export.
+    pub(crate) async fn run(self) -> Result<()> {
+        let db_dir = self.datadir.as_ref().map(|datadir| datadir.join("db"));
+        let storage = self.storage.storage_config(db_dir.as_deref())?.connect().await?;
+        self.export(storage.as_ref()).await
+    }
+
+    /// Exports the selected logs from `storage`.
+    async fn export(&self, storage: &dyn ShadowStorage) -> Result<()> {
+        std::fs::create_dir_all(&self.output)?;
+        let state_path = self.output.join(STATE_FILE);
+        let mut state = ExportState {
+            format: self.format,
+            partition_size: self.partition_size,
+            addresses: self.addresses.iter().map(ToLowerHex::to_lower_hex).collect(),
+            topics: self.topics.iter().map(ToLowerHex::to_lower_hex).collect(),
+            last_block: 0,
+        };
+
+        let horizon = storage.pruned_horizon().await?.unwrap_or_default();
+        let mut from_block = self.from_block.unwrap_or(horizon);
+        if self.incremental && state_path.exists() {
+            let previous: ExportState = serde_json::from_slice(&std::fs::read(&state_path)?)?;
+            if (ExportState { last_block: 0, ..previous.clone() }) != state {
+                return Err(eyre!(
+                    "{} was exported with a different format or filters, run without \
+                     `--incremental` or export to a new directory",
+                    self.output.display()
+                ))
+            }
+            // The partition of the last exported block may be incomplete, in which case it is
+            // exported again.
+            from_block = from_block
+                .max(partition_start(previous.last_block.saturating_add(1), self.partition_size));
+        }
+
+        let to_block = match self.to_block {
+            Some(to_block) => Some(to_block),
+            None => storage.checkpoint().await?.map(|checkpoint| checkpoint.block_number),
+        };
+        let Some(to_block) = to_block else {
+            println!("No blocks have been indexed, nothing to export");
+            return Ok(())
+        };
+        if from_block < horizon {
+            return Err(eyre!("logs before block {horizon} have been pruned"))
+        }
+
+        for (start, end) in partitions(from_block, to_block, self.partition_size) {
+            let filter = LogFilter {
+                block: BlockFilter::Range(start, end),
+                addresses: self.addresses.clone(),
+                topics: std::array::from_fn(|i| self.topics.get(i).copied().into_iter().collect()),
+            };
+            let logs = storage.get_logs(&filter).await?;
+
+            let partition = partition_start(start, self.partition_size);
+            let path = self.output.join(format!(
+                "shadow_logs_{partition:012}_{:012}.{}",
+                partition.saturating_add(self.partition_size - 1),
+                self.format.extension()
+            ));
+            write_atomically(&path, |file| match self.format {
+                ExportFormat::Parquet => write_parquet(file, &logs),
+                ExportFormat::Csv => write_csv(file, &logs),
+            })?;
+            println!(
+                "Exported {} logs in blocks {start}..={end} to {}",
+                logs.len(),
+                path.display()
+            );
+
+            state.last_block = end;
+            write_atomically(&state_path, |file| Ok(serde_json::to_writer_pretty(file, &state)?))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the first block of the partition containing `block_number`.
+const fn partition_start(block_number: u64, partition_size: u64) -> u64 {
+    block_number - block_number % partition_size
+}
+
+/// Splits `from_block..=to_block` at multiples of `partition_size`.
+fn partitions(from_block: u64, to_block: u64, partition_size: u64) -> Vec<(u64, u64)> {
+    let mut partitions = Vec::new();
+    let mut start = from_block;
+    while start <= to_block {
+        let end = partition_start(start, partition_size).saturating_add(partition_size - 1);
+        partitions.push((start, end.min(to_block)));
+        match end.checked_add(1) {
+            Some(next) => start = next,
+            None => break,
+        }
+    }
+    partitions
+}
+
+/// Writes a file through `write`, replacing `path` only once the file is complete.
+fn write_atomically(path: &Path, write: impl FnOnce(File) -> Result<()>) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    write(File::create(&tmp_path)?)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Writes `logs` to `file` as a Parquet file.
+fn write_parquet(file: File, logs: &[ShadowLog]) -> Result<()> {
+    let batch = record_batch(logs)?;
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), Some(properties))?;
+    writer.write(&batch)?;
+    let _ = writer.close()?;
+    Ok(())
+}
+
+/// Converts `logs` into an Arrow record batch with the [`COLUMNS`] of the export.
+fn record_batch(logs: &[ShadowLog]) -> Result<RecordBatch> {
+    let u64s = |f: fn(&ShadowLog) -> u64| -> ArrayRef {
+        Arc::new(UInt64Array::from_iter_values(logs.iter().map(f)))
+    };
+    let strings = |f: fn(&ShadowLog) -> Option<&str>| -> ArrayRef {
+        Arc::new(logs.iter().map(f).collect::<StringArray>())
+    };
+
+    let columns = vec![
+        u64s(|log| log.block_number),
+        strings(|log| Some(log.block_hash.as_str())),
+        u64s(|log| log.block_timestamp),
+        u64s(|log| log.transaction_index),
+        strings(|log| Some(log.transaction_hash.as_str())),
+        u64s(|log| log.block_log_index),
+        u64s(|log| log.transaction_log_index),
+        strings(|log| Some(log.address.as_str())),
+        strings(|log| log.data.as_deref()),
+        strings(|log| log.topic_0.as_deref()),
+        strings(|log| log.topic_1.as_deref()),
+        strings(|log| log.topic_2.as_deref()),
+        strings(|log| log.topic_3.as_deref()),
+        Arc::new(logs.iter().map(|log| Some(log.removed)).collect::<BooleanArray>()) as ArrayRef,
+    ];
+    // Only the data and topics of a log are optional.
+    let schema = Schema::new(
+        COLUMNS
+            .iter()
+            .zip(&columns)
+            .map(|(name, column)| {
+                let nullable = *name == "data" || name.starts_with("topic_");
+                Field::new(*name, column.data_type().clone(), nullable)
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    Ok(RecordBatch::try_new(Arc::new(schema), columns)?)
+}
+
+/// Writes `logs` to `file` as CSV, with a header row of [`COLUMNS`].
+fn write_csv(file: File, logs: &[ShadowLog]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(file);
+    writer.write_record(COLUMNS)?;
+    for log in logs {
+        writer.write_record([
+            log.block_number.to_string().as_str(),
+            log.block_hash.as_str(),
+            log.block_timestamp.to_string().as_str(),
+            log.transaction_index.to_string().as_str(),
+            log.transaction_hash.as_str(),
+            log.block_log_index.to_string().as_str(),
+            log.transaction_log_index.to_string().as_str(),
+            log.address.as_str(),
+            log.data.as_deref().unwrap_or_default(),
+            log.topic_0.as_deref().unwrap_or_default(),
+            log.topic_1.as_deref().unwrap_or_default(),
+            log.topic_2.as_deref().unwrap_or_default(),
+            log.topic_3.as_deref().unwrap_or_default(),
+            if log.removed { "true" } else { "false" },
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, path::Path};
+
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use reth_primitives::{Address, BlockHash, B256};
+    use shadow_reth_common::{Checkpoint, ShadowLog, ShadowSqliteDb, ShadowStorage, ToLowerHex};
+
+    use super::{partitions, ExportArgs, ExportFormat};
+    use crate::{StorageArgs, StorageBackend};
+
+    fn shadow_log(block_number: u64) -> ShadowLog {
+        ShadowLog {
+            address: Address::repeat_byte(1).to_lower_hex(),
+            block_hash: B256::left_padding_from(&block_number.to_be_bytes()).to_lower_hex(),
+            block_log_index: 0,
+            block_number,
+            block_timestamp: 1703595275,
+            transaction_index: 0,
+            transaction_hash: B256::repeat_byte(2).to_lower_hex(),
+            transaction_log_index: 0,
+            removed: false,
+            data: None,
+            topic_0: Some(B256::repeat_byte(3).to_lower_hex()),
+            topic_1: None,
+            topic_2: None,
+            topic_3: None,
+        }
+    }
+
+    fn export_args(output: &Path, format: ExportFormat) -> ExportArgs {
+        ExportArgs {
+            datadir: None,
+            storage: StorageArgs {
+                storage: StorageBackend::Sqlite,
+                postgres_url: None,
+                sqlite_read_connections: 1,
+                sqlite_busy_timeout_ms: 5000,
+            },
+            output: output.to_path_buf(),
+            format,
+            partition_size: 10,
+            addresses: vec![Address::repeat_byte(1)],
+            topics: vec![B256::repeat_byte(3)],
+            from_block: None,
+            to_block: None,
+            incremental: true,
+        }
+    }
+
+    /// Inserts one log for each of the given blocks, and advances the checkpoint to the last one.
+    async fn index_blocks(db: &ShadowSqliteDb, blocks: std::ops::RangeInclusive<u64>) {
+        let logs = blocks.clone().map(shadow_log).collect::<Vec<_>>();
+        let checkpoint = Checkpoint { block_number: *blocks.end(), block_hash: BlockHash::ZERO };
+        db.apply_chain_update(&[], &[], &logs, Some(checkpoint)).await.unwrap();
+    }
+
+    fn exported_files(dir: &Path) -> Vec<String> {
+        let mut files = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_partitions() {
+        assert_eq!(partitions(5, 25, 10), vec![(5, 9), (10, 19), (20, 25)]);
+        assert_eq!(partitions(10, 19, 10), vec![(10, 19)]);
+        assert_eq!(partitions(20, 19, 10), vec![]);
+        assert_eq!(partitions(u64::MAX - 1, u64::MAX, 10), vec![(u64::MAX - 1, u64::MAX)]);
+    }
+
+    #[tokio::test]
+    async fn test_incremental_csv_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let args = export_args(dir.path(), ExportFormat::Csv);
+
+        index_blocks(&db, 0..=14).await;
+        args.export(&db).await.unwrap();
+        assert_eq!(
+            exported_files(dir.path()),
+            [
+                "export.json",
+                "shadow_logs_000000000000_000000000009.csv",
+                "shadow_logs_000000000010_000000000019.csv",
+            ]
+        );
+
+        // The incomplete partition is exported again, including the new blocks.
+        index_blocks(&db, 15..=21).await;
+        args.export(&db).await.unwrap();
+        let partition = dir.path().join("shadow_logs_000000000010_000000000019.csv");
+        let rows = csv::Reader::from_path(partition)
+            .unwrap()
+            .records()
+            .map(|record| record.unwrap()[0].parse::<u64>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(rows, (10..=19).collect::<Vec<_>>());
+        assert_eq!(exported_files(dir.path()).len(), 4);
+
+        // Changing the filters of an incremental export is rejected.
+        let args = ExportArgs { topics: vec![], ..args };
+        assert!(args.export(&db).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parquet_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        index_blocks(&db, 0..=4).await;
+
+        export_args(dir.path(), ExportFormat::Parquet).export(&db).await.unwrap();
+
+        let file =
+            File::open(dir.path().join("shadow_logs_000000000000_000000000009.parquet")).unwrap();
+        let batches = ParquetRecordBatchReaderBuilder::try_new(file)
+            .unwrap()
+            .build()
+            .unwrap()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(batches.iter().map(|batch| batch.num_rows()).sum::<usize>(), 5);
+        assert_eq!(batches[0].schema().field(0).name(), "block_number");
+        assert_eq!(batches[0].num_columns(), 14);
+    }
+}
@@ -5,10 +5,228 @@
 
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 
-use eyre::Result;
+mod export;
+mod rpc;
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use eyre::{eyre, Result};
 use reth_node_ethereum::EthereumNode;
-use shadow_reth_exex::ShadowExEx;
-use shadow_reth_rpc::ShadowRpc;
+use reth_tracing::{RethTracer, Tracer};
+use shadow_reth_common::{PruneConfig, RetentionPolicy, SqliteConfig, StorageConfig};
+use shadow_reth_exex::{ShadowCaller, ShadowExEx, ShadowSinks};
+use shadow_reth_rpc::{
+    LogFormat, LogQueryLimits, OverlayListener, ShadowCall, ShadowOverlay, ShadowRpc,
+};
+
+use crate::{export::ExportArgs, rpc::RpcArgs};
+
+/// Storage backends for shadow logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum StorageBackend {
+    /// A SQLite database at `<datadir>/db/shadow.db`.
+    #[default]
+    Sqlite,
+    /// A PostgreSQL database at `--shadow.postgres-url`.
+    Postgres,
+    /// A separate MDBX environment at `<datadir>/db/shadow-mdbx`, not reth's own database.
+    Mdbx,
+}
+
+/// Command line arguments selecting and configuring the storage backend.
+#[derive(Debug, Clone, Args)]
+struct StorageArgs {
+    /// The backend in which shadow logs are stored.
+    #[arg(long = "shadow.storage", value_enum, default_value_t = StorageBackend::Sqlite)]
+    storage: StorageBackend,
+
+    /// Connection URL of the PostgreSQL database, used with `--shadow.storage postgres`.
+    #[arg(long = "shadow.postgres-url", env = "SHADOW_POSTGRES_URL")]
+    postgres_url: Option<String>,
+
+    /// Maximum number of read-only SQLite connections used by the RPC.
+    #[arg(long = "shadow.sqlite-read-connections", default_value_t = 8)]
+    sqlite_read_connections: u32,
+
+    /// How long SQLite connections wait for a locked database, in milliseconds.
+    #[arg(long = "shadow.sqlite-busy-timeout", default_value_t = 5000)]
+    sqlite_busy_timeout_ms: u64,
+}
+
+impl StorageArgs {
+    /// Returns the storage configuration. The SQLite and MDBX backends are stored in `db_dir`,
+    /// which is required for them.
+    fn storage_config(&self, db_dir: Option<&Path>) -> Result<StorageConfig> {
+        let require_db_dir =
+            || db_dir.ok_or_else(|| eyre!("`--datadir` is required for {:?}", self.storage));
+        Ok(match self.storage {
+            StorageBackend::Sqlite => StorageConfig::Sqlite(
+                require_db_dir()?.join("shadow.db"),
+                SqliteConfig {
+                    read_connections: self.sqlite_read_connections,
+                    busy_timeout: Duration::from_millis(self.sqlite_busy_timeout_ms),
+                },
+            ),
+            StorageBackend::Postgres => StorageConfig::Postgres(
+                self.postgres_url
+                    .clone()
+                    .ok_or_else(|| eyre!("`--shadow.postgres-url` is required for postgres"))?,
+            ),
+            StorageBackend::Mdbx => StorageConfig::Mdbx(require_db_dir()?.join("shadow-mdbx")),
+        })
+    }
+}
+
+/// Command line arguments for shadow-reth, in addition to reth's own arguments.
+#[derive(Debug, Clone, Args)]
+struct ShadowArgs {
+    #[command(flatten)]
+    storage: StorageArgs,
+
+    /// Only keep the shadow logs of this many most recent blocks.
+    #[arg(long = "shadow.retention-blocks", conflicts_with = "retention_from_block")]
+    retention_blocks: Option<u64>,
+
+    /// Only keep the shadow logs of blocks from this block number onward.
+    #[arg(long = "shadow.retention-from-block")]
+    retention_from_block: Option<u64>,
+
+    /// Time between two pruning runs of the shadow database, in seconds.
+    #[arg(long = "shadow.prune-interval", default_value_t = 60)]
+    prune_interval_secs: u64,
+
+    /// Path to a JSON file configuring sinks which receive shadow logs, e.g. webhooks.
+    #[arg(long = "shadow.sinks", value_name = "PATH")]
+    sinks: Option<PathBuf>,
+
+    #[command(flatten)]
+    log_format: LogFormatArgs,
+
+    #[command(flatten)]
+    limits: LogLimitsArgs,
+
+    #[command(flatten)]
+    overlay: OverlayArgs,
+}
+
+/// Limits on the logs selected by a single RPC request.
+#[derive(Debug, Clone, Copy, Args)]
+struct LogLimitsArgs {
+    /// Maximum number of blocks in the block range of a `shadow_getLogs` request.
+    #[arg(
+        long = "shadow.rpc.max-block-range",
+        default_value_t = LogQueryLimits::default().max_block_range
+    )]
+    max_block_range: u64,
+
+    /// Maximum number of logs returned by a `shadow_getLogs` request, and the maximum page size
+    /// of `shadow_getLogsPage`.
+    #[arg(long = "shadow.rpc.max-logs", default_value_t = LogQueryLimits::default().max_results)]
+    max_logs: usize,
+}
+
+impl LogLimitsArgs {
+    /// Returns the configured limits.
+    const fn limits(self) -> LogQueryLimits {
+        LogQueryLimits { max_block_range: self.max_block_range, max_results: self.max_logs }
+    }
+}
+
+/// Configures the `eth` namespace overlay, which serves canonical logs with the logs of shadowed
+/// contracts replaced by their shadow logs.
+#[derive(Debug, Clone, Args)]
+struct OverlayArgs {
+    /// Serve the `eth` namespace overlay on this port. The overlay answers `eth_getLogs` and
+    /// `eth_subscribe` with shadow logs, and forwards all other methods to the node.
+    #[arg(long = "shadow.overlay.port", value_name = "PORT")]
+    port: Option<u16>,
+
+    /// Address on which the overlay listens.
+    #[arg(long = "shadow.overlay.addr", default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    addr: IpAddr,
+
+    /// HTTP RPC of the node, to which methods not served by the overlay are forwarded.
+    #[arg(long = "shadow.overlay.upstream", default_value = "http://127.0.0.1:8545")]
+    upstream: String,
+}
+
+impl OverlayArgs {
+    /// Returns the address on which the overlay is served, or `None` if it is disabled.
+    fn socket_addr(&self) -> Option<SocketAddr> {
+        self.port.map(|port| SocketAddr::new(self.addr, port))
+    }
+}
+
+/// Selects the encoding of logs returned by the shadow namespace.
+#[derive(Debug, Clone, Copy, Args)]
+struct LogFormatArgs {
+    /// Return logs in the encoding of earlier releases, with an unprefixed block number, decimal
+    /// indices and four topics padded with `null`, instead of the standard Ethereum log object.
+    #[arg(long = "shadow.legacy-log-format")]
+    legacy_log_format: bool,
+}
+
+impl LogFormatArgs {
+    /// Returns the selected log format.
+    const fn log_format(self) -> LogFormat {
+        if self.legacy_log_format {
+            LogFormat::Legacy
+        } else {
+            LogFormat::Standard
+        }
+    }
+}
+
+impl ShadowArgs {
+    /// Returns the pruning configuration, which keeps all logs unless a retention limit is set.
+    fn prune_config(&self) -> PruneConfig {
+        let retention = match (self.retention_blocks, self.retention_from_block) {
+            (Some(blocks), _) => RetentionPolicy::LastBlocks(blocks),
+            (None, Some(block_number)) => RetentionPolicy::FromBlock(block_number),
+            (None, None) => RetentionPolicy::Full,
+        };
+        PruneConfig { retention, interval: Duration::from_secs(self.prune_interval_secs) }
+    }
+
+    /// Returns the configured sinks, whose cursors are stored in `db_dir`.
+    async fn sinks(&self, db_dir: &Path) -> Result<ShadowSinks> {
+        let state_dir = db_dir.join("shadow-sinks");
+        match &self.sinks {
+            Some(path) => ShadowSinks::from_file(path, state_dir).await,
+            None => Ok(ShadowSinks::new(state_dir)),
+        }
+    }
+}
+
+/// Commands of shadow-reth which are not part of reth, e.g. `shadow-reth shadow export`.
+#[derive(Debug, Parser)]
+#[command(name = "shadow-reth")]
+struct ShadowCli {
+    #[command(subcommand)]
+    command: ShadowCliCommand,
+}
+
+/// Top-level commands of [`ShadowCli`].
+#[derive(Debug, Subcommand)]
+enum ShadowCliCommand {
+    /// Commands operating on the shadow database.
+    #[command(subcommand)]
+    Shadow(ShadowCommand),
+}
+
+/// Subcommands of `shadow-reth shadow`.
+#[derive(Debug, Subcommand)]
+enum ShadowCommand {
+    /// Export shadow logs to Parquet or CSV files.
+    Export(ExportArgs),
+    /// Serve the shadow RPC namespace from an existing shadow database, without a reth node.
+    Rpc(RpcArgs),
+}
 
 fn main() -> Result<()> {
     // Enable backtraces unless a RUST_BACKTRACE value has already been explicitly provided.
@@ -16,17 +234,59 @@ fn main() -> Result<()> {
         std::env::set_var("RUST_BACKTRACE", "1");
     }
 
-    reth::cli::Cli::parse_args().run(|builder, _| async move {
-        let shadow_db_path = builder.data_dir().db().join("shadow.db");
-        let (indexed_block_hash_sender, indexed_block_hash_receiver) =
-            tokio::sync::broadcast::channel(4096);
+    // reth's CLI cannot be extended with subcommands, so shadow-reth's own commands are parsed
+    // separately.
+    if std::env::args().nth(1).as_deref() == Some("shadow") {
+        let ShadowCliCommand::Shadow(command) = ShadowCli::parse().command;
+        // reth only initialises tracing for its own commands.
+        let _guard = RethTracer::new().init()?;
+        let runtime = tokio::runtime::Runtime::new()?;
+        return match command {
+            ShadowCommand::Export(args) => runtime.block_on(args.run()),
+            ShadowCommand::Rpc(args) => runtime.block_on(args.run()),
+        }
+    }
+
+    reth::cli::Cli::<ShadowArgs>::parse_args().run(|builder, args| async move {
+        let db_dir = builder.data_dir().db();
+        let storage = args.storage.storage_config(Some(&db_dir))?.connect().await?;
+        let exex_storage = storage.clone();
+        let prune_config = args.prune_config();
+        let log_format = args.log_format.log_format();
+        let limits = args.limits.limits();
+        let sinks = args.sinks(&db_dir).await?;
+        // Bind the overlay before the node is launched, so that the node does not run without it.
+        let overlay = match args.overlay.socket_addr() {
+            Some(addr) => Some((
+                OverlayListener::bind(addr, args.overlay.upstream).await?,
+                ShadowExEx::shadowed_addresses()?,
+            )),
+            None => None,
+        };
+        let caller = ShadowCaller::from_config()?;
+        let (indexed_block_sender, indexed_block_receiver) = tokio::sync::broadcast::channel(4096);
 
         // Start reth w/ the shadow exex.
         let handle = builder
             .node(EthereumNode::default())
-            .install_exex("ShadowExEx", move |ctx| ShadowExEx::init(ctx, indexed_block_hash_sender))
+            .install_exex("ShadowExEx", move |ctx| {
+                ShadowExEx::init(ctx, exex_storage, indexed_block_sender, prune_config, sinks)
+            })
             .extend_rpc_modules(move |ctx| {
-                ShadowRpc::init(ctx, shadow_db_path, indexed_block_hash_receiver)
+                if let Some((listener, shadowed)) = overlay {
+                    let rpc = ShadowRpc::new(
+                        ctx.provider().clone(),
+                        storage.clone(),
+                        indexed_block_receiver.resubscribe(),
+                    )
+                    .with_limits(limits);
+                    let handle = listener.start(ShadowOverlay::new(rpc, shadowed));
+                    tokio::spawn(handle.stopped());
+                }
+                ctx.modules
+                    .merge_configured(ShadowCall::new(ctx.provider().clone(), caller).into_rpc())
+                    .map_err(|e| eyre!("failed to extend w/ ShadowCall: {e}"))?;
+                ShadowRpc::init(ctx, storage, indexed_block_receiver, log_format, limits)
             })
             .launch()
             .await?;
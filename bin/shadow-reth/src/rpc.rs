@@ -0,0 +1,61 @@
+//! The `shadow-reth shadow rpc` command, which serves the shadow namespace from an existing shadow
+//! database without running a reth node, e.g. from a copy of `shadow.db` on an analytics machine
+//! or in CI.
+//!
+//! Block tags and hashes are resolved from the blocks indexed by the ExEx. `safe` and `finalized`
+//! are not available, and `shadow_subscribe` subscriptions end immediately.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+};
+
+use clap::Args;
+use eyre::Result;
+use jsonrpsee::server::Server;
+use reth_tracing::tracing::info;
+use shadow_reth_rpc::{ShadowRpc, ShadowRpcApiServer};
+
+use crate::{LogFormatArgs, LogLimitsArgs, StorageArgs};
+
+/// Arguments of `shadow-reth shadow rpc`.
+#[derive(Debug, Args)]
+pub(crate) struct RpcArgs {
+    /// Data directory of the reth node. The shadow database is read from its `db` directory.
+    #[arg(long, value_name = "DATA_DIR")]
+    datadir: Option<PathBuf>,
+
+    #[command(flatten)]
+    storage: StorageArgs,
+
+    #[command(flatten)]
+    log_format: LogFormatArgs,
+
+    #[command(flatten)]
+    limits: LogLimitsArgs,
+
+    /// Address on which the JSON-RPC server listens for HTTP and WebSocket connections.
+    #[arg(long = "http.addr", default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    http_addr: IpAddr,
+
+    /// Port on which the JSON-RPC server listens for HTTP and WebSocket connections.
+    #[arg(long = "http.port", default_value_t = 8545)]
+    http_port: u16,
+}
+
+impl RpcArgs {
+    /// Serves the shadow namespace until the process is stopped.
+    pub(crate) async fn run(self) -> Result<()> {
+        let db_dir = self.datadir.as_ref().map(|datadir| datadir.join("db"));
+        let storage = self.storage.storage_config(db_dir.as_deref())?.connect().await?;
+
+        let server =
+            Server::builder().build(SocketAddr::new(self.http_addr, self.http_port)).await?;
+        info!(addr = %server.local_addr()?, "Serving the shadow namespace");
+        let rpc = ShadowRpc::standalone(storage)
+            .with_log_format(self.log_format.log_format())
+            .with_limits(self.limits.limits());
+        server.start(rpc.into_rpc()).stopped().await;
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+//! Throughput benchmarks for writing and reading shadow logs, comparing the SQLite and MDBX
+//! backends.
+
+#![allow(missing_docs)]
+
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use reth_primitives::{Address, B256};
+use shadow_reth_common::{
+    BlockFilter, LogFilter, ShadowLog, ShadowStorage, SqliteConfig, StorageConfig, ToLowerHex,
+};
+
+/// Number of logs per block in the generated fixtures.
+const LOGS_PER_BLOCK: u64 = 100;
+
+/// Backends compared by the benchmarks.
+const BACKENDS: [&str; 2] = ["sqlite", "mdbx"];
+
+/// Opens a fresh database of the given backend in `dir`.
+async fn open(backend: &str, dir: &Path) -> Arc<dyn ShadowStorage> {
+    let config = match backend {
+        "sqlite" => StorageConfig::Sqlite(dir.join("shadow.db"), SqliteConfig::default()),
+        "mdbx" => StorageConfig::Mdbx(dir.join("shadow-mdbx")),
+        _ => unreachable!(),
+    };
+    config.connect().await.unwrap()
+}
+
+/// Generates `count` shadow logs, spread over blocks of [`LOGS_PER_BLOCK`] logs each.
+fn shadow_logs(count: u64) -> Vec<ShadowLog> {
+    (0..count)
+        .map(|i| {
+            let block_number = 18870000 + i / LOGS_PER_BLOCK;
+            ShadowLog {
+                address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+                block_hash: B256::with_last_byte((block_number % 256) as u8).to_lower_hex(),
+                block_log_index: i % LOGS_PER_BLOCK,
+                block_number,
+                block_timestamp: 1703595275 + block_number * 12,
+                transaction_index: i % LOGS_PER_BLOCK,
+                transaction_hash: B256::left_padding_from(&i.to_be_bytes()).to_lower_hex(),
+                transaction_log_index: 0,
+                removed: false,
+                data: Some(
+                    "0x0000000000000000000000000000000000000000000000001bc16d674ec80000"
+                        .to_string(),
+                ),
+                topic_0: Some(
+                    "0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c"
+                        .to_string(),
+                ),
+                topic_1: Some(
+                    "0x0000000000000000000000003fc91a3afd70395cd496c647d5a6cc9d4b2b7fad"
+                        .to_string(),
+                ),
+                topic_2: None,
+                topic_3: None,
+            }
+        })
+        .collect()
+}
+
+fn bench_bulk_insert(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("bulk_insert_into_shadow_log_table");
+    group.sample_size(10);
+
+    for backend in BACKENDS {
+        for count in [1_000, 10_000, 100_000] {
+            let logs = shadow_logs(count);
+            group.throughput(Throughput::Elements(count));
+            group.bench_with_input(BenchmarkId::new(backend, count), &logs, |b, logs| {
+                // Every iteration writes into a fresh database, only the insert itself is timed.
+                b.to_async(&runtime).iter_custom(|iters| async move {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let dir = tempfile::tempdir().unwrap();
+                        let db = open(backend, dir.path()).await;
+
+                        let start = Instant::now();
+                        db.bulk_insert_into_shadow_log_table(logs).await.unwrap();
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn bench_get_logs(c: &mut Criterion) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("get_logs");
+    let logs = shadow_logs(100_000);
+    let first_block = logs[0].block_number;
+
+    let filters = [
+        (
+            "block_range",
+            LogFilter {
+                block: BlockFilter::Range(first_block + 100, first_block + 199),
+                addresses: vec![],
+                topics: Default::default(),
+            },
+        ),
+        (
+            "address",
+            LogFilter {
+                block: BlockFilter::Range(first_block + 100, first_block + 199),
+                addresses: vec![logs[0].address.parse::<Address>().unwrap()],
+                topics: Default::default(),
+            },
+        ),
+        (
+            "topic",
+            LogFilter {
+                block: BlockFilter::Range(first_block + 100, first_block + 199),
+                addresses: vec![],
+                topics: [
+                    logs[0].topic_0.iter().map(|t| t.parse::<B256>().unwrap()).collect(),
+                    vec![],
+                    vec![],
+                    vec![],
+                ],
+            },
+        ),
+    ];
+
+    for backend in BACKENDS {
+        let dir = tempfile::tempdir().unwrap();
+        let db = runtime.block_on(async {
+            let db = open(backend, dir.path()).await;
+            db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+            db
+        });
+
+        for (name, filter) in &filters {
+            group.bench_with_input(BenchmarkId::new(backend, name), filter, |b, filter| {
+                b.to_async(&runtime).iter(|| async { db.get_logs(filter).await.unwrap() })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_bulk_insert, bench_get_logs);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+-- Initial schema. Numeric columns were stored as text, since SQLite integers are signed.
+CREATE TABLE IF NOT EXISTS shadow_logs(
+    block_number      	text  	not null,
+    block_hash        	varchar(66) not null,
+    block_timestamp   	text  	not null,
+    transaction_index 	text  	not null,
+    transaction_hash  	varchar(66) not null,
+    block_log_index   	text  	not null,
+    transaction_log_index text  	not null,
+    address           	varchar(42) not null,
+    removed           	boolean     not null,
+    data              	text,
+    topic_0           	varchar(66),
+    topic_1           	varchar(66),
+    topic_2           	varchar(66),
+    topic_3           	varchar(66),
+    created_at        	datetime,
+    updated_at        	datetime
+);
+
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_address ON shadow_logs (address);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_block_number ON shadow_logs (block_number);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_block_hash ON shadow_logs (block_hash);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_0 ON shadow_logs (topic_0);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_1 ON shadow_logs (topic_1);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_2 ON shadow_logs (topic_2);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_3 ON shadow_logs (topic_3);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_transaction_hash ON shadow_logs (transaction_hash);
+CREATE INDEX IF NOT EXISTS idx_shadow_logs_removed ON shadow_logs (removed);
@@ -0,0 +1,8 @@
+-- Key logs by (block_hash, block_log_index). Of each set of duplicates, the most recently
+-- inserted row is kept.
+DELETE FROM shadow_logs WHERE rowid NOT IN (
+    SELECT MAX(rowid) FROM shadow_logs GROUP BY block_hash, block_log_index
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_shadow_logs_block_hash_block_log_index
+    ON shadow_logs (block_hash, block_log_index);
@@ -0,0 +1,57 @@
+-- Store block numbers, timestamps and indices as integers. Values were previously written as
+-- unquoted numeric literals, so a plain CAST is lossless up to i64::MAX. SQLite stored larger
+-- literals as approximate REAL values, which cannot be recovered and are clamped to i64::MAX.
+-- Block numbers, timestamps and indices do not reach i64::MAX on any real chain.
+ALTER TABLE shadow_logs RENAME TO shadow_logs_text;
+
+CREATE TABLE shadow_logs(
+    block_number      	integer 	not null,
+    block_hash        	varchar(66) not null,
+    block_timestamp   	integer 	not null,
+    transaction_index 	integer 	not null,
+    transaction_hash  	varchar(66) not null,
+    block_log_index   	integer 	not null,
+    transaction_log_index integer 	not null,
+    address           	varchar(42) not null,
+    removed           	boolean     not null,
+    data              	text,
+    topic_0           	varchar(66),
+    topic_1           	varchar(66),
+    topic_2           	varchar(66),
+    topic_3           	varchar(66),
+    created_at        	datetime,
+    updated_at        	datetime,
+    PRIMARY KEY (block_hash, block_log_index)
+);
+
+INSERT INTO shadow_logs SELECT
+    CAST(block_number AS INTEGER),
+    block_hash,
+    CAST(block_timestamp AS INTEGER),
+    CAST(transaction_index AS INTEGER),
+    transaction_hash,
+    CAST(block_log_index AS INTEGER),
+    CAST(transaction_log_index AS INTEGER),
+    address,
+    removed,
+    data,
+    topic_0,
+    topic_1,
+    topic_2,
+    topic_3,
+    created_at,
+    updated_at
+FROM shadow_logs_text;
+
+-- Dropping the old table also drops its indices, which are recreated on the new table below.
+DROP TABLE shadow_logs_text;
+
+CREATE INDEX idx_shadow_logs_address ON shadow_logs (address);
+CREATE INDEX idx_shadow_logs_block_number ON shadow_logs (block_number);
+CREATE INDEX idx_shadow_logs_block_hash ON shadow_logs (block_hash);
+CREATE INDEX idx_shadow_logs_topic_0 ON shadow_logs (topic_0);
+CREATE INDEX idx_shadow_logs_topic_1 ON shadow_logs (topic_1);
+CREATE INDEX idx_shadow_logs_topic_2 ON shadow_logs (topic_2);
+CREATE INDEX idx_shadow_logs_topic_3 ON shadow_logs (topic_3);
+CREATE INDEX idx_shadow_logs_transaction_hash ON shadow_logs (transaction_hash);
+CREATE INDEX idx_shadow_logs_removed ON shadow_logs (removed);
@@ -0,0 +1,7 @@
+-- Track the last block processed by the ExEx. The table holds at most one row.
+CREATE TABLE shadow_checkpoint(
+    id                	integer 	primary key check (id = 0),
+    block_number      	integer 	not null,
+    block_hash        	blob    	not null,
+    updated_at        	datetime 	not null
+);
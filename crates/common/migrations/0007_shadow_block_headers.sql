@@ -0,0 +1,14 @@
+-- Record the header of every indexed block and the configuration it was executed with. Blocks
+-- indexed before this migration have no header metadata.
+ALTER TABLE shadow_blocks ADD COLUMN parent_hash blob;
+ALTER TABLE shadow_blocks ADD COLUMN block_timestamp integer;
+ALTER TABLE shadow_blocks ADD COLUMN base_fee integer;
+ALTER TABLE shadow_blocks ADD COLUMN processed_at integer;
+ALTER TABLE shadow_blocks ADD COLUMN config_version blob;
+
+-- Blocks with shadow logs which were indexed before blocks were tracked are known to have been
+-- processed.
+INSERT OR IGNORE INTO shadow_blocks (block_hash, block_number, block_timestamp, removed)
+SELECT block_hash, MIN(block_number), MIN(block_timestamp), MIN(removed)
+FROM shadow_logs
+GROUP BY block_hash;
@@ -0,0 +1,38 @@
+-- Initial PostgreSQL schema, equivalent to version 4 of the SQLite schema.
+CREATE TABLE shadow_logs(
+    block_number      	bigint  	not null,
+    block_hash        	bytea   	not null,
+    block_timestamp   	bigint  	not null,
+    transaction_index 	bigint  	not null,
+    transaction_hash  	bytea   	not null,
+    block_log_index   	bigint  	not null,
+    transaction_log_index bigint  	not null,
+    address           	bytea   	not null,
+    removed           	boolean 	not null,
+    data              	bytea,
+    topic_0           	bytea,
+    topic_1           	bytea,
+    topic_2           	bytea,
+    topic_3           	bytea,
+    created_at        	timestamptz,
+    updated_at        	timestamptz,
+    PRIMARY KEY (block_hash, block_log_index)
+);
+
+CREATE INDEX idx_shadow_logs_address ON shadow_logs (address);
+CREATE INDEX idx_shadow_logs_block_number ON shadow_logs (block_number);
+CREATE INDEX idx_shadow_logs_block_hash ON shadow_logs (block_hash);
+CREATE INDEX idx_shadow_logs_topic_0 ON shadow_logs (topic_0);
+CREATE INDEX idx_shadow_logs_topic_1 ON shadow_logs (topic_1);
+CREATE INDEX idx_shadow_logs_topic_2 ON shadow_logs (topic_2);
+CREATE INDEX idx_shadow_logs_topic_3 ON shadow_logs (topic_3);
+CREATE INDEX idx_shadow_logs_transaction_hash ON shadow_logs (transaction_hash);
+CREATE INDEX idx_shadow_logs_removed ON shadow_logs (removed);
+
+-- Track the last block processed by the ExEx. The table holds at most one row.
+CREATE TABLE shadow_checkpoint(
+    id                	integer 	primary key check (id = 0),
+    block_number      	bigint  	not null,
+    block_hash        	bytea   	not null,
+    updated_at        	timestamptz not null
+);
@@ -0,0 +1,6 @@
+-- Track the first block whose logs have not been pruned. The table holds at most one row.
+CREATE TABLE shadow_prune_horizon(
+    id                	integer 	primary key check (id = 0),
+    block_number      	bigint  	not null,
+    updated_at        	timestamptz not null
+);
@@ -0,0 +1,9 @@
+-- Track every block processed by the ExEx, including blocks without shadow logs, so that block
+-- hashes can be resolved from the shadow database alone.
+CREATE TABLE shadow_blocks(
+    block_hash        	bytea   	primary key,
+    block_number      	bigint  	not null,
+    removed           	boolean 	not null
+);
+
+CREATE INDEX idx_shadow_blocks_block_number ON shadow_blocks (block_number);
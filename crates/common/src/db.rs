@@ -1,45 +1,412 @@
-use std::str::FromStr;
+use std::{str::FromStr, time::Duration};
 
-use eyre::Result;
-use reth_primitives::BlockHash;
+use async_trait::async_trait;
+use reth_primitives::{hex, BlockHash};
 use reth_tracing::tracing::debug;
 use sqlx::{
-    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
-    Pool, Sqlite,
+    database::HasArguments,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions, SqliteSynchronous},
+    Database, Encode, Pool, QueryBuilder, Sqlite, Type,
 };
 
-use crate::ShadowLog;
+use crate::{
+    migrate,
+    query::{
+        count_indexed_blocks_statement, delete_statement, select_logs_statement, RawBlockRow,
+        RawGetLogsRow, BLOCK_COLUMNS,
+    },
+    Checkpoint, IndexedBlock, LogFilter, LogPage, PruneOutcome, ShadowDbError, ShadowLog,
+    ShadowStorage,
+};
+
+/// Connection settings of a [`ShadowSqliteDb`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SqliteConfig {
+    /// Maximum number of connections in the read-only pool.
+    pub read_connections: u32,
+    /// How long a connection waits for a lock held by another connection before failing with
+    /// [`ShadowDbError::Busy`].
+    pub busy_timeout: Duration,
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        Self { read_connections: 8, busy_timeout: Duration::from_secs(5) }
+    }
+}
 
-/// Wrapper type around a SQLite connection pool.
+/// Wrapper type around the SQLite connection pools of the shadow database.
+///
+/// The database is opened in WAL mode, so that reads are not blocked by the ExEx writing a chain
+/// update. All writes go through a single writer connection, reads use a separate pool of
+/// read-only connections.
 #[derive(Clone, Debug)]
 pub struct ShadowSqliteDb {
-    /// Connection pool.
-    pub pool: Pool<Sqlite>,
+    /// Pool holding the single connection used for writes.
+    pub writer: Pool<Sqlite>,
+    /// Pool of read-only connections.
+    ///
+    /// For in-memory databases, this is the writer pool, as every read-only connection would
+    /// otherwise open a separate database.
+    pub reader: Pool<Sqlite>,
 }
 
 impl ShadowSqliteDb {
-    /// Creates a new instance.
-    pub async fn new(db_path: &str) -> Result<Self, sqlx::Error> {
-        let pool = SqlitePoolOptions::new()
-            .connect_with(SqliteConnectOptions::from_str(db_path)?.create_if_missing(true))
+    /// Creates a new instance with the default [`SqliteConfig`], applying any pending schema
+    /// migrations.
+    ///
+    /// Fails with [`ShadowDbError::UnsupportedSchemaVersion`] if the database was created by a
+    /// newer version of shadow-reth.
+    pub async fn new(db_path: &str) -> Result<Self, ShadowDbError> {
+        Self::with_config(db_path, SqliteConfig::default()).await
+    }
+
+    /// Creates a new instance with the given [`SqliteConfig`], applying any pending schema
+    /// migrations.
+    pub async fn with_config(db_path: &str, config: SqliteConfig) -> Result<Self, ShadowDbError> {
+        let options = SqliteConnectOptions::from_str(db_path)?.busy_timeout(config.busy_timeout);
+
+        // The writer connection is kept open for the lifetime of the pool, so that an in-memory
+        // database is not dropped when the connection is recycled.
+        let writer = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(
+                options
+                    .clone()
+                    .create_if_missing(true)
+                    .journal_mode(SqliteJournalMode::Wal)
+                    .synchronous(SqliteSynchronous::Normal),
+            )
             .await?;
-        create_tables(&pool).await?;
-        create_indices(&pool).await?;
+        migrate(&writer).await?;
+
+        let reader = if db_path.contains(":memory:") || db_path.contains("mode=memory") {
+            writer.clone()
+        } else {
+            SqlitePoolOptions::new()
+                .max_connections(config.read_connections)
+                .connect_with(options.read_only(true))
+                .await?
+        };
 
-        Ok(Self { pool })
+        Ok(Self { writer, reader })
     }
+}
 
-    #[allow(clippy::format_in_format_args)]
-    /// Bulk insert a list of [`ShadowLog`] instances into the `shadow_log` table.
-    ///
-    /// Note: using format here over bind because input is trusted, and bind was causing
-    /// borrow checker headaches.
-    pub async fn bulk_insert_into_shadow_log_table(
+#[async_trait]
+impl ShadowStorage for ShadowSqliteDb {
+    async fn apply_chain_update(
         &self,
-        logs: Vec<ShadowLog>,
-    ) -> Result<(), sqlx::Error> {
+        reverted: &[BlockHash],
+        committed: &[IndexedBlock],
+        logs: &[ShadowLog],
+        checkpoint: Option<Checkpoint>,
+    ) -> Result<(), ShadowDbError> {
         let start_time = std::time::Instant::now();
-        let mut query = "INSERT INTO shadow_logs (
+        let mut tx = self.writer.begin().await?;
+
+        for chunk in reverted.chunks(SQLITE_MAX_VARIABLE_NUMBER) {
+            for table in ["shadow_logs", "shadow_blocks"] {
+                let _ = block_hashes_statement::<Sqlite>(
+                    &format!("UPDATE {table} SET removed = true WHERE block_hash IN ("),
+                    chunk,
+                )
+                .build()
+                .execute(&mut *tx)
+                .await?;
+            }
+        }
+
+        let committed_hashes = committed.iter().map(|block| block.block_hash).collect::<Vec<_>>();
+        for chunk in committed_hashes.chunks(SQLITE_MAX_VARIABLE_NUMBER) {
+            let _ = block_hashes_statement::<Sqlite>(
+                "DELETE FROM shadow_logs WHERE block_hash IN (",
+                chunk,
+            )
+            .build()
+            .execute(&mut *tx)
+            .await?;
+        }
+        for chunk in committed.chunks(SQLITE_MAX_VARIABLE_NUMBER / BIND_PARAMETERS_PER_BLOCK) {
+            let _ = insert_blocks_statement::<Sqlite>(chunk).build().execute(&mut *tx).await?;
+        }
+
+        let rows = logs.iter().map(LogRow::try_from).collect::<Result<Vec<_>, _>>()?;
+        for chunk in rows.chunks(MAX_LOGS_PER_STATEMENT) {
+            let _ = insert_statement::<Sqlite>(chunk, "date()").build().execute(&mut *tx).await?;
+        }
+
+        if let Some(checkpoint) = checkpoint {
+            let _ = sqlx::query(
+                "INSERT INTO shadow_checkpoint (id, block_number, block_hash, updated_at)
+                VALUES (0, ?, ?, datetime())
+                ON CONFLICT (id) DO UPDATE SET
+                    block_number = excluded.block_number,
+                    block_hash = excluded.block_hash,
+                    updated_at = excluded.updated_at",
+            )
+            .bind(encode_u64(checkpoint.block_number))
+            .bind(checkpoint.block_hash.as_slice())
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        debug!(
+            reverted = reverted.len(),
+            committed = committed.len(),
+            logs = logs.len(),
+            "Applied chain update in {:?}",
+            start_time.elapsed()
+        );
+        Ok(())
+    }
+
+    async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<ShadowLog>, ShadowDbError> {
+        let rows: Vec<RawGetLogsRow> = select_logs_statement::<Sqlite>(filter, None)
+            .build_query_as()
+            .fetch_all(&self.reader)
+            .await?;
+        Ok(rows.into_iter().map(ShadowLog::from).collect())
+    }
+
+    async fn get_logs_page(
+        &self,
+        filter: &LogFilter,
+        page: &LogPage,
+    ) -> Result<Vec<ShadowLog>, ShadowDbError> {
+        let rows: Vec<RawGetLogsRow> = select_logs_statement::<Sqlite>(filter, Some(page))
+            .build_query_as()
+            .fetch_all(&self.reader)
+            .await?;
+        Ok(rows.into_iter().map(ShadowLog::from).collect())
+    }
+
+    async fn checkpoint(&self) -> Result<Option<Checkpoint>, ShadowDbError> {
+        let row: Option<(i64, Vec<u8>)> =
+            sqlx::query_as("SELECT block_number, block_hash FROM shadow_checkpoint")
+                .fetch_optional(&self.reader)
+                .await?;
+        Ok(row.map(|(block_number, block_hash)| Checkpoint {
+            block_number: decode_u64(block_number),
+            block_hash: BlockHash::from_slice(&block_hash),
+        }))
+    }
+
+    async fn indexed_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<IndexedBlock>, ShadowDbError> {
+        let row: Option<RawBlockRow> = sqlx::query_as(&format!(
+            "SELECT {BLOCK_COLUMNS} FROM shadow_blocks WHERE block_hash = ?"
+        ))
+        .bind(block_hash.as_slice())
+        .fetch_optional(&self.reader)
+        .await?;
+        Ok(row.map(IndexedBlock::from))
+    }
+
+    async fn indexed_block_count(&self, from: u64, to: u64) -> Result<u64, ShadowDbError> {
+        let count: i64 = count_indexed_blocks_statement::<Sqlite>(from, to)
+            .build_query_scalar()
+            .fetch_one(&self.reader)
+            .await?;
+        Ok(count as u64)
+    }
+
+    async fn prune(
+        &self,
+        horizon: Option<u64>,
+        finalized: Option<u64>,
+    ) -> Result<PruneOutcome, ShadowDbError> {
+        let mut outcome = PruneOutcome::default();
+        let mut tx = self.writer.begin().await?;
+
+        let current: Option<i64> =
+            sqlx::query_scalar("SELECT block_number FROM shadow_prune_horizon")
+                .fetch_optional(&mut *tx)
+                .await?;
+        if let Some(horizon) = horizon.filter(|horizon| {
+            *horizon > 0 && current.map_or(true, |current| *horizon > decode_u64(current))
+        }) {
+            outcome.logs = delete_statement::<Sqlite>("shadow_logs", horizon - 1, false)
+                .build()
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            let _ = delete_statement::<Sqlite>("shadow_blocks", horizon - 1, false)
+                .build()
+                .execute(&mut *tx)
+                .await?;
+            let _ = sqlx::query(
+                "INSERT INTO shadow_prune_horizon (id, block_number, updated_at)
+                VALUES (0, ?, datetime())
+                ON CONFLICT (id) DO UPDATE SET
+                    block_number = excluded.block_number,
+                    updated_at = excluded.updated_at",
+            )
+            .bind(encode_u64(horizon))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        if let Some(finalized) = finalized {
+            outcome.removed_logs = delete_statement::<Sqlite>("shadow_logs", finalized, true)
+                .build()
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+            let _ = delete_statement::<Sqlite>("shadow_blocks", finalized, true)
+                .build()
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn pruned_horizon(&self) -> Result<Option<u64>, ShadowDbError> {
+        let horizon: Option<i64> =
+            sqlx::query_scalar("SELECT block_number FROM shadow_prune_horizon")
+                .fetch_optional(&self.reader)
+                .await?;
+        Ok(horizon.map(decode_u64))
+    }
+}
+
+/// Maximum number of bound parameters in a single statement. This is the default limit since
+/// SQLite 3.32.0; the SQLite version bundled with sqlx is newer.
+const SQLITE_MAX_VARIABLE_NUMBER: usize = 32766;
+
+/// Number of parameters bound for each log in [`insert_statement`].
+pub(crate) const BIND_PARAMETERS_PER_LOG: usize = 14;
+
+/// Number of parameters bound for each block in [`insert_blocks_statement`].
+pub(crate) const BIND_PARAMETERS_PER_BLOCK: usize = 7;
+
+/// Maximum number of logs inserted by a single statement.
+const MAX_LOGS_PER_STATEMENT: usize = SQLITE_MAX_VARIABLE_NUMBER / BIND_PARAMETERS_PER_LOG;
+
+/// A [`ShadowLog`], converted to the column types of the `shadow_logs` table.
+#[derive(Debug)]
+pub(crate) struct LogRow {
+    block_number: i64,
+    block_hash: Vec<u8>,
+    block_timestamp: i64,
+    transaction_index: i64,
+    transaction_hash: Vec<u8>,
+    block_log_index: i64,
+    transaction_log_index: i64,
+    address: Vec<u8>,
+    data: Option<Vec<u8>>,
+    topics: [Option<Vec<u8>>; 4],
+    removed: bool,
+}
+
+impl TryFrom<&ShadowLog> for LogRow {
+    type Error = ShadowDbError;
+
+    fn try_from(log: &ShadowLog) -> Result<Self, Self::Error> {
+        let decode = |value: &str| {
+            hex::decode(value).map_err(|e| ShadowDbError::InvalidLog(format!("{value}: {e}")))
+        };
+        let decode_opt = |value: &Option<String>| value.as_deref().map(decode).transpose();
+
+        Ok(Self {
+            block_number: encode_u64(log.block_number),
+            block_hash: decode(&log.block_hash)?,
+            block_timestamp: encode_u64(log.block_timestamp),
+            transaction_index: encode_u64(log.transaction_index),
+            transaction_hash: decode(&log.transaction_hash)?,
+            block_log_index: encode_u64(log.block_log_index),
+            transaction_log_index: encode_u64(log.transaction_log_index),
+            address: decode(&log.address)?,
+            data: decode_opt(&log.data)?,
+            topics: [
+                decode_opt(&log.topic_0)?,
+                decode_opt(&log.topic_1)?,
+                decode_opt(&log.topic_2)?,
+                decode_opt(&log.topic_3)?,
+            ],
+            removed: log.removed,
+        })
+    }
+}
+
+/// Builds a statement starting with `sql`, followed by the given block hashes as a list of bound
+/// parameters and a closing parenthesis.
+pub(crate) fn block_hashes_statement<'a, DB>(
+    sql: &str,
+    block_hashes: &'a [BlockHash],
+) -> QueryBuilder<'a, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'a>>::Arguments: Default,
+    &'a [u8]: Encode<'a, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(sql);
+    let mut separated = query.separated(", ");
+    for block_hash in block_hashes {
+        separated.push_bind(block_hash.as_slice());
+    }
+    separated.push_unseparated(")");
+    query
+}
+
+/// Builds a prepared statement which upserts the given blocks into the `shadow_blocks` table,
+/// marking them as canonical.
+pub(crate) fn insert_blocks_statement<'a, DB>(blocks: &'a [IndexedBlock]) -> QueryBuilder<'a, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'a>>::Arguments: Default,
+    i64: Encode<'a, DB> + Type<DB>,
+    Option<i64>: Encode<'a, DB> + Type<DB>,
+    &'a [u8]: Encode<'a, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(
+        "INSERT INTO shadow_blocks (block_hash, block_number, parent_hash, block_timestamp, \
+        base_fee, processed_at, config_version, removed) ",
+    );
+    query.push_values(blocks, |mut row_query, block| {
+        let _ = row_query
+            .push_bind(block.block_hash.as_slice())
+            .push_bind(encode_u64(block.block_number))
+            .push_bind(block.parent_hash.as_slice())
+            .push_bind(encode_u64(block.block_timestamp))
+            .push_bind(block.base_fee.map(encode_u64))
+            .push_bind(encode_u64(block.processed_at))
+            .push_bind(block.config_version.as_slice())
+            .push("false");
+    });
+    let _ = query.push(
+        " ON CONFLICT (block_hash) DO UPDATE SET
+            block_number = excluded.block_number,
+            parent_hash = excluded.parent_hash,
+            block_timestamp = excluded.block_timestamp,
+            base_fee = excluded.base_fee,
+            processed_at = excluded.processed_at,
+            config_version = excluded.config_version,
+            removed = excluded.removed",
+    );
+    query
+}
+
+/// Builds a prepared statement which upserts the given rows into the `shadow_logs` table.
+///
+/// `now` is the SQL expression used for the `created_at` and `updated_at` columns.
+pub(crate) fn insert_statement<'a, DB>(rows: &'a [LogRow], now: &str) -> QueryBuilder<'a, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'a>>::Arguments: Default,
+    i64: Encode<'a, DB> + Type<DB>,
+    bool: Encode<'a, DB> + Type<DB>,
+    &'a [u8]: Encode<'a, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(
+        "INSERT INTO shadow_logs (
             block_number,
             block_hash,
             block_timestamp,
@@ -56,95 +423,459 @@ impl ShadowSqliteDb {
             removed,
             created_at,
             updated_at
-        ) VALUES "
-            .to_string();
-
-        let logs_len = logs.len();
-        logs.iter().enumerate().for_each(|(i, log)| {
-            query.push_str(&format!(
-                "({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, date(), date())",
-                log.block_number,
-                format!("X'{}'", &log.block_hash[2..]),
-                log.block_timestamp,
-                log.transaction_index,
-                format!("X'{}'", &log.transaction_hash[2..]),
-                log.block_log_index,
-                log.transaction_log_index,
-                format!("X'{}'", &log.address[2..]),
-                log.data.clone().map_or("NULL".to_string(), |d| format!("X'{}'", &d[2..])),
-                log.topic_0.clone().map_or("NULL".to_string(), |t| format!("X'{}'", &t[2..])),
-                log.topic_1.clone().map_or("NULL".to_string(), |t| format!("X'{}'", &t[2..])),
-                log.topic_2.clone().map_or("NULL".to_string(), |t| format!("X'{}'", &t[2..])),
-                log.topic_3.clone().map_or("NULL".to_string(), |t| format!("X'{}'", &t[2..])),
-                log.removed
-            ));
-            if i < logs_len - 1 {
-                query.push_str(", ");
-            }
-        });
+        ) ",
+    );
 
-        let _ = sqlx::query(&query).execute(&self.pool).await?;
-        debug!("Inserted {} shadow logs in {:?}", logs_len, start_time.elapsed());
-        Ok(())
+    query.push_values(rows, |mut row_query, row| {
+        let _ = row_query
+            .push_bind(row.block_number)
+            .push_bind(row.block_hash.as_slice())
+            .push_bind(row.block_timestamp)
+            .push_bind(row.transaction_index)
+            .push_bind(row.transaction_hash.as_slice())
+            .push_bind(row.block_log_index)
+            .push_bind(row.transaction_log_index)
+            .push_bind(row.address.as_slice())
+            .push_bind(row.data.as_deref())
+            .push_bind(row.topics[0].as_deref())
+            .push_bind(row.topics[1].as_deref())
+            .push_bind(row.topics[2].as_deref())
+            .push_bind(row.topics[3].as_deref())
+            .push_bind(row.removed)
+            .push(now)
+            .push(now);
+    });
+
+    let _ = query.push(
+        " ON CONFLICT (block_hash, block_log_index) DO UPDATE SET
+            block_number = excluded.block_number,
+            block_timestamp = excluded.block_timestamp,
+            transaction_index = excluded.transaction_index,
+            transaction_hash = excluded.transaction_hash,
+            transaction_log_index = excluded.transaction_log_index,
+            address = excluded.address,
+            data = excluded.data,
+            topic_0 = excluded.topic_0,
+            topic_1 = excluded.topic_1,
+            topic_2 = excluded.topic_2,
+            topic_3 = excluded.topic_3,
+            removed = excluded.removed,
+            updated_at = excluded.updated_at",
+    );
+
+    query
+}
+
+/// Encodes a `u64` for storage in an SQLite `INTEGER` or PostgreSQL `BIGINT` column.
+///
+/// Both are signed 64-bit values. Values up to `i64::MAX` are stored unchanged, larger
+/// values wrap around into the negative range, which keeps the encoding lossless. Use
+/// [`push_u64_range_condition`] to filter encoded columns by range.
+pub const fn encode_u64(value: u64) -> i64 {
+    value as i64
+}
+
+/// Decodes a `u64` stored with [`encode_u64`].
+pub const fn decode_u64(value: i64) -> u64 {
+    value as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reth_primitives::{hex, BlockHash, B256};
+    use sqlx::{QueryBuilder, Sqlite};
+
+    use super::{
+        decode_u64, insert_statement, LogRow, ShadowSqliteDb, SqliteConfig, MAX_LOGS_PER_STATEMENT,
+    };
+    use crate::{
+        push_u64_range_condition, BlockFilter, Checkpoint, IndexedBlock, LogFilter, LogPage,
+        LogPosition, PruneOutcome, ShadowDbError, ShadowLog, ShadowStorage, ToLowerHex,
+    };
+
+    fn shadow_log(block_hash: BlockHash, block_log_index: u64) -> ShadowLog {
+        ShadowLog {
+            address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            block_hash: block_hash.to_lower_hex(),
+            block_log_index,
+            block_number: 18870001,
+            block_timestamp: 1703595275,
+            transaction_index: 2,
+            transaction_hash: "0xd02dc650cc9a34def3d7a78808a36a8cb2e292613c2989f4313155e8e4af9b0f"
+                .to_string(),
+            transaction_log_index: block_log_index,
+            removed: false,
+            data: None,
+            topic_0: Some(
+                "0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c".to_string(),
+            ),
+            topic_1: None,
+            topic_2: None,
+            topic_3: None,
+        }
     }
 
-    /// Marks all logs with the given `block_hash` as removed.
-    ///
-    /// This is used to invalid all logs in a block when a reorg happens.
-    pub async fn handle_block_reorg(&self, block_hash: BlockHash) -> Result<()> {
-        let start_time = std::time::Instant::now();
-        let _ = sqlx::query(&format!(
-            "UPDATE shadow_logs SET removed = true WHERE block_hash = X'{block_hash:x}'",
-        ))
-        .execute(&self.pool)
-        .await?;
-        debug!("Invalidated block '{block_hash}' in {:?}", start_time.elapsed());
-        Ok(())
+    async fn count_logs(db: &ShadowSqliteDb, removed: bool) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM shadow_logs WHERE removed = ?")
+            .bind(removed)
+            .fetch_one(&db.reader)
+            .await
+            .unwrap()
     }
-}
 
-async fn create_tables(pool: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
-    // Since BIGINT in SQLite is actually an i64, we're storing the unsigned
-    // values as text instead. The values for these fields will be converted
-    // into their u64 counterparts as they are returned from the database.
-    let sql = r#"
-        CREATE TABLE IF NOT EXISTS shadow_logs(
-            block_number      	text  	not null,
-            block_hash        	varchar(66) not null,
-            block_timestamp   	text  	not null,
-            transaction_index 	text  	not null,
-            transaction_hash  	varchar(66) not null,
-            block_log_index   	text  	not null,
-            transaction_log_index text  	not null,
-            address           	varchar(42) not null,
-            removed           	boolean     not null,
-            data              	text,
-            topic_0           	varchar(66),
-            topic_1           	varchar(66),
-            topic_2           	varchar(66),
-            topic_3           	varchar(66),
-            created_at        	datetime,
-            updated_at        	datetime
+    #[tokio::test]
+    async fn test_reincluded_block_replaces_removed_logs() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let block_hash = BlockHash::repeat_byte(1);
+        let logs = vec![shadow_log(block_hash, 0), shadow_log(block_hash, 1)];
+
+        db.apply_chain_update(&[], &[IndexedBlock::new(18870001, block_hash)], &logs, None)
+            .await
+            .unwrap();
+        assert_eq!(count_logs(&db, false).await, 2);
+
+        db.apply_chain_update(&[block_hash], &[], &[], None).await.unwrap();
+        assert_eq!(count_logs(&db, false).await, 0);
+        assert_eq!(count_logs(&db, true).await, 2);
+
+        db.apply_chain_update(&[], &[IndexedBlock::new(18870001, block_hash)], &logs, None)
+            .await
+            .unwrap();
+        assert_eq!(count_logs(&db, false).await, 2);
+        assert_eq!(count_logs(&db, true).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_page_excludes_removed_logs() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let (reverted, replacement) = (BlockHash::repeat_byte(1), BlockHash::repeat_byte(2));
+        db.bulk_insert_into_shadow_log_table(&[shadow_log(reverted, 0), shadow_log(reverted, 1)])
+            .await
+            .unwrap();
+        db.apply_chain_update(
+            &[reverted],
+            &[IndexedBlock::new(18870001, replacement)],
+            &[shadow_log(replacement, 0), shadow_log(replacement, 1)],
+            None,
         )
-        "#;
+        .await
+        .unwrap();
 
-    let _ = sqlx::query(sql).execute(pool).await?;
-    Ok(())
-}
+        let filter = LogFilter {
+            block: BlockFilter::Range(18870001, 18870001),
+            addresses: Vec::new(),
+            topics: Default::default(),
+        };
+        let page = LogPage {
+            after: Some(LogPosition { block_number: 18870001, block_log_index: 0 }),
+            limit: 10,
+            include_removed: false,
+        };
+        let logs = db.get_logs_page(&filter, &page).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].block_hash, replacement.to_lower_hex());
+        assert_eq!(logs[0].block_log_index, 1);
+
+        let page = LogPage { after: None, limit: 3, include_removed: true };
+        assert_eq!(db.get_logs_page(&filter, &page).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_insert_is_idempotent() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let block_hash = BlockHash::repeat_byte(1);
+        let logs = vec![shadow_log(block_hash, 0), shadow_log(block_hash, 1)];
+
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+
+        assert_eq!(count_logs(&db, false).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        assert_eq!(db.checkpoint().await.unwrap(), None);
+
+        let checkpoint = Checkpoint { block_number: 1, block_hash: BlockHash::repeat_byte(1) };
+        db.apply_chain_update(
+            &[],
+            &[IndexedBlock::new(18870001, checkpoint.block_hash)],
+            &[shadow_log(checkpoint.block_hash, 0)],
+            Some(checkpoint),
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.checkpoint().await.unwrap(), Some(checkpoint));
+
+        let checkpoint =
+            Checkpoint { block_number: u64::MAX, block_hash: BlockHash::repeat_byte(2) };
+        db.apply_chain_update(&[], &[], &[], Some(checkpoint)).await.unwrap();
+        assert_eq!(db.checkpoint().await.unwrap(), Some(checkpoint));
+    }
+
+    #[tokio::test]
+    async fn test_insert_in_chunks() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let block_hash = BlockHash::repeat_byte(1);
+        let logs = (0..MAX_LOGS_PER_STATEMENT as u64 * 2 + 1)
+            .map(|i| shadow_log(block_hash, i))
+            .collect::<Vec<_>>();
+
+        db.bulk_insert_into_shadow_log_table(&[]).await.unwrap();
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+
+        assert_eq!(count_logs(&db, false).await, logs.len() as i64);
+    }
+
+    #[tokio::test]
+    async fn test_insert_rejects_invalid_hex() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let log = ShadowLog {
+            topic_0: Some("0x'); DROP TABLE shadow_logs; --".to_string()),
+            ..shadow_log(BlockHash::repeat_byte(1), 0)
+        };
 
-async fn create_indices(pool: &Pool<Sqlite>) -> Result<(), sqlx::Error> {
-    let sql = r#"
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_address ON shadow_logs (address);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_block_number ON shadow_logs (block_number);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_block_hash ON shadow_logs (block_hash);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_0 ON shadow_logs (topic_0);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_1 ON shadow_logs (topic_1);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_2 ON shadow_logs (topic_2);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_topic_3 ON shadow_logs (topic_3);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_transaction_hash ON shadow_logs (transaction_hash);
-        CREATE INDEX IF NOT EXISTS idx_shadow_logs_removed ON shadow_logs (removed);
-        "#;
-
-    let _ = sqlx::query(sql).execute(pool).await?;
-    Ok(())
+        assert!(matches!(
+            db.bulk_insert_into_shadow_log_table(&[log]).await,
+            Err(ShadowDbError::InvalidLog(_))
+        ));
+        assert_eq!(count_logs(&db, false).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_push_u64_range_condition() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let logs = [9, 10, 100, i64::MAX as u64, i64::MAX as u64 + 1, u64::MAX]
+            .into_iter()
+            .enumerate()
+            .map(|(i, block_number)| ShadowLog {
+                block_number,
+                ..shadow_log(BlockHash::repeat_byte(1), i as u64)
+            })
+            .collect::<Vec<_>>();
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+
+        let block_numbers = |from: u64, to: u64| {
+            let pool = db.reader.clone();
+            async move {
+                let mut query = QueryBuilder::new("SELECT block_number FROM shadow_logs WHERE ");
+                push_u64_range_condition(&mut query, "block_number", from, to);
+                query
+                    .push(" ORDER BY rowid")
+                    .build_query_scalar::<i64>()
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(decode_u64)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(block_numbers(9, 10).await, vec![9, 10]);
+        assert_eq!(block_numbers(10, 99).await, vec![10]);
+        assert_eq!(
+            block_numbers(100, u64::MAX).await,
+            vec![100, i64::MAX as u64, i64::MAX as u64 + 1, u64::MAX]
+        );
+        assert_eq!(
+            block_numbers(i64::MAX as u64 + 1, u64::MAX).await,
+            vec![i64::MAX as u64 + 1, u64::MAX]
+        );
+        assert_eq!(block_numbers(u64::MAX, 9).await, Vec::<u64>::new());
+    }
+
+    #[tokio::test]
+    async fn test_reorg_is_applied_atomically() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let old_hash = BlockHash::repeat_byte(1);
+        let new_hash = BlockHash::repeat_byte(2);
+
+        db.apply_chain_update(
+            &[],
+            &[IndexedBlock::new(18870001, old_hash)],
+            &[shadow_log(old_hash, 0)],
+            None,
+        )
+        .await
+        .unwrap();
+        db.apply_chain_update(
+            &[old_hash],
+            &[IndexedBlock::new(18870001, new_hash)],
+            &[shadow_log(new_hash, 0)],
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(count_logs(&db, false).await, 1);
+        assert_eq!(count_logs(&db, true).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_indexed_blocks() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let (old_hash, new_hash) = (BlockHash::repeat_byte(1), BlockHash::repeat_byte(2));
+        assert_eq!(db.indexed_block(old_hash).await.unwrap(), None);
+        let new_block = IndexedBlock {
+            parent_hash: BlockHash::repeat_byte(3),
+            block_timestamp: u64::MAX,
+            base_fee: Some(7),
+            processed_at: 1_700_000_000,
+            config_version: B256::repeat_byte(4),
+            ..IndexedBlock::new(1, new_hash)
+        };
+
+        // Blocks are recorded even if they did not emit any shadow logs.
+        db.apply_chain_update(&[], &[IndexedBlock::new(1, old_hash)], &[], None).await.unwrap();
+        assert_eq!(db.indexed_block(old_hash).await.unwrap(), Some(IndexedBlock::new(1, old_hash)));
+
+        db.apply_chain_update(&[old_hash], &[new_block], &[], None).await.unwrap();
+        assert_eq!(
+            db.indexed_block(old_hash).await.unwrap(),
+            Some(IndexedBlock { removed: true, ..IndexedBlock::new(1, old_hash) })
+        );
+        assert_eq!(db.indexed_block(new_hash).await.unwrap(), Some(new_block));
+        // Block 1 is counted once, although both a removed and a canonical block have its number.
+        assert_eq!(db.indexed_block_count(0, 2).await.unwrap(), 1);
+        assert_eq!(db.indexed_block_count(1, 1).await.unwrap(), 1);
+        assert_eq!(db.indexed_block_count(2, 1).await.unwrap(), 0);
+
+        // Removed blocks are pruned once finalized, canonical blocks once below the horizon.
+        db.prune(None, Some(1)).await.unwrap();
+        assert_eq!(db.indexed_block(old_hash).await.unwrap(), None);
+        assert!(db.indexed_block(new_hash).await.unwrap().is_some());
+        db.prune(Some(2), None).await.unwrap();
+        assert_eq!(db.indexed_block(new_hash).await.unwrap(), None);
+        assert_eq!(db.indexed_block_count(0, 2).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let logs = (1..=5)
+            .map(|block_number| ShadowLog {
+                block_number,
+                ..shadow_log(BlockHash::repeat_byte(block_number as u8), 0)
+            })
+            .collect::<Vec<_>>();
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+        for block in [2, 4, 5] {
+            db.handle_block_reorg(BlockHash::repeat_byte(block)).await.unwrap();
+        }
+        assert_eq!(db.pruned_horizon().await.unwrap(), None);
+
+        // A horizon of 0 prunes nothing.
+        assert_eq!(db.prune(Some(0), None).await.unwrap(), PruneOutcome::default());
+        assert_eq!(db.pruned_horizon().await.unwrap(), None);
+        assert_eq!(count_logs(&db, false).await, 2);
+        assert_eq!(count_logs(&db, true).await, 3);
+
+        let outcome = db.prune(Some(3), Some(4)).await.unwrap();
+        assert_eq!(outcome, PruneOutcome { logs: 2, removed_logs: 1 });
+        assert_eq!(db.pruned_horizon().await.unwrap(), Some(3));
+        assert_eq!(count_logs(&db, false).await, 1);
+        assert_eq!(count_logs(&db, true).await, 1);
+
+        // The horizon never moves backwards.
+        let outcome = db.prune(Some(2), None).await.unwrap();
+        assert_eq!(outcome, PruneOutcome::default());
+        assert_eq!(db.pruned_horizon().await.unwrap(), Some(3));
+    }
+
+    /// Opens a database file in a temporary directory, which is removed when the returned
+    /// [`tempfile::TempDir`] is dropped.
+    async fn file_db() -> (tempfile::TempDir, ShadowSqliteDb) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shadow.db");
+        let config = SqliteConfig { read_connections: 4, busy_timeout: Duration::from_millis(100) };
+        let db = ShadowSqliteDb::with_config(path.to_str().unwrap(), config).await.unwrap();
+        (dir, db)
+    }
+
+    #[tokio::test]
+    async fn test_file_db_uses_wal_and_read_only_pool() {
+        let (_dir, db) = file_db().await;
+
+        let journal_mode: String =
+            sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&db.reader).await.unwrap();
+        assert_eq!(journal_mode, "wal");
+        assert!(sqlx::query("DELETE FROM shadow_logs").execute(&db.reader).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reads_are_not_blocked_by_open_write() {
+        let (_dir, db) = file_db().await;
+        let block_hash = BlockHash::repeat_byte(1);
+        let filter = LogFilter {
+            block: BlockFilter::Hash(block_hash),
+            addresses: vec![],
+            topics: Default::default(),
+        };
+        let rows = (0..10)
+            .map(|i| LogRow::try_from(&shadow_log(block_hash, i)).unwrap())
+            .collect::<Vec<_>>();
+
+        // Hold a write transaction open for longer than the busy timeout. Readers see the last
+        // committed state instead of waiting for the lock.
+        let mut tx = db.writer.begin().await.unwrap();
+        let _ =
+            insert_statement::<Sqlite>(&rows, "date()").build().execute(&mut *tx).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(db.get_logs(&filter).await.unwrap().is_empty());
+        assert_eq!(db.checkpoint().await.unwrap(), None);
+
+        tx.commit().await.unwrap();
+        assert_eq!(db.get_logs(&filter).await.unwrap().len(), rows.len());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_reads_during_bulk_writes() {
+        let (_dir, db) = file_db().await;
+        let filter = LogFilter {
+            block: BlockFilter::Range(0, u64::MAX),
+            addresses: vec![],
+            topics: Default::default(),
+        };
+
+        let writer = {
+            let db = db.clone();
+            tokio::spawn(async move {
+                for block in 0..10u8 {
+                    let block_hash = BlockHash::repeat_byte(block);
+                    let logs = (0..MAX_LOGS_PER_STATEMENT as u64 * 2)
+                        .map(|i| shadow_log(block_hash, i))
+                        .collect::<Vec<_>>();
+                    db.apply_chain_update(
+                        &[],
+                        &[IndexedBlock::new(18870001, block_hash)],
+                        &logs,
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                }
+            })
+        };
+
+        // Every read sees a consistent snapshot, i.e. a whole number of blocks.
+        let readers = (0..4).map(|_| {
+            let (db, filter) = (db.clone(), filter.clone());
+            tokio::spawn(async move {
+                for _ in 0..20 {
+                    let logs = db.get_logs(&filter).await.unwrap();
+                    assert_eq!(logs.len() % (MAX_LOGS_PER_STATEMENT * 2), 0);
+                }
+            })
+        });
+        for reader in readers.collect::<Vec<_>>() {
+            reader.await.unwrap();
+        }
+        writer.await.unwrap();
+
+        assert_eq!(db.get_logs(&filter).await.unwrap().len(), MAX_LOGS_PER_STATEMENT * 2 * 10);
+    }
 }
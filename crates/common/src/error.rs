@@ -0,0 +1,138 @@
+use reth_primitives::Bytes;
+use sqlx::{error::DatabaseError, postgres::PgDatabaseError};
+
+/// SQLite primary result codes which indicate a transient failure.
+///
+/// See <https://www.sqlite.org/rescode.html> for the full list.
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+const SQLITE_NOMEM: i32 = 7;
+const SQLITE_IOERR: i32 = 10;
+const SQLITE_FULL: i32 = 13;
+
+/// PostgreSQL error classes which indicate a transient failure, the first two characters of an
+/// SQLSTATE code.
+///
+/// See <https://www.postgresql.org/docs/current/errcodes-appendix.html> for the full list.
+const POSTGRES_CONNECTION_EXCEPTION: &str = "08";
+const POSTGRES_TRANSACTION_ROLLBACK: &str = "40";
+const POSTGRES_INSUFFICIENT_RESOURCES: &str = "53";
+const POSTGRES_OPERATOR_INTERVENTION: &str = "57";
+
+/// Errors returned by [`ShadowStorage`](crate::ShadowStorage) operations.
+///
+/// Errors are split into retryable and fatal failures, see
+/// [`ShadowDbError::is_retryable`].
+#[derive(Debug, thiserror::Error)]
+pub enum ShadowDbError {
+    /// The database file is busy or locked by another connection.
+    #[error("shadow database is busy: {0}")]
+    Busy(#[source] sqlx::Error),
+    /// The database could not be written to, e.g. because the disk is full or an I/O error
+    /// occurred.
+    #[error("failed to write to shadow database: {0}")]
+    Io(#[source] sqlx::Error),
+    /// No connection could be acquired from the pool in time.
+    #[error("timed out waiting for a shadow database connection")]
+    PoolTimedOut,
+    /// The database was created by a newer version of shadow-reth and cannot be opened.
+    #[error("shadow database schema version {found} is newer than supported version {supported}")]
+    UnsupportedSchemaVersion {
+        /// Schema version of the database.
+        found: i64,
+        /// Latest schema version supported by this build.
+        supported: i64,
+    },
+    /// A log could not be encoded for storage, e.g. because a hash is not valid hex.
+    #[error("invalid shadow log: {0}")]
+    InvalidLog(String),
+    /// The directory of the MDBX environment could not be created.
+    #[error("failed to create shadow database directory: {0}")]
+    CreateDir(#[source] std::io::Error),
+    /// The MDBX environment returned an error.
+    #[error("shadow MDBX error: {0}")]
+    Mdbx(#[from] reth_libmdbx::Error),
+    /// The blocking task running an MDBX transaction panicked or was cancelled.
+    #[error("shadow MDBX task failed: {0}")]
+    MdbxTask(#[source] tokio::task::JoinError),
+    /// A stored entry could not be decoded.
+    #[error("corrupt shadow database: {0}")]
+    Corrupt(String),
+    /// Any other error, such as a malformed query, a constraint violation or a corrupt database.
+    #[error("fatal shadow database error: {0}")]
+    Fatal(#[source] sqlx::Error),
+}
+
+/// Errors returned by calls executed with the shadow bytecode, as by `shadow_call` and
+/// `shadow_estimateGas`.
+#[derive(Debug, thiserror::Error)]
+pub enum ShadowCallError {
+    /// The call reverted, with the given revert data.
+    #[error("execution reverted")]
+    Reverted(Bytes),
+    /// The call halted exceptionally, e.g. because it ran out of gas.
+    #[error("execution halted: {0}")]
+    Halted(String),
+    /// The call cannot be executed as requested, e.g. because it has no recipient.
+    #[error("invalid call: {0}")]
+    InvalidCall(String),
+    /// The state of the requested block could not be read.
+    #[error("failed to read state: {0}")]
+    State(String),
+}
+
+impl ShadowDbError {
+    /// Returns `true` if the failed operation may succeed when attempted again.
+    ///
+    /// Busy databases, I/O failures (including a full disk or MDBX map) and pool timeouts are
+    /// considered transient. Everything else is fatal and must not be retried.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Busy(_) | Self::Io(_) | Self::PoolTimedOut => true,
+            Self::Mdbx(err) => matches!(
+                err,
+                reth_libmdbx::Error::Busy |
+                    reth_libmdbx::Error::ReadersFull |
+                    reth_libmdbx::Error::MapFull |
+                    reth_libmdbx::Error::UnableExtendMapSize
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl From<sqlx::Error> for ShadowDbError {
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::PoolTimedOut => Self::PoolTimedOut,
+            sqlx::Error::Io(_) => Self::Io(err),
+            sqlx::Error::Database(db_err) if is_postgres(db_err.as_ref()) => {
+                let class = db_err.code().map(|code| code.chars().take(2).collect::<String>());
+                match class.as_deref() {
+                    Some(POSTGRES_TRANSACTION_ROLLBACK) => Self::Busy(err),
+                    Some(
+                        POSTGRES_CONNECTION_EXCEPTION |
+                        POSTGRES_INSUFFICIENT_RESOURCES |
+                        POSTGRES_OPERATOR_INTERVENTION,
+                    ) => Self::Io(err),
+                    _ => Self::Fatal(err),
+                }
+            }
+            sqlx::Error::Database(db_err) => {
+                // SQLite reports extended result codes, the primary code is the lowest byte.
+                let code = db_err.code().and_then(|code| code.parse::<i32>().ok());
+                match code.map(|code| code & 0xff) {
+                    Some(SQLITE_BUSY | SQLITE_LOCKED) => Self::Busy(err),
+                    Some(SQLITE_NOMEM | SQLITE_IOERR | SQLITE_FULL) => Self::Io(err),
+                    _ => Self::Fatal(err),
+                }
+            }
+            _ => Self::Fatal(err),
+        }
+    }
+}
+
+/// Returns `true` if the error was reported by a PostgreSQL database.
+fn is_postgres(err: &dyn DatabaseError) -> bool {
+    err.try_downcast_ref::<PgDatabaseError>().is_some()
+}
@@ -3,10 +3,26 @@
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 
 mod db;
+mod error;
 mod hex;
+mod mdbx;
+mod migrations;
+mod postgres;
+mod query;
+mod retention;
+mod retry;
+mod storage;
 mod types;
 
 // re-exports
 pub use db::*;
+pub use error::*;
 pub use hex::*;
+pub use mdbx::*;
+pub use migrations::*;
+pub use postgres::*;
+pub use query::push_u64_range_condition;
+pub use retention::*;
+pub use retry::*;
+pub use storage::*;
 pub use types::*;
@@ -0,0 +1,1111 @@
+//! Shadow log storage in a dedicated MDBX environment.
+//!
+//! Logs are stored in the `logs` table, keyed by `block_number | block_hash | block_log_index`,
+//! so that block range scans are sequential reads. Big-endian integers keep the keys ordered by
+//! block number. Two index tables map addresses and topics to log keys. Every block processed by
+//! the ExEx is recorded in the `blocks` table, whether or not it emitted any logs:
+//!
+//! | Table           | Key                                        | Value                    |
+//! |-----------------|--------------------------------------------|--------------------------|
+//! | `logs`          | `block_number \| block_hash \| log_index`  | encoded log              |
+//! | `blocks`        | `block_number \| block_hash`               | encoded block            |
+//! | `block_numbers` | `block_hash`                               | `block_number`           |
+//! | `address_index` | `address \| log key`                       | empty                    |
+//! | `topic_index`   | `topic position \| topic \| log key`       | empty                    |
+//! | `checkpoint`    | `0`                                        | `block_number \| hash`   |
+//! | `prune_horizon` | `0`                                        | `block_number`           |
+//!
+//! The environment is separate from reth's own database, whose tables are fixed by reth. It also
+//! has its own write lock, so indexing does not contend with the node's write transactions.
+//!
+//! MDBX transactions are blocking, so every operation runs on tokio's blocking thread pool.
+
+use std::{collections::BTreeSet, path::Path, str::FromStr};
+
+use async_trait::async_trait;
+use reth_libmdbx::{
+    Cursor, Database, DatabaseFlags, Environment, EnvironmentFlags, Geometry, Mode, SyncMode,
+    Transaction, TransactionKind, WriteFlags, RO, RW,
+};
+use reth_primitives::{Address, BlockHash, B256};
+use reth_tracing::tracing::debug;
+
+use crate::{
+    BlockFilter, Checkpoint, IndexedBlock, LogFilter, LogPage, LogPosition, PruneOutcome,
+    ShadowDbError, ShadowLog, ShadowStorage, ToLowerHex,
+};
+
+const LOGS: &str = "logs";
+const BLOCKS: &str = "blocks";
+const BLOCK_NUMBERS: &str = "block_numbers";
+const ADDRESS_INDEX: &str = "address_index";
+const TOPIC_INDEX: &str = "topic_index";
+const CHECKPOINT: &str = "checkpoint";
+const PRUNE_HORIZON: &str = "prune_horizon";
+const TABLES: [&str; 7] =
+    [LOGS, BLOCKS, BLOCK_NUMBERS, ADDRESS_INDEX, TOPIC_INDEX, CHECKPOINT, PRUNE_HORIZON];
+
+/// Key of the single entry in the `checkpoint` and `prune_horizon` tables.
+const ENTRY_KEY: [u8; 1] = [0];
+
+/// Length of a key in the `logs` table.
+const LOG_KEY_LEN: usize = 8 + 32 + 8;
+
+/// Upper bound for the size of the MDBX environment.
+const MAX_SIZE: usize = 1024 * 1024 * 1024 * 1024;
+
+/// Amount by which the MDBX environment grows when it is full.
+const GROWTH_STEP: isize = 256 * 1024 * 1024;
+
+/// Shadow log storage backed by MDBX.
+#[derive(Clone, Debug)]
+pub struct ShadowMdbxDb {
+    /// The MDBX environment.
+    env: Environment,
+}
+
+impl ShadowMdbxDb {
+    /// Opens the MDBX environment in the given directory, creating it if it does not exist.
+    pub fn new(path: &Path) -> Result<Self, ShadowDbError> {
+        std::fs::create_dir_all(path).map_err(ShadowDbError::CreateDir)?;
+
+        let env = Environment::builder()
+            .set_max_dbs(TABLES.len())
+            .set_geometry(Geometry {
+                size: Some(0..MAX_SIZE),
+                growth_step: Some(GROWTH_STEP),
+                shrink_threshold: None,
+                page_size: None,
+            })
+            .set_flags(EnvironmentFlags {
+                mode: Mode::ReadWrite { sync_mode: SyncMode::Durable },
+                no_rdahead: true,
+                coalesce: true,
+                ..Default::default()
+            })
+            .open(path)?;
+
+        let txn = env.begin_rw_txn()?;
+        for table in TABLES {
+            let _ = txn.create_db(Some(table), DatabaseFlags::default())?;
+        }
+        let _ = txn.commit()?;
+
+        Ok(Self { env })
+    }
+
+    /// Runs `f` with a read-write transaction on the blocking thread pool, committing the
+    /// transaction if `f` succeeds.
+    async fn write<T, F>(&self, f: F) -> Result<T, ShadowDbError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Transaction<RW>) -> Result<T, ShadowDbError> + Send + 'static,
+    {
+        let env = self.env.clone();
+        tokio::task::spawn_blocking(move || {
+            let txn = env.begin_rw_txn()?;
+            let value = f(&txn)?;
+            let _ = txn.commit()?;
+            Ok(value)
+        })
+        .await
+        .map_err(ShadowDbError::MdbxTask)?
+    }
+
+    /// Returns the logs matching `filter`, ordered by block number and log index. If a `page` is
+    /// given, only the logs of that page are returned.
+    async fn select_logs(
+        &self,
+        filter: &LogFilter,
+        page: Option<&LogPage>,
+    ) -> Result<Vec<ShadowLog>, ShadowDbError> {
+        let filter = filter.clone();
+        let page = page.cloned();
+        self.read(move |txn| {
+            let tables = Tables::open(txn)?;
+            let limit = page.as_ref().map_or(usize::MAX, |page| page.limit);
+            let admits = |log: &StoredLog| {
+                log.matches(&filter) &&
+                    page.as_ref().map_or(true, |page| page.admits(log.position(), log.removed))
+            };
+
+            let (from, to) = match filter.block {
+                BlockFilter::Hash(block_hash) => {
+                    let mut logs = Vec::new();
+                    for (key, value) in tables.block_logs(txn, &block_hash)? {
+                        if logs.len() == limit {
+                            break
+                        }
+                        let log = StoredLog::decode(&key, &value)?;
+                        if admits(&log) {
+                            logs.push(ShadowLog::from(log));
+                        }
+                    }
+                    return Ok(logs)
+                }
+                BlockFilter::Range(from, to) => {
+                    // Logs before the page start in earlier blocks, so they are not scanned.
+                    let after = page.as_ref().and_then(|page| page.after);
+                    (after.map_or(from, |after| from.max(after.block_number)), to)
+                }
+            };
+            if from > to || limit == 0 {
+                return Ok(Vec::new())
+            }
+
+            // Scan the most selective table available: the address index, then the topic index,
+            // and only then the full block range.
+            let mut cursors = if !filter.addresses.is_empty() {
+                filter
+                    .addresses
+                    .iter()
+                    .map(|address| {
+                        IndexCursor::new(txn, &tables.address_index, address.to_vec(), from, to)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            } else if let Some((position, alternatives)) =
+                filter.topics.iter().enumerate().find(|(_, alternatives)| !alternatives.is_empty())
+            {
+                alternatives
+                    .iter()
+                    .map(|topic| {
+                        let mut prefix = vec![position as u8];
+                        prefix.extend_from_slice(topic.as_slice());
+                        IndexCursor::new(txn, &tables.topic_index, prefix, from, to)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            } else {
+                Vec::new()
+            };
+
+            let mut logs = Vec::new();
+            if cursors.is_empty() {
+                scan(txn, &tables.logs, &from.to_be_bytes(), |key, value| {
+                    let log = StoredLog::decode(&key, &value)?;
+                    if log.block_number > to || logs.len() == limit {
+                        return Ok(false)
+                    }
+                    if admits(&log) {
+                        logs.push(ShadowLog::from(log));
+                    }
+                    Ok(true)
+                })?;
+                return Ok(logs)
+            }
+
+            // Merge the index cursors in log key order, stopping as soon as the page is full.
+            while logs.len() < limit {
+                let Some(key) = cursors.iter().filter_map(|cursor| cursor.key.clone()).min() else {
+                    break
+                };
+                for cursor in &mut cursors {
+                    if cursor.key.as_ref() == Some(&key) {
+                        cursor.advance()?;
+                    }
+                }
+                if let Some(value) = txn.get::<Vec<u8>>(tables.logs.dbi(), &key)? {
+                    let log = StoredLog::decode(&key, &value)?;
+                    if admits(&log) {
+                        logs.push(ShadowLog::from(log));
+                    }
+                }
+            }
+
+            Ok(logs)
+        })
+        .await
+    }
+
+    /// Runs `f` with a read-only transaction on the blocking thread pool.
+    async fn read<T, F>(&self, f: F) -> Result<T, ShadowDbError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Transaction<RO>) -> Result<T, ShadowDbError> + Send + 'static,
+    {
+        let env = self.env.clone();
+        tokio::task::spawn_blocking(move || f(&env.begin_ro_txn()?))
+            .await
+            .map_err(ShadowDbError::MdbxTask)?
+    }
+}
+
+#[async_trait]
+impl ShadowStorage for ShadowMdbxDb {
+    async fn apply_chain_update(
+        &self,
+        reverted: &[BlockHash],
+        committed: &[IndexedBlock],
+        logs: &[ShadowLog],
+        checkpoint: Option<Checkpoint>,
+    ) -> Result<(), ShadowDbError> {
+        let start_time = std::time::Instant::now();
+        let (reverted_len, committed_len, logs_len) = (reverted.len(), committed.len(), logs.len());
+        let reverted = reverted.to_vec();
+        let committed = committed.to_vec();
+        let logs = logs.iter().map(StoredLog::try_from).collect::<Result<Vec<_>, _>>()?;
+
+        self.write(move |txn| {
+            let tables = Tables::open(txn)?;
+
+            for block_hash in &reverted {
+                for (key, value) in tables.block_logs(txn, block_hash)? {
+                    let mut log = StoredLog::decode(&key, &value)?;
+                    log.removed = true;
+                    txn.put(tables.logs.dbi(), key, log.encode_value(), WriteFlags::empty())?;
+                }
+                if let Some(mut block) = tables.block(txn, block_hash)? {
+                    block.removed = true;
+                    tables.insert_block(txn, &block)?;
+                }
+            }
+
+            for block in &committed {
+                for (key, value) in tables.block_logs(txn, &block.block_hash)? {
+                    tables.delete_log(txn, &StoredLog::decode(&key, &value)?)?;
+                }
+                tables.insert_block(txn, &IndexedBlock { removed: false, ..*block })?;
+            }
+
+            for log in &logs {
+                let key = log.key();
+                if let Some(value) = txn.get::<Vec<u8>>(tables.logs.dbi(), &key)? {
+                    tables.delete_log(txn, &StoredLog::decode(&key, &value)?)?;
+                }
+                tables.insert_log(txn, log)?;
+            }
+
+            if let Some(checkpoint) = checkpoint {
+                let mut value = checkpoint.block_number.to_be_bytes().to_vec();
+                value.extend_from_slice(checkpoint.block_hash.as_slice());
+                txn.put(tables.checkpoint.dbi(), ENTRY_KEY, value, WriteFlags::empty())?;
+            }
+
+            Ok(())
+        })
+        .await?;
+
+        debug!(
+            reverted = reverted_len,
+            committed = committed_len,
+            logs = logs_len,
+            "Applied chain update in {:?}",
+            start_time.elapsed()
+        );
+        Ok(())
+    }
+
+    async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<ShadowLog>, ShadowDbError> {
+        self.select_logs(filter, None).await
+    }
+
+    async fn get_logs_page(
+        &self,
+        filter: &LogFilter,
+        page: &LogPage,
+    ) -> Result<Vec<ShadowLog>, ShadowDbError> {
+        self.select_logs(filter, Some(page)).await
+    }
+
+    async fn checkpoint(&self) -> Result<Option<Checkpoint>, ShadowDbError> {
+        self.read(|txn| {
+            let tables = Tables::open(txn)?;
+            txn.get::<Vec<u8>>(tables.checkpoint.dbi(), &ENTRY_KEY)?
+                .map(|value| {
+                    if value.len() != 8 + 32 {
+                        return Err(ShadowDbError::Corrupt("invalid checkpoint".to_string()))
+                    }
+                    Ok(Checkpoint {
+                        block_number: read_u64(&value[..8]),
+                        block_hash: BlockHash::from_slice(&value[8..]),
+                    })
+                })
+                .transpose()
+        })
+        .await
+    }
+
+    async fn indexed_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<IndexedBlock>, ShadowDbError> {
+        self.read(move |txn| Tables::open(txn)?.block(txn, &block_hash)).await
+    }
+
+    async fn indexed_block_count(&self, from: u64, to: u64) -> Result<u64, ShadowDbError> {
+        if from > to {
+            return Ok(0)
+        }
+        self.read(move |txn| {
+            let tables = Tables::open(txn)?;
+            let (mut count, mut last) = (0, None);
+            scan(txn, &tables.blocks, &from.to_be_bytes(), |key, value| {
+                let block_number = read_u64(&key);
+                if block_number > to {
+                    return Ok(false)
+                }
+                // A block number has several entries if a reverted block was replaced.
+                if value.first() != Some(&1) && last != Some(block_number) {
+                    count += 1;
+                    last = Some(block_number);
+                }
+                Ok(true)
+            })?;
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn prune(
+        &self,
+        horizon: Option<u64>,
+        finalized: Option<u64>,
+    ) -> Result<PruneOutcome, ShadowDbError> {
+        self.write(move |txn| {
+            let tables = Tables::open(txn)?;
+            let mut outcome = PruneOutcome::default();
+            let current = tables.pruned_horizon(txn)?;
+
+            if let Some(horizon) = horizon.filter(|horizon| {
+                *horizon > 0 && current.map_or(true, |current| *horizon > current)
+            }) {
+                outcome.logs = tables.delete_logs(txn, horizon - 1, false)?;
+                tables.delete_blocks(txn, horizon - 1, false)?;
+                txn.put(
+                    tables.prune_horizon.dbi(),
+                    ENTRY_KEY,
+                    horizon.to_be_bytes(),
+                    WriteFlags::empty(),
+                )?;
+            }
+
+            if let Some(finalized) = finalized {
+                outcome.removed_logs = tables.delete_logs(txn, finalized, true)?;
+                tables.delete_blocks(txn, finalized, true)?;
+            }
+
+            Ok(outcome)
+        })
+        .await
+    }
+
+    async fn pruned_horizon(&self) -> Result<Option<u64>, ShadowDbError> {
+        self.read(|txn| Tables::open(txn)?.pruned_horizon(txn)).await
+    }
+}
+
+/// Handles to the tables of the shadow MDBX environment.
+#[derive(Debug)]
+struct Tables {
+    logs: Database,
+    blocks: Database,
+    block_numbers: Database,
+    address_index: Database,
+    topic_index: Database,
+    checkpoint: Database,
+    prune_horizon: Database,
+}
+
+impl Tables {
+    fn open<K: TransactionKind>(txn: &Transaction<K>) -> Result<Self, ShadowDbError> {
+        Ok(Self {
+            logs: txn.open_db(Some(LOGS))?,
+            blocks: txn.open_db(Some(BLOCKS))?,
+            block_numbers: txn.open_db(Some(BLOCK_NUMBERS))?,
+            address_index: txn.open_db(Some(ADDRESS_INDEX))?,
+            topic_index: txn.open_db(Some(TOPIC_INDEX))?,
+            checkpoint: txn.open_db(Some(CHECKPOINT))?,
+            prune_horizon: txn.open_db(Some(PRUNE_HORIZON))?,
+        })
+    }
+
+    /// Returns the first block whose logs have not been pruned, if any were.
+    fn pruned_horizon<K: TransactionKind>(
+        &self,
+        txn: &Transaction<K>,
+    ) -> Result<Option<u64>, ShadowDbError> {
+        txn.get::<Vec<u8>>(self.prune_horizon.dbi(), &ENTRY_KEY)?
+            .map(|value| {
+                if value.len() != 8 {
+                    return Err(ShadowDbError::Corrupt("invalid prune horizon".to_string()))
+                }
+                Ok(read_u64(&value))
+            })
+            .transpose()
+    }
+
+    /// Deletes the logs in blocks up to and including `to_block`, or only the removed ones if
+    /// `removed_only` is set. Returns the number of deleted logs.
+    fn delete_logs(
+        &self,
+        txn: &Transaction<RW>,
+        to_block: u64,
+        removed_only: bool,
+    ) -> Result<u64, ShadowDbError> {
+        // Nothing is stored below the pruned horizon, so the scan can start there.
+        let from_block = self.pruned_horizon(txn)?.unwrap_or_default();
+        let mut logs = Vec::new();
+        scan(txn, &self.logs, &from_block.to_be_bytes(), |key, value| {
+            let log = StoredLog::decode(&key, &value)?;
+            if log.block_number > to_block {
+                return Ok(false)
+            }
+            if log.removed || !removed_only {
+                logs.push(log);
+            }
+            Ok(true)
+        })?;
+
+        let mut block_hashes = BTreeSet::new();
+        for log in &logs {
+            self.delete_log(txn, log)?;
+            let _ = block_hashes.insert(log.block_hash);
+        }
+        // Removed logs are only deleted for whole blocks, as a block is reverted as a whole.
+        for block_hash in block_hashes {
+            let _ = txn.del(self.block_numbers.dbi(), block_hash, None)?;
+        }
+        Ok(logs.len() as u64)
+    }
+
+    /// Returns the indexed block with the given hash, if any.
+    fn block<K: TransactionKind>(
+        &self,
+        txn: &Transaction<K>,
+        block_hash: &BlockHash,
+    ) -> Result<Option<IndexedBlock>, ShadowDbError> {
+        let Some(block_number) =
+            txn.get::<Vec<u8>>(self.block_numbers.dbi(), block_hash.as_slice())?
+        else {
+            return Ok(None)
+        };
+        let key = [block_number.as_slice(), block_hash.as_slice()].concat();
+        txn.get::<Vec<u8>>(self.blocks.dbi(), &key)?
+            .map(|value| decode_block(read_u64(&block_number), *block_hash, &value))
+            .transpose()
+    }
+
+    /// Writes an indexed block.
+    fn insert_block(
+        &self,
+        txn: &Transaction<RW>,
+        block: &IndexedBlock,
+    ) -> Result<(), ShadowDbError> {
+        let block_number = block.block_number.to_be_bytes();
+        let key = [block_number.as_slice(), block.block_hash.as_slice()].concat();
+        txn.put(self.blocks.dbi(), key, encode_block(block), WriteFlags::empty())?;
+        txn.put(self.block_numbers.dbi(), block.block_hash, block_number, WriteFlags::empty())?;
+        Ok(())
+    }
+
+    /// Deletes the indexed blocks up to and including `to_block`, or only the removed ones if
+    /// `removed_only` is set.
+    fn delete_blocks(
+        &self,
+        txn: &Transaction<RW>,
+        to_block: u64,
+        removed_only: bool,
+    ) -> Result<(), ShadowDbError> {
+        let from_block = self.pruned_horizon(txn)?.unwrap_or_default();
+        let mut keys = Vec::new();
+        scan(txn, &self.blocks, &from_block.to_be_bytes(), |key, value| {
+            if read_u64(&key) > to_block {
+                return Ok(false)
+            }
+            if value.first() == Some(&1) || !removed_only {
+                keys.push(key);
+            }
+            Ok(true)
+        })?;
+
+        for key in keys {
+            let _ = txn.del(self.blocks.dbi(), &key, None)?;
+            let _ = txn.del(self.block_numbers.dbi(), &key[8..], None)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the keys and values of all logs in the given block.
+    fn block_logs<K: TransactionKind>(
+        &self,
+        txn: &Transaction<K>,
+        block_hash: &BlockHash,
+    ) -> Result<Vec<(Vec<u8>, Vec<u8>)>, ShadowDbError> {
+        let Some(block_number) =
+            txn.get::<Vec<u8>>(self.block_numbers.dbi(), block_hash.as_slice())?
+        else {
+            return Ok(Vec::new())
+        };
+        let mut prefix = block_number;
+        prefix.extend_from_slice(block_hash.as_slice());
+
+        let mut logs = Vec::new();
+        scan(txn, &self.logs, &prefix, |key, value| {
+            if !key.starts_with(&prefix) {
+                return Ok(false)
+            }
+            logs.push((key, value));
+            Ok(true)
+        })?;
+        Ok(logs)
+    }
+
+    /// Writes a log and its index entries.
+    fn insert_log(&self, txn: &Transaction<RW>, log: &StoredLog) -> Result<(), ShadowDbError> {
+        let key = log.key();
+        txn.put(self.logs.dbi(), &key, log.encode_value(), WriteFlags::empty())?;
+        txn.put(
+            self.block_numbers.dbi(),
+            log.block_hash,
+            log.block_number.to_be_bytes(),
+            WriteFlags::empty(),
+        )?;
+        txn.put(self.address_index.dbi(), log.address_key(&key), b"", WriteFlags::empty())?;
+        for topic_key in log.topic_keys(&key) {
+            txn.put(self.topic_index.dbi(), topic_key, b"", WriteFlags::empty())?;
+        }
+        Ok(())
+    }
+
+    /// Deletes a log and its index entries.
+    fn delete_log(&self, txn: &Transaction<RW>, log: &StoredLog) -> Result<(), ShadowDbError> {
+        let key = log.key();
+        let _ = txn.del(self.logs.dbi(), &key, None)?;
+        let _ = txn.del(self.address_index.dbi(), log.address_key(&key), None)?;
+        for topic_key in log.topic_keys(&key) {
+            let _ = txn.del(self.topic_index.dbi(), topic_key, None)?;
+        }
+        Ok(())
+    }
+}
+
+/// A cursor over the keys of the logs in a block range, in the entries of an index table which
+/// start with a given prefix.
+struct IndexCursor<K: TransactionKind> {
+    cursor: Cursor<K>,
+    prefix: Vec<u8>,
+    to: u64,
+    /// Key of the current log, or `None` once the cursor is exhausted.
+    key: Option<Vec<u8>>,
+}
+
+impl<K: TransactionKind> IndexCursor<K> {
+    /// Positions a cursor at the first log in `from..=to` in the entries starting with `prefix`.
+    fn new(
+        txn: &Transaction<K>,
+        index: &Database,
+        prefix: Vec<u8>,
+        from: u64,
+        to: u64,
+    ) -> Result<Self, ShadowDbError> {
+        let mut start = prefix.clone();
+        start.extend_from_slice(&from.to_be_bytes());
+        let mut cursor = txn.cursor(index)?;
+        let entry = cursor.set_range::<Vec<u8>, Vec<u8>>(&start)?;
+        let mut index_cursor = Self { cursor, prefix, to, key: None };
+        index_cursor.key = index_cursor.log_key(entry);
+        Ok(index_cursor)
+    }
+
+    /// Moves the cursor to the next log.
+    fn advance(&mut self) -> Result<(), ShadowDbError> {
+        let entry = self.cursor.next::<Vec<u8>, Vec<u8>>()?;
+        self.key = self.log_key(entry);
+        Ok(())
+    }
+
+    /// Returns the log key of an index entry, or `None` if it is past the end of the scan.
+    fn log_key(&self, entry: Option<(Vec<u8>, Vec<u8>)>) -> Option<Vec<u8>> {
+        let (key, _) = entry?;
+        let log_key = key.strip_prefix(self.prefix.as_slice())?;
+        (read_u64(log_key) <= self.to).then(|| log_key.to_vec())
+    }
+}
+
+/// Calls `f` with every entry of `db`, starting at the first key not less than `start`, until `f`
+/// returns `false`.
+fn scan<K: TransactionKind>(
+    txn: &Transaction<K>,
+    db: &Database,
+    start: &[u8],
+    mut f: impl FnMut(Vec<u8>, Vec<u8>) -> Result<bool, ShadowDbError>,
+) -> Result<(), ShadowDbError> {
+    let mut cursor = txn.cursor(db)?;
+    let mut entry = cursor.set_range::<Vec<u8>, Vec<u8>>(start)?;
+    while let Some((key, value)) = entry {
+        if !f(key, value)? {
+            break
+        }
+        entry = cursor.next()?;
+    }
+    Ok(())
+}
+
+/// Reads a big-endian `u64` from the first eight bytes of `bytes`.
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(buf)
+}
+
+/// A [`ShadowLog`] with decoded fields, as stored in the `logs` table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StoredLog {
+    block_number: u64,
+    block_hash: BlockHash,
+    block_log_index: u64,
+    block_timestamp: u64,
+    transaction_index: u64,
+    transaction_hash: B256,
+    transaction_log_index: u64,
+    address: Address,
+    topics: [Option<B256>; 4],
+    data: Option<Vec<u8>>,
+    removed: bool,
+}
+
+impl StoredLog {
+    /// Returns the key of the log in the `logs` table.
+    fn key(&self) -> Vec<u8> {
+        let mut key = Vec::with_capacity(LOG_KEY_LEN);
+        key.extend_from_slice(&self.block_number.to_be_bytes());
+        key.extend_from_slice(self.block_hash.as_slice());
+        key.extend_from_slice(&self.block_log_index.to_be_bytes());
+        key
+    }
+
+    /// Returns the key of the log's entry in the `address_index` table.
+    fn address_key(&self, key: &[u8]) -> Vec<u8> {
+        [self.address.as_slice(), key].concat()
+    }
+
+    /// Returns the keys of the log's entries in the `topic_index` table.
+    fn topic_keys<'a>(&'a self, key: &'a [u8]) -> impl Iterator<Item = Vec<u8>> + 'a {
+        self.topics.iter().enumerate().filter_map(move |(position, topic)| {
+            topic.map(|topic| [&[position as u8], topic.as_slice(), key].concat())
+        })
+    }
+
+    /// Encodes all fields which are not part of the key:
+    ///
+    /// `removed (1) | topic mask (1) | block_timestamp (8) | transaction_index (8) |
+    /// transaction_log_index (8) | transaction_hash (32) | address (20) | topics (32 each) |
+    /// data`
+    ///
+    /// Bit `i` of the topic mask is set if topic `i` is present. `data` is prefixed with a `1`
+    /// byte if present, and omitted otherwise.
+    fn encode_value(&self) -> Vec<u8> {
+        let mask = self.topics.iter().enumerate().fold(0u8, |mask, (i, topic)| {
+            if topic.is_some() {
+                mask | 1 << i
+            } else {
+                mask
+            }
+        });
+
+        let mut value = vec![self.removed as u8, mask];
+        value.extend_from_slice(&self.block_timestamp.to_be_bytes());
+        value.extend_from_slice(&self.transaction_index.to_be_bytes());
+        value.extend_from_slice(&self.transaction_log_index.to_be_bytes());
+        value.extend_from_slice(self.transaction_hash.as_slice());
+        value.extend_from_slice(self.address.as_slice());
+        for topic in self.topics.iter().flatten() {
+            value.extend_from_slice(topic.as_slice());
+        }
+        if let Some(data) = &self.data {
+            value.push(1);
+            value.extend_from_slice(data);
+        }
+        value
+    }
+
+    /// Decodes a log from its key and value, see [`StoredLog::encode_value`].
+    fn decode(key: &[u8], value: &[u8]) -> Result<Self, ShadowDbError> {
+        const FIXED_LEN: usize = 2 + 8 + 8 + 8 + 32 + 20;
+
+        let mask = value.get(1).copied().unwrap_or_default();
+        let topics_len = 32 * mask.count_ones() as usize;
+        if key.len() != LOG_KEY_LEN || value.len() < FIXED_LEN + topics_len {
+            return Err(ShadowDbError::Corrupt(format!("invalid log entry {}", hex(key))))
+        }
+
+        let mut topics = [None; 4];
+        let mut offset = FIXED_LEN;
+        for (i, topic) in topics.iter_mut().enumerate() {
+            if mask & (1 << i) != 0 {
+                *topic = Some(B256::from_slice(&value[offset..offset + 32]));
+                offset += 32;
+            }
+        }
+        let data = value.get(offset + 1..).map(<[u8]>::to_vec);
+
+        Ok(Self {
+            block_number: read_u64(&key[..8]),
+            block_hash: BlockHash::from_slice(&key[8..40]),
+            block_log_index: read_u64(&key[40..]),
+            removed: value[0] != 0,
+            block_timestamp: read_u64(&value[2..10]),
+            transaction_index: read_u64(&value[10..18]),
+            transaction_log_index: read_u64(&value[18..26]),
+            transaction_hash: B256::from_slice(&value[26..58]),
+            address: Address::from_slice(&value[58..78]),
+            topics,
+            data,
+        })
+    }
+
+    /// Returns the position of the log.
+    const fn position(&self) -> LogPosition {
+        LogPosition { block_number: self.block_number, block_log_index: self.block_log_index }
+    }
+
+    /// Returns `true` if the log matches the addresses and topics of `filter`.
+    fn matches(&self, filter: &LogFilter) -> bool {
+        (filter.addresses.is_empty() || filter.addresses.contains(&self.address)) &&
+            filter.topics.iter().zip(&self.topics).all(|(alternatives, topic)| {
+                alternatives.is_empty() || topic.map_or(false, |t| alternatives.contains(&t))
+            })
+    }
+}
+
+/// Formats bytes as `0x`-prefixed hex, for error messages.
+fn hex(bytes: &[u8]) -> String {
+    format!("0x{}", reth_primitives::hex::encode(bytes))
+}
+
+impl TryFrom<&ShadowLog> for StoredLog {
+    type Error = ShadowDbError;
+
+    fn try_from(log: &ShadowLog) -> Result<Self, Self::Error> {
+        fn parse<T: FromStr>(value: &str) -> Result<T, ShadowDbError>
+        where
+            T::Err: std::fmt::Display,
+        {
+            value.parse().map_err(|e: T::Err| ShadowDbError::InvalidLog(format!("{value}: {e}")))
+        }
+        let topic = |topic: &Option<String>| topic.as_deref().map(parse::<B256>).transpose();
+
+        Ok(Self {
+            block_number: log.block_number,
+            block_hash: parse(&log.block_hash)?,
+            block_log_index: log.block_log_index,
+            block_timestamp: log.block_timestamp,
+            transaction_index: log.transaction_index,
+            transaction_hash: parse(&log.transaction_hash)?,
+            transaction_log_index: log.transaction_log_index,
+            address: parse(&log.address)?,
+            topics: [
+                topic(&log.topic_0)?,
+                topic(&log.topic_1)?,
+                topic(&log.topic_2)?,
+                topic(&log.topic_3)?,
+            ],
+            data: log
+                .data
+                .as_deref()
+                .map(|data| {
+                    reth_primitives::hex::decode(data)
+                        .map_err(|e| ShadowDbError::InvalidLog(format!("{data}: {e}")))
+                })
+                .transpose()?,
+            removed: log.removed,
+        })
+    }
+}
+
+impl From<StoredLog> for ShadowLog {
+    fn from(log: StoredLog) -> Self {
+        let [topic_0, topic_1, topic_2, topic_3] = log.topics.map(|t| t.map(|t| t.to_lower_hex()));
+        Self {
+            address: log.address.to_lower_hex(),
+            block_hash: log.block_hash.to_lower_hex(),
+            block_log_index: log.block_log_index,
+            block_number: log.block_number,
+            block_timestamp: log.block_timestamp,
+            transaction_index: log.transaction_index,
+            transaction_hash: log.transaction_hash.to_lower_hex(),
+            transaction_log_index: log.transaction_log_index,
+            removed: log.removed,
+            data: log.data.map(|data| hex(&data)),
+            topic_0,
+            topic_1,
+            topic_2,
+            topic_3,
+        }
+    }
+}
+
+/// Encodes all fields of an indexed block which are not part of its key:
+///
+/// `removed (1) | parent_hash (32) | block_timestamp (8) | processed_at (8) |
+/// config_version (32) | base_fee (8)`
+///
+/// `base_fee` is omitted for blocks without a base fee.
+fn encode_block(block: &IndexedBlock) -> Vec<u8> {
+    let mut value = vec![block.removed as u8];
+    value.extend_from_slice(block.parent_hash.as_slice());
+    value.extend_from_slice(&block.block_timestamp.to_be_bytes());
+    value.extend_from_slice(&block.processed_at.to_be_bytes());
+    value.extend_from_slice(block.config_version.as_slice());
+    if let Some(base_fee) = block.base_fee {
+        value.extend_from_slice(&base_fee.to_be_bytes());
+    }
+    value
+}
+
+/// Decodes an indexed block, see [`encode_block`]. Blocks indexed before header metadata was
+/// recorded only store the `removed` flag.
+fn decode_block(
+    block_number: u64,
+    block_hash: BlockHash,
+    value: &[u8],
+) -> Result<IndexedBlock, ShadowDbError> {
+    const FIXED_LEN: usize = 1 + 32 + 8 + 8 + 32;
+
+    let mut block = IndexedBlock::new(block_number, block_hash);
+    block.removed = value.first() == Some(&1);
+    if value.len() == 1 {
+        return Ok(block)
+    }
+    if value.len() != FIXED_LEN && value.len() != FIXED_LEN + 8 {
+        return Err(ShadowDbError::Corrupt(format!("invalid block entry {block_hash}")))
+    }
+
+    block.parent_hash = BlockHash::from_slice(&value[1..33]);
+    block.block_timestamp = read_u64(&value[33..41]);
+    block.processed_at = read_u64(&value[41..49]);
+    block.config_version = B256::from_slice(&value[49..81]);
+    block.base_fee = value.get(FIXED_LEN..).filter(|v| !v.is_empty()).map(read_u64);
+    Ok(block)
+}
+
+#[cfg(test)]
+mod tests {
+    use reth_primitives::{Address, BlockHash, B256};
+
+    use super::{ShadowMdbxDb, StoredLog};
+    use crate::{
+        BlockFilter, Checkpoint, IndexedBlock, LogFilter, LogPage, LogPosition, PruneOutcome,
+        ShadowLog, ShadowStorage, ToLowerHex,
+    };
+
+    fn shadow_log(block_number: u64, block_log_index: u64) -> ShadowLog {
+        ShadowLog {
+            address: Address::repeat_byte(block_log_index as u8).to_lower_hex(),
+            block_hash: B256::left_padding_from(&block_number.to_be_bytes()).to_lower_hex(),
+            block_log_index,
+            block_number,
+            block_timestamp: 1703595275,
+            transaction_index: 0,
+            transaction_hash: B256::repeat_byte(1).to_lower_hex(),
+            transaction_log_index: block_log_index,
+            removed: false,
+            data: Some("0x".to_string()),
+            topic_0: None,
+            topic_1: Some(B256::repeat_byte(2).to_lower_hex()),
+            topic_2: None,
+            topic_3: None,
+        }
+    }
+
+    fn block_numbers(logs: Vec<ShadowLog>) -> Vec<(u64, u64)> {
+        logs.into_iter().map(|log| (log.block_number, log.block_log_index)).collect()
+    }
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        let log = StoredLog::try_from(&shadow_log(u64::MAX, 1)).unwrap();
+        let decoded = StoredLog::decode(&log.key(), &log.encode_value()).unwrap();
+        assert_eq!(decoded, log);
+
+        for data in [None, Some(vec![0]), Some(vec![1, 2, 3])] {
+            let log = StoredLog { data, ..log.clone() };
+            let decoded = StoredLog::decode(&log.key(), &log.encode_value()).unwrap();
+            assert_eq!(decoded, log);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ShadowMdbxDb::new(dir.path()).unwrap();
+        let logs = [9, 10, 11, u64::MAX]
+            .into_iter()
+            .flat_map(|block_number| [shadow_log(block_number, 0), shadow_log(block_number, 1)])
+            .collect::<Vec<_>>();
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+
+        let mut filter = LogFilter {
+            block: BlockFilter::Range(10, u64::MAX),
+            addresses: vec![],
+            topics: Default::default(),
+        };
+        assert_eq!(
+            block_numbers(db.get_logs(&filter).await.unwrap()),
+            vec![(10, 0), (10, 1), (11, 0), (11, 1), (u64::MAX, 0), (u64::MAX, 1)]
+        );
+
+        filter.addresses = vec![Address::repeat_byte(1), Address::repeat_byte(3)];
+        assert_eq!(
+            block_numbers(db.get_logs(&filter).await.unwrap()),
+            vec![(10, 1), (11, 1), (u64::MAX, 1)]
+        );
+
+        // The index entries of several addresses are merged in order, up to the page limit.
+        filter.addresses = vec![Address::repeat_byte(1), Address::ZERO];
+        let mut page = LogPage { after: None, limit: 3, include_removed: false };
+        assert_eq!(
+            block_numbers(db.get_logs_page(&filter, &page).await.unwrap()),
+            vec![(10, 0), (10, 1), (11, 0)]
+        );
+        page.after = Some(LogPosition { block_number: 11, block_log_index: 0 });
+        assert_eq!(
+            block_numbers(db.get_logs_page(&filter, &page).await.unwrap()),
+            vec![(11, 1), (u64::MAX, 0), (u64::MAX, 1)]
+        );
+
+        filter.addresses = vec![];
+        filter.block = BlockFilter::Range(9, 10);
+        filter.topics[1] = vec![B256::repeat_byte(2)];
+        assert_eq!(
+            block_numbers(db.get_logs(&filter).await.unwrap()),
+            vec![(9, 0), (9, 1), (10, 0), (10, 1)]
+        );
+
+        filter.topics[0] = vec![B256::repeat_byte(2)];
+        assert!(db.get_logs(&filter).await.unwrap().is_empty());
+
+        let filter = LogFilter {
+            block: BlockFilter::Hash(B256::left_padding_from(&11u64.to_be_bytes())),
+            addresses: vec![Address::ZERO],
+            topics: Default::default(),
+        };
+        let logs = db.get_logs(&filter).await.unwrap();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0].data.as_deref(), Some("0x"));
+    }
+
+    #[tokio::test]
+    async fn test_reorg_and_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ShadowMdbxDb::new(dir.path()).unwrap();
+        let old = shadow_log(1, 0);
+        let new = ShadowLog {
+            block_hash: BlockHash::repeat_byte(9).to_lower_hex(),
+            address: Address::repeat_byte(5).to_lower_hex(),
+            ..shadow_log(1, 0)
+        };
+        let old_hash: BlockHash = old.block_hash.parse().unwrap();
+        let new_hash: BlockHash = new.block_hash.parse().unwrap();
+        assert_eq!(db.checkpoint().await.unwrap(), None);
+
+        let checkpoint = Checkpoint { block_number: 1, block_hash: old_hash };
+        db.apply_chain_update(
+            &[],
+            &[IndexedBlock::new(1, old_hash)],
+            &[old.clone()],
+            Some(checkpoint),
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.checkpoint().await.unwrap(), Some(checkpoint));
+
+        let checkpoint = Checkpoint { block_number: 1, block_hash: new_hash };
+        db.apply_chain_update(
+            &[old_hash],
+            &[IndexedBlock::new(1, new_hash)],
+            &[new.clone()],
+            Some(checkpoint),
+        )
+        .await
+        .unwrap();
+        assert_eq!(db.checkpoint().await.unwrap(), Some(checkpoint));
+
+        let filter = LogFilter {
+            block: BlockFilter::Range(1, 1),
+            addresses: vec![],
+            topics: Default::default(),
+        };
+        let logs = db.get_logs(&filter).await.unwrap();
+        assert_eq!(logs.len(), 2);
+        assert!(logs.iter().any(|log| log.block_hash == old.block_hash && log.removed));
+        assert!(logs.iter().any(|log| log.block_hash == new.block_hash && !log.removed));
+
+        // Re-including the old block replaces its removed logs, including their index entries.
+        let replacement = ShadowLog { address: Address::repeat_byte(6).to_lower_hex(), ..old };
+        db.apply_chain_update(&[new_hash], &[IndexedBlock::new(1, old_hash)], &[replacement], None)
+            .await
+            .unwrap();
+        let filter = LogFilter { addresses: vec![Address::repeat_byte(0)], ..filter };
+        assert!(db.get_logs(&filter).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_indexed_blocks() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ShadowMdbxDb::new(dir.path()).unwrap();
+        let (old_hash, new_hash) = (BlockHash::repeat_byte(1), BlockHash::repeat_byte(2));
+        assert_eq!(db.indexed_block(old_hash).await.unwrap(), None);
+        let new_block = IndexedBlock {
+            parent_hash: BlockHash::repeat_byte(3),
+            block_timestamp: u64::MAX,
+            base_fee: Some(7),
+            processed_at: 1_700_000_000,
+            config_version: B256::repeat_byte(4),
+            ..IndexedBlock::new(1, new_hash)
+        };
+
+        db.apply_chain_update(&[], &[IndexedBlock::new(1, old_hash)], &[], None).await.unwrap();
+        db.apply_chain_update(&[old_hash], &[new_block], &[], None).await.unwrap();
+        assert_eq!(
+            db.indexed_block(old_hash).await.unwrap(),
+            Some(IndexedBlock { removed: true, ..IndexedBlock::new(1, old_hash) })
+        );
+        assert_eq!(db.indexed_block(new_hash).await.unwrap(), Some(new_block));
+        assert_eq!(db.indexed_block_count(0, 2).await.unwrap(), 1);
+        assert_eq!(db.indexed_block_count(1, 1).await.unwrap(), 1);
+        assert_eq!(db.indexed_block_count(2, 1).await.unwrap(), 0);
+
+        db.prune(None, Some(1)).await.unwrap();
+        assert_eq!(db.indexed_block(old_hash).await.unwrap(), None);
+        db.prune(Some(2), None).await.unwrap();
+        assert_eq!(db.indexed_block(new_hash).await.unwrap(), None);
+        assert_eq!(db.indexed_block_count(0, 2).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_prune() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ShadowMdbxDb::new(dir.path()).unwrap();
+        let logs = (1..=5).map(|block_number| shadow_log(block_number, 0)).collect::<Vec<_>>();
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+        for block_number in [2u64, 4, 5] {
+            let block_hash = B256::left_padding_from(&block_number.to_be_bytes());
+            db.handle_block_reorg(block_hash).await.unwrap();
+        }
+        let filter = LogFilter {
+            block: BlockFilter::Range(0, u64::MAX),
+            addresses: vec![Address::ZERO],
+            topics: Default::default(),
+        };
+
+        // A horizon of 0 prunes nothing.
+        assert_eq!(db.prune(Some(0), None).await.unwrap(), PruneOutcome::default());
+        assert_eq!(db.pruned_horizon().await.unwrap(), None);
+        assert_eq!(db.get_logs(&filter).await.unwrap().len(), 5);
+
+        let outcome = db.prune(Some(3), Some(4)).await.unwrap();
+        assert_eq!(outcome, PruneOutcome { logs: 2, removed_logs: 1 });
+        assert_eq!(db.pruned_horizon().await.unwrap(), Some(3));
+
+        let logs = db.get_logs(&filter).await.unwrap();
+        assert_eq!(
+            logs.iter().map(|log| (log.block_number, log.removed)).collect::<Vec<_>>(),
+            [(3, false), (5, true)]
+        );
+
+        // The horizon never moves backwards.
+        assert_eq!(db.prune(Some(2), None).await.unwrap(), PruneOutcome::default());
+        assert_eq!(db.pruned_horizon().await.unwrap(), Some(3));
+    }
+}
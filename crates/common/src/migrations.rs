@@ -0,0 +1,317 @@
+//! Versioned schema migrations for the shadow database.
+//!
+//! Migrations are embedded into the binary and applied in order by [`migrate`], or
+//! [`migrate_postgres`] for PostgreSQL databases. The version of each applied migration is
+//! recorded in the `schema_version` table.
+
+use reth_tracing::tracing::info;
+use sqlx::{Executor, Pool, Postgres, Sqlite};
+
+use crate::ShadowDbError;
+
+/// A forward-only schema migration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    /// Schema version after this migration has been applied.
+    pub version: i64,
+    /// Short description of the migration.
+    pub description: &'static str,
+    /// SQL statements applying the migration.
+    pub sql: &'static str,
+}
+
+/// All migrations, in the order they are applied.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create shadow_logs",
+        sql: include_str!("../migrations/0001_create_shadow_logs.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "unique log key",
+        sql: include_str!("../migrations/0002_unique_log_key.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "integer columns",
+        sql: include_str!("../migrations/0003_integer_columns.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "checkpoint",
+        sql: include_str!("../migrations/0004_checkpoint.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "prune horizon",
+        sql: include_str!("../migrations/0005_prune_horizon.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "shadow blocks",
+        sql: include_str!("../migrations/0006_shadow_blocks.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "shadow block headers",
+        sql: include_str!("../migrations/0007_shadow_block_headers.sql"),
+    },
+];
+
+/// All PostgreSQL migrations, in the order they are applied.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create shadow_logs",
+        sql: include_str!("../migrations/postgres/0001_create_shadow_logs.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "prune horizon",
+        sql: include_str!("../migrations/postgres/0002_prune_horizon.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "shadow blocks",
+        sql: include_str!("../migrations/postgres/0003_shadow_blocks.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "shadow block headers",
+        sql: include_str!("../migrations/postgres/0004_shadow_block_headers.sql"),
+    },
+];
+
+/// The schema version this build of shadow-reth expects.
+pub fn latest_schema_version() -> i64 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Brings the schema of the given database up to [`latest_schema_version`].
+///
+/// Each pending migration runs in its own transaction, together with recording its version.
+/// Databases created by a newer version of shadow-reth are rejected with
+/// [`ShadowDbError::UnsupportedSchemaVersion`].
+pub async fn migrate(pool: &Pool<Sqlite>) -> Result<(), ShadowDbError> {
+    let _ = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version(
+            version     	integer 	primary key,
+            description 	text    	not null,
+            applied_at  	datetime 	not null
+        )",
+    )
+    .execute(pool)
+    .await?;
+
+    let current = match current_schema_version(pool).await? {
+        Some(version) => version,
+        None => baseline_schema_version(pool).await?,
+    };
+    let supported = latest_schema_version();
+    if current > supported {
+        return Err(ShadowDbError::UnsupportedSchemaVersion { found: current, supported })
+    }
+
+    for migration in MIGRATIONS.iter().filter(|migration| migration.version > current) {
+        let start_time = std::time::Instant::now();
+        let mut tx = pool.begin().await?;
+        let _ = sqlx::query(migration.sql).execute(&mut *tx).await?;
+        let _ = sqlx::query("INSERT INTO schema_version VALUES (?, ?, datetime())")
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        info!(
+            version = migration.version,
+            "Applied shadow database migration `{}` in {:?}",
+            migration.description,
+            start_time.elapsed()
+        );
+    }
+
+    Ok(())
+}
+
+/// Brings the schema of the given PostgreSQL database up to date with [`POSTGRES_MIGRATIONS`].
+///
+/// See [`migrate`] for details.
+pub async fn migrate_postgres(pool: &Pool<Postgres>) -> Result<(), ShadowDbError> {
+    let _ = sqlx::query(
+        "CREATE TABLE IF NOT EXISTS schema_version(
+            version     	bigint  	primary key,
+            description 	text    	not null,
+            applied_at  	timestamptz not null
+        )",
+    )
+    .execute(pool)
+    .await?;
+
+    let current = sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM schema_version")
+        .fetch_one(pool)
+        .await?
+        .unwrap_or_default();
+    let supported = POSTGRES_MIGRATIONS.last().map_or(0, |migration| migration.version);
+    if current > supported {
+        return Err(ShadowDbError::UnsupportedSchemaVersion { found: current, supported })
+    }
+
+    for migration in POSTGRES_MIGRATIONS.iter().filter(|migration| migration.version > current) {
+        let start_time = std::time::Instant::now();
+        let mut tx = pool.begin().await?;
+        // Executing the raw string uses the simple query protocol, which allows a migration to
+        // consist of multiple statements.
+        let _ = (&mut *tx).execute(migration.sql).await?;
+        let _ = sqlx::query("INSERT INTO schema_version VALUES ($1, $2, now())")
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        info!(
+            version = migration.version,
+            "Applied shadow database migration `{}` in {:?}",
+            migration.description,
+            start_time.elapsed()
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the highest schema version recorded in the `schema_version` table, if any.
+pub async fn current_schema_version(pool: &Pool<Sqlite>) -> Result<Option<i64>, ShadowDbError> {
+    Ok(sqlx::query_scalar::<_, Option<i64>>("SELECT MAX(version) FROM schema_version")
+        .fetch_one(pool)
+        .await?)
+}
+
+/// Determines the schema version of a database created before versions were recorded, and
+/// records it as the baseline.
+///
+/// Returns `0` for empty databases.
+async fn baseline_schema_version(pool: &Pool<Sqlite>) -> Result<i64, ShadowDbError> {
+    let block_number_type: Option<String> = sqlx::query_scalar(
+        "SELECT type FROM pragma_table_info('shadow_logs') WHERE name = 'block_number'",
+    )
+    .fetch_optional(pool)
+    .await?;
+    let unique_indices: i64 = sqlx::query_scalar(
+        r#"SELECT COUNT(*) FROM pragma_index_list('shadow_logs') WHERE "unique" = 1"#,
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let version = match block_number_type {
+        None => return Ok(0),
+        Some(t) if t.eq_ignore_ascii_case("integer") => 3,
+        Some(_) if unique_indices > 0 => 2,
+        Some(_) => 1,
+    };
+
+    let _ = sqlx::query("INSERT INTO schema_version VALUES (?, 'baseline', datetime())")
+        .bind(version)
+        .execute(pool)
+        .await?;
+    info!(version, "Detected existing shadow database schema");
+
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
+
+    use super::{
+        current_schema_version, latest_schema_version, migrate, MIGRATIONS, POSTGRES_MIGRATIONS,
+    };
+    use crate::ShadowDbError;
+
+    async fn pool() -> Pool<Sqlite> {
+        SqlitePoolOptions::new().connect(":memory:").await.unwrap()
+    }
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        for migrations in [MIGRATIONS, POSTGRES_MIGRATIONS] {
+            for (i, migration) in migrations.iter().enumerate() {
+                assert_eq!(migration.version, i as i64 + 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_migrate_empty_database() {
+        let pool = pool().await;
+
+        migrate(&pool).await.unwrap();
+        // Running the migrations a second time is a no-op.
+        migrate(&pool).await.unwrap();
+
+        assert_eq!(current_schema_version(&pool).await.unwrap(), Some(latest_schema_version()));
+    }
+
+    #[tokio::test]
+    async fn test_migrate_unversioned_database() {
+        let pool = pool().await;
+        sqlx::query(MIGRATIONS[0].sql).execute(&pool).await.unwrap();
+        sqlx::query(
+            "INSERT INTO shadow_logs (
+                block_number,
+                block_hash,
+                block_timestamp,
+                transaction_index,
+                transaction_hash,
+                block_log_index,
+                transaction_log_index,
+                address,
+                removed
+            ) VALUES
+                (9, X'01', 1, 0, X'02', 0, 0, X'03', false),
+                (9, X'01', 1, 0, X'02', 0, 0, X'03', false),
+                (10, X'04', 2, 0, X'05', 0, 0, X'03', false)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        migrate(&pool).await.unwrap();
+
+        assert_eq!(current_schema_version(&pool).await.unwrap(), Some(latest_schema_version()));
+        let block_numbers: Vec<i64> = sqlx::query_scalar(
+            "SELECT block_number FROM shadow_logs WHERE block_number BETWEEN 9 AND 10",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        assert_eq!(block_numbers, vec![9, 10]);
+
+        // Blocks with logs are recorded as indexed blocks.
+        let block_numbers: Vec<i64> =
+            sqlx::query_scalar("SELECT block_number FROM shadow_blocks ORDER BY block_number")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(block_numbers, vec![9, 10]);
+    }
+
+    #[tokio::test]
+    async fn test_refuses_newer_schema_version() {
+        let pool = pool().await;
+        migrate(&pool).await.unwrap();
+        sqlx::query("INSERT INTO schema_version VALUES (?, 'future', datetime())")
+            .bind(latest_schema_version() + 1)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            migrate(&pool).await,
+            Err(ShadowDbError::UnsupportedSchemaVersion { found, supported })
+                if found == latest_schema_version() + 1 && supported == latest_schema_version()
+        ));
+    }
+}
@@ -0,0 +1,434 @@
+//! SQL statements shared by the SQLite and PostgreSQL backends.
+
+use reth_primitives::{hex, BlockHash, B256};
+use sqlx::{database::HasArguments, Database, Encode, QueryBuilder, Type};
+
+use crate::{decode_u64, encode_u64, BlockFilter, IndexedBlock, LogFilter, LogPage, ShadowLog};
+
+/// Builds a statement selecting the logs which match `filter`, ordered by block number and log
+/// index. If a `page` is given, only the logs of that page are selected.
+///
+/// All values are bound as parameters, none are interpolated into the SQL statement.
+pub(crate) fn select_logs_statement<'args, DB>(
+    filter: &LogFilter,
+    page: Option<&LogPage>,
+) -> QueryBuilder<'args, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'args>>::Arguments: Default,
+    i64: Encode<'args, DB> + Type<DB>,
+    Vec<u8>: Encode<'args, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(
+        "
+        SELECT
+            address,
+            block_hash,
+            block_log_index,
+            block_number,
+            block_timestamp,
+            data,
+            removed,
+            topic_0,
+            topic_1,
+            topic_2,
+            topic_3,
+            transaction_hash,
+            transaction_index,
+            transaction_log_index
+        FROM shadow_logs
+        WHERE ",
+    );
+
+    if !filter.addresses.is_empty() {
+        let _ = query.push("address IN (");
+        let mut addresses = query.separated(", ");
+        for address in &filter.addresses {
+            let _ = addresses.push_bind(address.to_vec());
+        }
+        let _ = query.push(") AND ");
+    }
+
+    match &filter.block {
+        BlockFilter::Hash(block_hash) => {
+            let _ = query.push("block_hash = ").push_bind(block_hash.to_vec());
+        }
+        BlockFilter::Range(from_block, to_block) => {
+            push_u64_range_condition(&mut query, "block_number", *from_block, *to_block);
+        }
+    }
+
+    for (idx, alternatives) in filter.topics.iter().enumerate() {
+        match alternatives.as_slice() {
+            [] => {}
+            [topic] => {
+                let _ = query.push(format_args!(" AND topic_{idx} = ")).push_bind(topic.to_vec());
+            }
+            alternatives => {
+                let _ = query.push(format_args!(" AND topic_{idx} IN ("));
+                let mut topics = query.separated(", ");
+                for topic in alternatives {
+                    let _ = topics.push_bind(topic.to_vec());
+                }
+                let _ = query.push(")");
+            }
+        }
+    }
+
+    if let Some(page) = page {
+        if !page.include_removed {
+            let _ = query.push(" AND removed = false");
+        }
+        if let Some(after) = page.after {
+            let block_number = encode_u64(after.block_number);
+            let _ = query
+                .push(" AND (block_number > ")
+                .push_bind(block_number)
+                .push(" OR (block_number = ")
+                .push_bind(block_number)
+                .push(" AND block_log_index > ")
+                .push_bind(encode_u64(after.block_log_index))
+                .push("))");
+        }
+    }
+
+    let _ = query.push(" ORDER BY block_number, block_log_index");
+    if let Some(page) = page {
+        let _ = query.push(" LIMIT ").push_bind(i64::try_from(page.limit).unwrap_or(i64::MAX));
+    }
+    query
+}
+
+/// Builds a statement deleting the rows of `table` in blocks up to and including `to_block`. If
+/// `removed_only` is set, only rows which were removed from the canonical chain are deleted.
+///
+/// `table` must have `block_number` and `removed` columns, such as `shadow_logs` and
+/// `shadow_blocks`.
+pub(crate) fn delete_statement<'args, DB>(
+    table: &str,
+    to_block: u64,
+    removed_only: bool,
+) -> QueryBuilder<'args, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'args>>::Arguments: Default,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(format!("DELETE FROM {table} WHERE "));
+    if removed_only {
+        let _ = query.push("removed = true AND ");
+    }
+    push_u64_range_condition(&mut query, "block_number", 0, to_block);
+    query
+}
+
+/// Builds a statement counting the distinct block numbers in `from..=to` with a canonical row in
+/// `shadow_blocks`.
+pub(crate) fn count_indexed_blocks_statement<'args, DB>(
+    from: u64,
+    to: u64,
+) -> QueryBuilder<'args, DB>
+where
+    DB: Database,
+    <DB as HasArguments<'args>>::Arguments: Default,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    let mut query = QueryBuilder::new(
+        "SELECT COUNT(DISTINCT block_number) FROM shadow_blocks WHERE removed = false AND ",
+    );
+    push_u64_range_condition(&mut query, "block_number", from, to);
+    query
+}
+
+/// Appends an SQL condition matching rows where the [`encode_u64`]-encoded `column` lies within
+/// `from..=to` to `query`. The bounds are bound as parameters.
+pub fn push_u64_range_condition<'args, DB>(
+    query: &mut QueryBuilder<'args, DB>,
+    column: &str,
+    from: u64,
+    to: u64,
+) where
+    DB: Database,
+    i64: Encode<'args, DB> + Type<DB>,
+{
+    let (from_encoded, to_encoded) = (encode_u64(from), encode_u64(to));
+    match (from_encoded < 0, to_encoded < 0) {
+        _ if from > to => {
+            let _ = query.push("FALSE");
+        }
+        // The range crosses `i64::MAX`, so it is split into a non-negative and a negative part.
+        (false, true) => {
+            let _ = query
+                .push(format_args!("({column} >= "))
+                .push_bind(from_encoded)
+                .push(format_args!(" OR {column} BETWEEN "))
+                .push_bind(i64::MIN)
+                .push(" AND ")
+                .push_bind(to_encoded)
+                .push(")");
+        }
+        _ => {
+            let _ = query
+                .push(format_args!("{column} BETWEEN "))
+                .push_bind(from_encoded)
+                .push(" AND ")
+                .push_bind(to_encoded);
+        }
+    }
+}
+
+/// A row returned by [`select_logs_statement`].
+#[derive(Debug, sqlx::FromRow)]
+pub(crate) struct RawGetLogsRow {
+    /// Address from which a log originated.
+    pub(crate) address: Vec<u8>,
+    /// Hash of bock from which a log orignated.
+    pub(crate) block_hash: Vec<u8>,
+    /// Integer of the log index position in its containing block.
+    pub(crate) block_log_index: i64,
+    /// Block number from which a log originated.
+    pub(crate) block_number: i64,
+    /// Timestamp of block from which the log originated.
+    pub(crate) block_timestamp: i64,
+    /// Contains one or more 32-byte non-indexed arguments of the log.
+    pub(crate) data: Option<Vec<u8>>,
+    /// Indicates whether a log was removed from the canonical chain.
+    pub(crate) removed: bool,
+    /// Hash of event signature.
+    pub(crate) topic_0: Option<Vec<u8>>,
+    /// Additional topic #1.
+    pub(crate) topic_1: Option<Vec<u8>>,
+    /// Additional topic #2.
+    pub(crate) topic_2: Option<Vec<u8>>,
+    /// Additional topic #3.
+    pub(crate) topic_3: Option<Vec<u8>>,
+    /// Hash of the transaction from which a log originated.
+    pub(crate) transaction_hash: Vec<u8>,
+    /// Integer of the transaction index position in a log's containing block.
+    pub(crate) transaction_index: i64,
+    /// Integer of the log index position within a transaction.
+    pub(crate) transaction_log_index: i64,
+}
+
+impl From<RawGetLogsRow> for ShadowLog {
+    fn from(value: RawGetLogsRow) -> Self {
+        Self {
+            address: format!("0x{}", hex::encode(value.address)),
+            block_hash: format!("0x{}", hex::encode(value.block_hash)),
+            block_log_index: decode_u64(value.block_log_index),
+            block_number: decode_u64(value.block_number),
+            block_timestamp: decode_u64(value.block_timestamp),
+            transaction_index: decode_u64(value.transaction_index),
+            transaction_hash: format!("0x{}", hex::encode(value.transaction_hash)),
+            transaction_log_index: decode_u64(value.transaction_log_index),
+            removed: value.removed,
+            data: value.data.map(|d| format!("0x{}", hex::encode(d))),
+            topic_0: value.topic_0.map(|t| format!("0x{}", hex::encode(t))),
+            topic_1: value.topic_1.map(|t| format!("0x{}", hex::encode(t))),
+            topic_2: value.topic_2.map(|t| format!("0x{}", hex::encode(t))),
+            topic_3: value.topic_3.map(|t| format!("0x{}", hex::encode(t))),
+        }
+    }
+}
+
+/// Columns of the `shadow_blocks` table selected into a [`RawBlockRow`].
+pub(crate) const BLOCK_COLUMNS: &str = "block_number, block_hash, parent_hash, block_timestamp, \
+    base_fee, processed_at, config_version, removed";
+
+/// A row of the `shadow_blocks` table, see [`BLOCK_COLUMNS`].
+#[derive(Debug, sqlx::FromRow)]
+pub(crate) struct RawBlockRow {
+    /// Number of the block.
+    pub(crate) block_number: i64,
+    /// Hash of the block.
+    pub(crate) block_hash: Vec<u8>,
+    /// Hash of the parent block, unless the block was indexed before headers were recorded.
+    pub(crate) parent_hash: Option<Vec<u8>>,
+    /// Timestamp of the block.
+    pub(crate) block_timestamp: Option<i64>,
+    /// Base fee per gas of the block.
+    pub(crate) base_fee: Option<i64>,
+    /// UNIX timestamp at which the block was processed.
+    pub(crate) processed_at: Option<i64>,
+    /// Version of the shadow configuration the block was executed with.
+    pub(crate) config_version: Option<Vec<u8>>,
+    /// Indicates whether the block was removed from the canonical chain.
+    pub(crate) removed: bool,
+}
+
+impl From<RawBlockRow> for IndexedBlock {
+    fn from(value: RawBlockRow) -> Self {
+        Self {
+            block_number: decode_u64(value.block_number),
+            block_hash: BlockHash::from_slice(&value.block_hash),
+            parent_hash: value.parent_hash.map_or(BlockHash::ZERO, |h| BlockHash::from_slice(&h)),
+            block_timestamp: value.block_timestamp.map_or(0, decode_u64),
+            base_fee: value.base_fee.map(decode_u64),
+            processed_at: value.processed_at.map_or(0, decode_u64),
+            config_version: value.config_version.map_or(B256::ZERO, |v| B256::from_slice(&v)),
+            removed: value.removed,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, option, prelude::*, sample::select};
+    use reth_primitives::{Address, B256};
+    use sqlx::Sqlite;
+
+    use super::select_logs_statement;
+    use crate::{
+        BlockFilter, LogFilter, LogPage, LogPosition, ShadowLog, ShadowMdbxDb, ShadowSqliteDb,
+        ShadowStorage, ToLowerHex,
+    };
+
+    /// Addresses and topics are drawn from small sets, so that filters regularly match.
+    const VALUES: [u8; 3] = [1, 2, 3];
+
+    fn arb_log() -> impl Strategy<Value = ShadowLog> {
+        (1..=5u64, select(VALUES.to_vec()), [(); 4].map(|_| option::of(select(VALUES.to_vec()))))
+            .prop_map(|(block_number, address, topics)| {
+                let [topic_0, topic_1, topic_2, topic_3] =
+                    topics.map(|t| t.map(|t| B256::repeat_byte(t).to_lower_hex()));
+                ShadowLog {
+                    address: Address::repeat_byte(address).to_lower_hex(),
+                    block_hash: B256::left_padding_from(&[block_number as u8]).to_lower_hex(),
+                    block_log_index: 0,
+                    block_number,
+                    block_timestamp: 0,
+                    transaction_index: 0,
+                    transaction_hash: B256::ZERO.to_lower_hex(),
+                    transaction_log_index: 0,
+                    removed: false,
+                    data: None,
+                    topic_0,
+                    topic_1,
+                    topic_2,
+                    topic_3,
+                }
+            })
+    }
+
+    fn arb_filter() -> impl Strategy<Value = LogFilter> {
+        let values = || vec(select(VALUES.to_vec()), 0..=2);
+        (0..=6u64, 0..=6u64, values(), [(); 4].map(|_| values())).prop_map(
+            |(from, to, addresses, topics)| LogFilter {
+                block: BlockFilter::Range(from, to),
+                addresses: addresses.into_iter().map(Address::repeat_byte).collect(),
+                topics: topics.map(|t| t.into_iter().map(B256::repeat_byte).collect()),
+            },
+        )
+    }
+
+    /// The reference semantics of `eth_getLogs` filters.
+    fn reference_filter(logs: &[ShadowLog], filter: &LogFilter) -> Vec<(u64, u64)> {
+        let BlockFilter::Range(from, to) = filter.block else { unreachable!() };
+        logs.iter()
+            .filter(|log| (from..=to).contains(&log.block_number))
+            .filter(|log| {
+                filter.addresses.is_empty() ||
+                    filter.addresses.iter().any(|a| a.to_lower_hex() == log.address)
+            })
+            .filter(|log| {
+                let topics = [&log.topic_0, &log.topic_1, &log.topic_2, &log.topic_3];
+                filter.topics.iter().zip(topics).all(|(alternatives, topic)| {
+                    alternatives.is_empty() ||
+                        alternatives.iter().any(|a| Some(a.to_lower_hex()) == *topic)
+                })
+            })
+            .map(|log| (log.block_number, log.block_log_index))
+            .collect()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_get_logs_matches_reference_filter(
+            mut logs in vec(arb_log(), 0..30),
+            filter in arb_filter(),
+        ) {
+            logs.sort_by_key(|log| log.block_number);
+            for (block_log_index, log) in logs.iter_mut().enumerate() {
+                log.block_log_index = block_log_index as u64;
+            }
+            let expected = reference_filter(&logs, &filter);
+
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            let dir = tempfile::tempdir().unwrap();
+            let backends: [Box<dyn ShadowStorage>; 2] = runtime.block_on(async {
+                [
+                    Box::new(ShadowSqliteDb::new(":memory:").await.unwrap()),
+                    Box::new(ShadowMdbxDb::new(dir.path()).unwrap()),
+                ]
+            });
+            for db in backends {
+                let found = runtime.block_on(async {
+                    db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+                    db.get_logs(&filter).await.unwrap()
+                });
+                let found = found
+                    .iter()
+                    .map(|log| (log.block_number, log.block_log_index))
+                    .collect::<Vec<_>>();
+                prop_assert_eq!(&found, &expected, "{:?}", db);
+
+                // Paging through the logs yields the same logs.
+                let mut page = LogPage { after: None, limit: 2, include_removed: false };
+                let mut paged = Vec::new();
+                loop {
+                    let logs = runtime.block_on(db.get_logs_page(&filter, &page)).unwrap();
+                    paged.extend(logs.iter().map(|log| (log.block_number, log.block_log_index)));
+                    match logs.last() {
+                        Some(log) if logs.len() == page.limit => {
+                            page.after = Some(LogPosition {
+                                block_number: log.block_number,
+                                block_log_index: log.block_log_index,
+                            });
+                        }
+                        _ => break,
+                    }
+                }
+                prop_assert_eq!(&paged, &expected, "{:?}", db);
+            }
+        }
+    }
+
+    #[test]
+    fn test_select_logs_statement() {
+        let filter = LogFilter {
+            block: BlockFilter::Hash(B256::ZERO),
+            addresses: vec![Address::ZERO],
+            topics: [vec![B256::ZERO], vec![], vec![], vec![]],
+        };
+        let query = select_logs_statement::<Sqlite>(&filter, None);
+        assert!(query.sql().ends_with(
+            "WHERE address IN (?) AND block_hash = ? AND topic_0 = ? \
+             ORDER BY block_number, block_log_index"
+        ));
+
+        let filter = LogFilter {
+            block: BlockFilter::Range(0, 10),
+            addresses: vec![Address::ZERO, Address::repeat_byte(1)],
+            topics: [vec![B256::ZERO], vec![], vec![B256::ZERO, B256::repeat_byte(1)], vec![]],
+        };
+        let query = select_logs_statement::<Sqlite>(&filter, None);
+        assert!(query.sql().ends_with(
+            "WHERE address IN (?, ?) AND block_number BETWEEN ? AND ? AND topic_0 = ? \
+             AND topic_2 IN (?, ?) ORDER BY block_number, block_log_index"
+        ));
+
+        let page = LogPage {
+            after: Some(LogPosition { block_number: 5, block_log_index: 2 }),
+            limit: 100,
+            include_removed: false,
+        };
+        let query = select_logs_statement::<Sqlite>(&filter, Some(&page));
+        assert!(query.sql().ends_with(
+            "AND topic_2 IN (?, ?) AND removed = false AND (block_number > ? OR \
+             (block_number = ? AND block_log_index > ?)) \
+             ORDER BY block_number, block_log_index LIMIT ?"
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Which shadow logs are kept when the shadow database is pruned.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RetentionPolicy {
+    /// Keep the logs of all blocks.
+    #[default]
+    Full,
+    /// Keep the logs of the given number of most recent blocks.
+    LastBlocks(u64),
+    /// Keep the logs of all blocks from the given block number onward.
+    FromBlock(u64),
+}
+
+impl RetentionPolicy {
+    /// Returns the first block whose logs are retained once the chain has reached `tip`, or
+    /// `None` if no logs should be pruned.
+    pub fn horizon(&self, tip: u64) -> Option<u64> {
+        match *self {
+            Self::Full => None,
+            Self::LastBlocks(blocks) => Some(tip.saturating_sub(blocks.saturating_sub(1))),
+            Self::FromBlock(block_number) => Some(block_number),
+        }
+        .filter(|horizon| *horizon > 0)
+    }
+}
+
+/// Configuration of the background pruning of the shadow database.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PruneConfig {
+    /// Which logs are kept.
+    pub retention: RetentionPolicy,
+    /// Time between two pruning runs.
+    pub interval: Duration,
+}
+
+impl Default for PruneConfig {
+    fn default() -> Self {
+        Self { retention: RetentionPolicy::Full, interval: Duration::from_secs(60) }
+    }
+}
+
+/// Number of logs deleted by [`ShadowStorage::prune`](crate::ShadowStorage::prune).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PruneOutcome {
+    /// Logs deleted because they were below the pruned horizon.
+    pub logs: u64,
+    /// Removed logs deleted because their block was finalized.
+    pub removed_logs: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetentionPolicy;
+
+    #[test]
+    fn test_horizon() {
+        assert_eq!(RetentionPolicy::Full.horizon(100), None);
+        assert_eq!(RetentionPolicy::LastBlocks(10).horizon(100), Some(91));
+        assert_eq!(RetentionPolicy::LastBlocks(1).horizon(100), Some(100));
+        assert_eq!(RetentionPolicy::LastBlocks(1000).horizon(100), None);
+        assert_eq!(RetentionPolicy::FromBlock(50).horizon(100), Some(50));
+        assert_eq!(RetentionPolicy::FromBlock(0).horizon(100), None);
+    }
+}
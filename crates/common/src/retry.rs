@@ -0,0 +1,136 @@
+use std::{future::Future, time::Duration};
+
+use reth_tracing::tracing::warn;
+
+use crate::ShadowDbError;
+
+/// Exponential backoff policy for retrying shadow database operations.
+///
+/// Only errors for which [`ShadowDbError::is_retryable`] returns `true` are retried. Fatal errors
+/// are returned immediately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_backoff: Duration,
+    /// Maximum number of attempts, including the first one. `None` retries until the operation
+    /// succeeds or fails with a fatal error.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Runs `op` until it succeeds, fails with a fatal error, or `max_attempts` is reached.
+    ///
+    /// The delay between attempts starts at `initial_backoff` and doubles after every failure,
+    /// capped at `max_backoff`.
+    pub async fn retry<T, F, Fut>(&self, name: &str, mut op: F) -> Result<T, ShadowDbError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, ShadowDbError>>,
+    {
+        let mut attempt = 1;
+        let mut backoff = self.initial_backoff;
+        loop {
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(err) if !err.is_retryable() => return Err(err),
+                Err(err) if self.max_attempts.is_some_and(|max| attempt >= max) => return Err(err),
+                Err(err) => {
+                    warn!(
+                        %err,
+                        attempt,
+                        ?backoff,
+                        operation = name,
+                        "Shadow database operation failed, retrying"
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.max_backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    };
+
+    use super::RetryPolicy;
+    use crate::ShadowDbError;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(2),
+        max_attempts: Some(5),
+    };
+
+    #[tokio::test]
+    async fn test_retries_transient_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = POLICY
+            .retry("test", || async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(ShadowDbError::from(sqlx::Error::PoolTimedOut))
+                } else {
+                    Ok(())
+                }
+            })
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_does_not_retry_fatal_errors() {
+        let attempts = AtomicU32::new(0);
+        let result = POLICY
+            .retry("test", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(ShadowDbError::from(sqlx::Error::RowNotFound))
+            })
+            .await;
+
+        assert!(matches!(result, Err(ShadowDbError::Fatal(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_mdbx_errors() {
+        for err in [reth_libmdbx::Error::Busy, reth_libmdbx::Error::MapFull] {
+            assert!(ShadowDbError::from(err).is_retryable());
+        }
+        for err in [reth_libmdbx::Error::Corrupted, reth_libmdbx::Error::TxnFull] {
+            assert!(!ShadowDbError::from(err).is_retryable());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let attempts = AtomicU32::new(0);
+        let result = POLICY
+            .retry("test", || async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(ShadowDbError::from(sqlx::Error::PoolTimedOut))
+            })
+            .await;
+
+        assert!(matches!(result, Err(ShadowDbError::PoolTimedOut)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 5);
+    }
+}
@@ -0,0 +1,232 @@
+//! Storage backends for shadow logs.
+//!
+//! The ExEx writes to, and the RPC reads from, a [`ShadowStorage`]. SQLite is the default backend,
+//! PostgreSQL and MDBX can be selected with [`StorageConfig::Postgres`] and
+//! [`StorageConfig::Mdbx`].
+
+use std::{fmt::Debug, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use reth_primitives::{Address, BlockHash, B256};
+
+use crate::{
+    PruneOutcome, ShadowDbError, ShadowLog, ShadowMdbxDb, ShadowPostgresDb, ShadowSqliteDb,
+    SqliteConfig,
+};
+
+/// A backend storing shadow logs and the progress of the ExEx.
+#[async_trait]
+pub trait ShadowStorage: Debug + Send + Sync {
+    /// Bulk insert a list of [`ShadowLog`] instances.
+    ///
+    /// Logs are keyed by `(block_hash, block_log_index)`, so inserting a log which already exists
+    /// updates the stored log instead of creating a duplicate.
+    async fn bulk_insert_into_shadow_log_table(
+        &self,
+        logs: &[ShadowLog],
+    ) -> Result<(), ShadowDbError> {
+        self.apply_chain_update(&[], &[], logs, None).await
+    }
+
+    /// Marks all logs with the given `block_hash` as removed.
+    ///
+    /// This is used to invalid all logs in a block when a reorg happens.
+    async fn handle_block_reorg(&self, block_hash: BlockHash) -> Result<(), ShadowDbError> {
+        self.apply_chain_update(&[block_hash], &[], &[], None).await
+    }
+
+    /// Applies a chain update from the ExEx atomically.
+    ///
+    /// All logs in the `reverted` blocks are marked as removed. Any logs already stored for the
+    /// `committed` blocks are then replaced by `logs`, so that a block which is re-included after
+    /// a revert is not left with duplicate logs. The `reverted` and `committed` blocks are
+    /// recorded as indexed blocks, see [`ShadowStorage::indexed_block`]. If a `checkpoint` is
+    /// given, it is stored as part of the same update.
+    async fn apply_chain_update(
+        &self,
+        reverted: &[BlockHash],
+        committed: &[IndexedBlock],
+        logs: &[ShadowLog],
+        checkpoint: Option<Checkpoint>,
+    ) -> Result<(), ShadowDbError>;
+
+    /// Returns all logs matching the given filter, ordered by block number and log index.
+    async fn get_logs(&self, filter: &LogFilter) -> Result<Vec<ShadowLog>, ShadowDbError>;
+
+    /// Returns at most `page.limit` logs matching the given filter which come after `page.after`,
+    /// ordered by block number and log index.
+    async fn get_logs_page(
+        &self,
+        filter: &LogFilter,
+        page: &LogPage,
+    ) -> Result<Vec<ShadowLog>, ShadowDbError>;
+
+    /// Returns the last block processed by the ExEx, if any.
+    async fn checkpoint(&self) -> Result<Option<Checkpoint>, ShadowDbError>;
+
+    /// Returns the block with the given hash, if it has been processed by the ExEx and not been
+    /// pruned.
+    async fn indexed_block(
+        &self,
+        block_hash: BlockHash,
+    ) -> Result<Option<IndexedBlock>, ShadowDbError>;
+
+    /// Returns the number of block numbers in `from..=to` with a canonical indexed block, which
+    /// equals the length of the range if every block in it has been processed by the ExEx and not
+    /// been pruned.
+    async fn indexed_block_count(&self, from: u64, to: u64) -> Result<u64, ShadowDbError>;
+
+    /// Deletes all logs in blocks below `horizon`, and all removed logs in blocks up to and
+    /// including `finalized`, which can no longer be re-included.
+    ///
+    /// The pruned horizon only moves forward: a `horizon` at or below the current one is ignored,
+    /// as is a `horizon` of 0, below which there are no blocks.
+    async fn prune(
+        &self,
+        horizon: Option<u64>,
+        finalized: Option<u64>,
+    ) -> Result<PruneOutcome, ShadowDbError>;
+
+    /// Returns the first block whose logs have not been pruned, or `None` if nothing has been
+    /// pruned yet.
+    async fn pruned_horizon(&self) -> Result<Option<u64>, ShadowDbError>;
+}
+
+/// The last block processed by the ExEx.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Number of the block.
+    pub block_number: u64,
+    /// Hash of the block.
+    pub block_hash: BlockHash,
+}
+
+/// A block processed by the ExEx, whether or not it emitted any shadow logs.
+///
+/// Blocks indexed before header metadata was recorded have a zero parent hash, timestamp,
+/// processing time and configuration version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexedBlock {
+    /// Number of the block.
+    pub block_number: u64,
+    /// Hash of the block.
+    pub block_hash: BlockHash,
+    /// Hash of the parent block.
+    pub parent_hash: BlockHash,
+    /// Timestamp of the block.
+    pub block_timestamp: u64,
+    /// Base fee per gas of the block, if it was produced after London.
+    pub base_fee: Option<u64>,
+    /// UNIX timestamp, in seconds, at which the ExEx processed the block.
+    pub processed_at: u64,
+    /// Version of the shadow configuration the block was executed with, which is the keccak-256
+    /// hash of the configuration file.
+    pub config_version: B256,
+    /// Whether the block has been reverted, i.e. is no longer part of the canonical chain.
+    pub removed: bool,
+}
+
+impl IndexedBlock {
+    /// Creates a canonical block with the given number and hash, without header metadata.
+    pub const fn new(block_number: u64, block_hash: BlockHash) -> Self {
+        Self {
+            block_number,
+            block_hash,
+            parent_hash: BlockHash::ZERO,
+            block_timestamp: 0,
+            base_fee: None,
+            processed_at: 0,
+            config_version: B256::ZERO,
+            removed: false,
+        }
+    }
+}
+
+/// Blocks from which logs are selected by a [`LogFilter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockFilter {
+    /// A single block, identified by its hash.
+    Hash(BlockHash),
+    /// An inclusive range of block numbers.
+    Range(u64, u64),
+}
+
+/// A validated filter selecting shadow logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    /// Blocks from which logs are selected.
+    pub block: BlockFilter,
+    /// Addresses from which logs are selected. An empty list matches any address.
+    pub addresses: Vec<Address>,
+    /// Topics which logs must match, by position. A log matches a position if its topic is one of
+    /// the listed alternatives. An empty list matches any topic, including a missing one.
+    pub topics: [Vec<B256>; 4],
+}
+
+impl LogFilter {
+    /// Returns `true` if a log with the given address and topics matches the addresses and topics
+    /// of the filter. The block of the log is not checked.
+    pub fn matches(&self, address: &Address, topics: &[B256]) -> bool {
+        (self.addresses.is_empty() || self.addresses.contains(address)) &&
+            self.topics.iter().enumerate().all(|(position, alternatives)| {
+                alternatives.is_empty() ||
+                    topics.get(position).map_or(false, |topic| alternatives.contains(topic))
+            })
+    }
+}
+
+/// The position of a log in the canonical chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LogPosition {
+    /// Number of the block of the log.
+    pub block_number: u64,
+    /// Index of the log in its block.
+    pub block_log_index: u64,
+}
+
+/// Selects a page of the logs matching a [`LogFilter`], see [`ShadowStorage::get_logs_page`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogPage {
+    /// Only logs after this position are selected.
+    pub after: Option<LogPosition>,
+    /// Maximum number of logs selected.
+    pub limit: usize,
+    /// Whether logs of blocks which were removed from the canonical chain are selected.
+    ///
+    /// Positions are only unique among canonical logs, as a removed block shares its block number
+    /// with the block which replaced it. Pages continuing `after` a position should therefore
+    /// exclude removed logs.
+    pub include_removed: bool,
+}
+
+impl LogPage {
+    /// Returns `true` if a log at `position`, which is `removed` or not, belongs to the page,
+    /// disregarding its limit.
+    pub(crate) fn admits(&self, position: LogPosition, removed: bool) -> bool {
+        (self.include_removed || !removed) && self.after.map_or(true, |after| position > after)
+    }
+}
+
+/// Selects and configures the [`ShadowStorage`] backend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageConfig {
+    /// A SQLite database at the given path, with the given connection settings.
+    Sqlite(PathBuf, SqliteConfig),
+    /// A PostgreSQL database at the given connection URL.
+    Postgres(String),
+    /// An MDBX environment in the given directory.
+    Mdbx(PathBuf),
+}
+
+impl StorageConfig {
+    /// Connects to the configured backend, applying any pending schema migrations.
+    pub async fn connect(&self) -> Result<Arc<dyn ShadowStorage>, ShadowDbError> {
+        Ok(match self {
+            Self::Sqlite(path, config) => {
+                Arc::new(ShadowSqliteDb::with_config(&path.to_string_lossy(), *config).await?)
+            }
+            Self::Postgres(url) => Arc::new(ShadowPostgresDb::new(url).await?),
+            Self::Mdbx(path) => Arc::new(ShadowMdbxDb::new(path)?),
+        })
+    }
+}
@@ -1,3 +1,5 @@
+use reth_primitives::BlockHash;
+
 /// A shadow log entry.
 #[derive(Debug, Clone)]
 pub struct ShadowLog {
@@ -30,3 +32,26 @@ pub struct ShadowLog {
     /// Topic 3.
     pub topic_3: Option<String>,
 }
+
+/// A block indexed by the ExEx, as announced to the RPC once its shadow logs have been stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexedBlockEvent {
+    /// The block was added to the canonical chain.
+    Committed(BlockHash),
+    /// The block was removed from the canonical chain, and its logs marked as removed.
+    Reverted(BlockHash),
+}
+
+impl IndexedBlockEvent {
+    /// Returns the hash of the block.
+    pub const fn block_hash(&self) -> BlockHash {
+        match self {
+            Self::Committed(block_hash) | Self::Reverted(block_hash) => *block_hash,
+        }
+    }
+
+    /// Returns `true` if the block was removed from the canonical chain.
+    pub const fn is_reverted(&self) -> bool {
+        matches!(self, Self::Reverted(_))
+    }
+}
@@ -0,0 +1,491 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use eyre::Result;
+use reth_evm_ethereum::EthEvmConfig;
+use reth_node_api::{ConfigureEvm, ConfigureEvmEnv};
+use reth_primitives::{
+    Address, BlockWithSenders, Bytes, ChainSpec, Header, Receipt, B256, U256, U64,
+};
+use reth_provider::StateProvider;
+use reth_revm::{
+    db::{CacheDB, DatabaseRef},
+    primitives::{Bytecode, CfgEnvWithHandlerCfg, EVMError, ExecutionResult, TransactTo},
+    Database, Evm,
+};
+use serde::{Deserialize, Serialize};
+use shadow_reth_common::{ShadowCallError, ShadowLog, ToLowerHex};
+
+use crate::{
+    contracts::ShadowContracts, db::ShadowDatabase, execution::ShadowExecutor, ShadowExEx,
+};
+
+/// A call executed by [`ShadowCaller`], in the format of the `eth_call` transaction object.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallRequest {
+    /// Sender of the call, which defaults to the zero address.
+    pub from: Option<Address>,
+    /// Contract which is called.
+    pub to: Option<Address>,
+    /// Gas limit of the call, which defaults to the gas limit of the block.
+    pub gas: Option<U64>,
+    /// Gas price of the call. Calls without a gas price do not pay the base fee.
+    pub gas_price: Option<U256>,
+    /// Value sent with the call.
+    pub value: Option<U256>,
+    /// Calldata of the call.
+    pub input: Option<Bytes>,
+    /// Calldata of the call, used if `input` is not set.
+    pub data: Option<Bytes>,
+}
+
+/// Overrides of the state of an account for the duration of a call, in the format of `eth_call`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountOverride {
+    /// Balance of the account.
+    pub balance: Option<U256>,
+    /// Nonce of the account.
+    pub nonce: Option<U64>,
+    /// Code of the account, which takes precedence over its shadow bytecode.
+    pub code: Option<Bytes>,
+    /// Storage of the account, replacing all of its slots.
+    pub state: Option<HashMap<B256, B256>>,
+    /// Storage slots of the account which are overridden, keeping the other slots.
+    pub state_diff: Option<HashMap<B256, B256>>,
+}
+
+/// Overrides of the state of accounts for the duration of a call, keyed by their address.
+pub type StateOverride = HashMap<Address, AccountOverride>;
+
+/// The result of replaying a range of blocks with candidate shadow bytecode, as returned by
+/// [`ShadowCaller::simulate`].
+#[derive(Debug, Clone, Default)]
+pub struct Simulation {
+    /// Logs emitted by shadowed contracts, as they would be indexed.
+    pub logs: Vec<ShadowLog>,
+    /// Transactions whose status or gas used differs from their canonical execution.
+    pub transactions: Vec<SimulatedTransaction>,
+}
+
+/// A transaction whose replay with the candidate shadow bytecode differs from its canonical
+/// execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulatedTransaction {
+    /// Number of the block of the transaction.
+    pub block_number: U64,
+    /// Index of the transaction in its block.
+    pub transaction_index: U64,
+    /// Hash of the transaction.
+    pub transaction_hash: B256,
+    /// Outcome of the canonical execution, from the receipt of the transaction.
+    pub canonical: TransactionOutcome,
+    /// Outcome of the execution with the shadow bytecode.
+    pub shadow: TransactionOutcome,
+    /// Gas used by the shadow execution minus the gas used by the canonical execution.
+    pub gas_diff: i64,
+}
+
+/// The outcome of the execution of a transaction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionOutcome {
+    /// Whether the transaction succeeded.
+    pub status: TransactionStatus,
+    /// Gas used by the transaction, after refunds.
+    pub gas_used: U64,
+    /// Revert data of a reverted shadow execution, or the reason of a halted one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Status of the execution of a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TransactionStatus {
+    /// The transaction succeeded.
+    Success,
+    /// The transaction reverted. Canonical transactions which failed are always reported as
+    /// reverted, as their receipts do not tell reverts and exceptional halts apart.
+    Reverted,
+    /// The transaction halted exceptionally, e.g. because it ran out of gas.
+    Halted,
+    /// The transaction was not executed, as it is invalid on top of the shadow state.
+    Skipped,
+}
+
+impl From<&ExecutionResult> for TransactionOutcome {
+    fn from(result: &ExecutionResult) -> Self {
+        let gas_used = U64::from(result.gas_used());
+        match result {
+            ExecutionResult::Success { .. } => {
+                Self { status: TransactionStatus::Success, gas_used, output: None }
+            }
+            ExecutionResult::Revert { output, .. } => Self {
+                status: TransactionStatus::Reverted,
+                gas_used,
+                output: Some(output.to_lower_hex()),
+            },
+            ExecutionResult::Halt { reason, .. } => Self {
+                status: TransactionStatus::Halted,
+                gas_used,
+                output: Some(format!("{reason:?}")),
+            },
+        }
+    }
+}
+
+/// Executes calls against historical state, with the contracts in `shadow.json` replaced by their
+/// shadow bytecode, as for `shadow_call` and `shadow_estimateGas`.
+#[derive(Debug, Clone)]
+pub struct ShadowCaller {
+    contracts: ShadowContracts,
+    evm_config: EthEvmConfig,
+}
+
+impl ShadowCaller {
+    /// Creates a caller with the shadow contracts configured by `shadow.json` in the current
+    /// working directory.
+    pub fn from_config() -> Result<Self> {
+        let (_, contracts) = ShadowExEx::load_config()?;
+        Ok(Self { contracts, evm_config: EthEvmConfig::default() })
+    }
+
+    /// Executes `request` on top of `state`, the state after the block with the given `header`,
+    /// and returns its output.
+    pub fn call<DB: StateProvider>(
+        &self,
+        state: DB,
+        chain: &ChainSpec,
+        header: &Header,
+        request: &CallRequest,
+        overrides: &StateOverride,
+    ) -> Result<Bytes, ShadowCallError> {
+        let gas_limit = request.gas.map_or(header.gas_limit, |gas| gas.to());
+        let mut evm = self.evm(state, chain, header, request, overrides)?;
+        match transact(&mut evm, gas_limit)? {
+            ExecutionResult::Success { output, .. } => Ok(output.into_data()),
+            result => Err(failure(result)),
+        }
+    }
+
+    /// Returns the lowest gas limit with which `request` succeeds on top of `state`, the state
+    /// after the block with the given `header`. The gas limit of the request, or else of the
+    /// block, is the upper bound of the estimate.
+    pub fn estimate_gas<DB: StateProvider>(
+        &self,
+        state: DB,
+        chain: &ChainSpec,
+        header: &Header,
+        request: &CallRequest,
+        overrides: &StateOverride,
+    ) -> Result<u64, ShadowCallError> {
+        let cap = request.gas.map_or(header.gas_limit, |gas| gas.to());
+        let mut evm = self.evm(state, chain, header, request, overrides)?;
+        let gas_used = match transact(&mut evm, cap)? {
+            ExecutionResult::Success { gas_used, .. } => gas_used,
+            result => return Err(failure(result)),
+        };
+
+        // Refunds are deducted from the gas used, so the call runs out of gas with less than
+        // that. Search for the lowest gas limit with which it succeeds, between a failing lower
+        // and a succeeding upper bound.
+        let (mut low, mut high) = (gas_used.saturating_sub(1), cap);
+        while low + 1 < high {
+            let mid = low + (high - low) / 2;
+            if transact(&mut evm, mid)?.is_success() {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        Ok(high)
+    }
+
+    /// Replays `blocks` on top of `state`, the state before the first of them, with `candidates`
+    /// added to the shadow contracts, and returns the shadow logs and the transactions whose
+    /// outcome differs from their canonical `receipts`. Nothing is persisted.
+    pub fn simulate<DB: StateProvider>(
+        &self,
+        state: DB,
+        chain: Arc<ChainSpec>,
+        blocks: Vec<(BlockWithSenders, Vec<Receipt>)>,
+        candidates: &HashMap<Address, Bytes>,
+    ) -> Result<Simulation, ShadowCallError> {
+        let contracts = self.contracts.with_overrides(candidates);
+        let db = ShadowDatabase::new(state, contracts.clone());
+        let mut executor = ShadowExecutor::new(&self.evm_config, db, chain);
+
+        let mut simulation = Simulation::default();
+        for (block, receipts) in blocks {
+            let executed =
+                executor.execute_one(block).map_err(|e| ShadowCallError::State(e.to_string()))?;
+            simulation.logs.extend(executed.logs().into_iter().filter(|log| {
+                contracts.is_shadowed(&log.address.parse().expect("failed to parse log address"))
+            }));
+
+            let block = executed.block();
+            let mut results = executed.results().iter().peekable();
+            let mut cumulative_gas_used = 0;
+            for (index, (transaction, receipt)) in block.body.iter().zip(&receipts).enumerate() {
+                let canonical = TransactionOutcome {
+                    status: if receipt.success {
+                        TransactionStatus::Success
+                    } else {
+                        TransactionStatus::Reverted
+                    },
+                    gas_used: U64::from(receipt.cumulative_gas_used - cumulative_gas_used),
+                    output: None,
+                };
+                cumulative_gas_used = receipt.cumulative_gas_used;
+
+                let shadow = match results.next_if(|(i, ..)| *i == index as u64) {
+                    Some((_, _, result)) => TransactionOutcome::from(result),
+                    None => TransactionOutcome {
+                        status: TransactionStatus::Skipped,
+                        gas_used: U64::ZERO,
+                        output: None,
+                    },
+                };
+                if shadow.status == canonical.status && shadow.gas_used == canonical.gas_used {
+                    continue
+                }
+
+                let gas_diff = shadow.gas_used.to::<i64>() - canonical.gas_used.to::<i64>();
+                simulation.transactions.push(SimulatedTransaction {
+                    block_number: U64::from(block.number),
+                    transaction_index: U64::from(index),
+                    transaction_hash: transaction.hash,
+                    canonical,
+                    shadow,
+                    gas_diff,
+                });
+            }
+        }
+        Ok(simulation)
+    }
+
+    /// Configures an EVM executing `request` on top of `state`, with `overrides` applied.
+    fn evm<DB: StateProvider>(
+        &self,
+        state: DB,
+        chain: &ChainSpec,
+        header: &Header,
+        request: &CallRequest,
+        overrides: &StateOverride,
+    ) -> Result<Evm<'_, (), CacheDB<ShadowDatabase<DB>>>, ShadowCallError> {
+        let to = request
+            .to
+            .ok_or_else(|| ShadowCallError::InvalidCall("missing `to` address".to_string()))?;
+
+        let mut db = CacheDB::new(ShadowDatabase::new(state, self.contracts.clone()));
+        apply_overrides(&mut db, overrides)?;
+
+        let mut evm = self.evm_config.evm(db);
+        let mut cfg = CfgEnvWithHandlerCfg::new_with_spec_id(evm.cfg().clone(), evm.spec_id());
+        EthEvmConfig::fill_cfg_and_block_env(&mut cfg, evm.block_mut(), chain, header, U256::ZERO);
+        *evm.cfg_mut() = cfg.cfg_env;
+
+        // As with `eth_call`, calls without a gas price do not pay the base fee.
+        if request.gas_price.is_none() {
+            evm.block_mut().basefee = U256::ZERO;
+        }
+
+        let tx = evm.tx_mut();
+        tx.caller = request.from.unwrap_or_default();
+        tx.transact_to = TransactTo::Call(to);
+        tx.value = request.value.unwrap_or_default();
+        tx.data = request.input.clone().or_else(|| request.data.clone()).unwrap_or_default();
+        tx.gas_price = request.gas_price.unwrap_or_default();
+        tx.nonce = None;
+
+        Ok(evm)
+    }
+}
+
+/// Executes the transaction of `evm` with the given gas limit, without committing its state.
+///
+/// The transaction is not verified against the state, as the shadow bytecode may not agree with
+/// the canonical state, see [`ShadowExecutor`](crate::execution::ShadowExecutor).
+fn transact<DB: Database>(
+    evm: &mut Evm<'_, (), DB>,
+    gas_limit: u64,
+) -> Result<ExecutionResult, ShadowCallError>
+where
+    DB::Error: Display,
+{
+    evm.tx_mut().gas_limit = gas_limit;
+    evm.transact_preverified().map(|result| result.result).map_err(|err| match err {
+        EVMError::Transaction(err) => ShadowCallError::InvalidCall(err.to_string()),
+        err => ShadowCallError::State(err.to_string()),
+    })
+}
+
+/// Returns the error of a call which did not succeed.
+fn failure(result: ExecutionResult) -> ShadowCallError {
+    match result {
+        ExecutionResult::Revert { output, .. } => ShadowCallError::Reverted(output),
+        ExecutionResult::Halt { reason, .. } => ShadowCallError::Halted(format!("{reason:?}")),
+        ExecutionResult::Success { .. } => unreachable!("call succeeded"),
+    }
+}
+
+/// Applies `overrides` to the accounts cached by `db`.
+fn apply_overrides<DB: DatabaseRef>(
+    db: &mut CacheDB<DB>,
+    overrides: &StateOverride,
+) -> Result<(), ShadowCallError>
+where
+    DB::Error: Display,
+{
+    let state_error = |err: DB::Error| ShadowCallError::State(err.to_string());
+    let slot = |slot: &B256| U256::from_be_bytes(slot.0);
+
+    for (address, account_override) in overrides {
+        let mut info = db.basic(*address).map_err(state_error)?.unwrap_or_default();
+        if let Some(balance) = account_override.balance {
+            info.balance = balance;
+        }
+        if let Some(nonce) = account_override.nonce {
+            info.nonce = nonce.to();
+        }
+        if let Some(code) = &account_override.code {
+            let code = Bytecode::new_raw(code.clone());
+            info.code_hash = code.hash_slow();
+            info.code = Some(code);
+        }
+        db.insert_account_info(*address, info);
+
+        match (&account_override.state, &account_override.state_diff) {
+            (Some(_), Some(_)) => {
+                return Err(ShadowCallError::InvalidCall(format!(
+                    "both state and stateDiff are overridden for {address}"
+                )))
+            }
+            (Some(state), None) => {
+                let storage = state.iter().map(|(key, value)| (slot(key), slot(value))).collect();
+                db.replace_account_storage(*address, storage).map_err(state_error)?;
+            }
+            (None, Some(state_diff)) => {
+                for (key, value) in state_diff {
+                    db.insert_account_storage(*address, slot(key), slot(value))
+                        .map_err(state_error)?;
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reth_primitives::{
+        Address, Block, BlockWithSenders, Bytes, Header, Receipt, TransactionSigned, MAINNET, U256,
+        U64,
+    };
+    use reth_provider::test_utils::{ExtendedAccount, MockEthProvider};
+    use serde_json::Value;
+    use shadow_reth_common::ShadowCallError;
+
+    use super::{
+        AccountOverride, CallRequest, ShadowCaller, StateOverride, TransactionOutcome,
+        TransactionStatus,
+    };
+    use crate::contracts::ShadowContracts;
+
+    /// Returns 42.
+    const RETURN_42: &str = "0x602a60005260206000f3";
+    /// Reverts without data.
+    const REVERT: &str = "0x60006000fd";
+
+    fn caller(shadowed: Address) -> ShadowCaller {
+        let config =
+            Value::Object([(shadowed.to_string(), RETURN_42.into())].into_iter().collect());
+        let contracts = ShadowContracts::try_from(config).unwrap();
+        ShadowCaller { contracts, evm_config: Default::default() }
+    }
+
+    #[test]
+    fn test_call_with_shadow_bytecode_and_overrides() {
+        let shadowed = Address::repeat_byte(1);
+        let other = Address::repeat_byte(2);
+        let state = MockEthProvider::default();
+        state.add_account(shadowed, ExtendedAccount::new(0, U256::ZERO));
+        let header = Header { number: 15_000_000, gas_limit: 30_000_000, ..Default::default() };
+        let caller = caller(shadowed);
+
+        let request = CallRequest { to: Some(shadowed), ..Default::default() };
+        let output = caller.call(state.clone(), &MAINNET, &header, &request, &Default::default());
+        assert_eq!(output.unwrap(), Bytes::from(U256::from(42).to_be_bytes_vec()));
+
+        let overrides = StateOverride::from([(
+            other,
+            AccountOverride { code: Some(REVERT.parse().unwrap()), ..Default::default() },
+        )]);
+        let request = CallRequest { to: Some(other), ..Default::default() };
+        let err = caller.call(state.clone(), &MAINNET, &header, &request, &overrides).unwrap_err();
+        assert!(matches!(err, ShadowCallError::Reverted(output) if output.is_empty()));
+
+        // The estimate is the lowest gas limit with which the call succeeds.
+        let request = CallRequest { to: Some(shadowed), ..Default::default() };
+        let estimate = caller
+            .estimate_gas(state.clone(), &MAINNET, &header, &request, &Default::default())
+            .unwrap();
+        let request = CallRequest { gas: Some(U64::from(estimate)), ..request };
+        assert!(caller
+            .call(state.clone(), &MAINNET, &header, &request, &Default::default())
+            .is_ok());
+        let request = CallRequest { gas: Some(U64::from(estimate - 1)), ..request };
+        let err = caller.call(state, &MAINNET, &header, &request, &Default::default()).unwrap_err();
+        assert!(matches!(err, ShadowCallError::Halted(_)));
+    }
+
+    #[test]
+    fn test_simulate_reports_diverging_transactions() {
+        let shadowed = Address::repeat_byte(1);
+        let state = MockEthProvider::default();
+        let caller = caller(shadowed);
+
+        // Transactions whose sender cannot be recovered are skipped by the shadow execution, so
+        // their canonical gas is reported as a diff.
+        let header = Header { number: 15_000_000, gas_limit: 30_000_000, ..Default::default() };
+        let block = Block {
+            header,
+            body: vec![TransactionSigned::default(), TransactionSigned::default()],
+            ..Default::default()
+        };
+        let block = BlockWithSenders { block, senders: vec![Address::ZERO, Address::ZERO] };
+        let receipts = vec![
+            Receipt { success: true, cumulative_gas_used: 21_000, ..Default::default() },
+            Receipt { success: false, cumulative_gas_used: 50_000, ..Default::default() },
+        ];
+        let candidates = [(shadowed, Bytes::from_static(&[0x00]))].into();
+
+        let simulation =
+            caller.simulate(state, MAINNET.clone(), vec![(block, receipts)], &candidates).unwrap();
+        assert!(simulation.logs.is_empty());
+        let diffs = simulation
+            .transactions
+            .iter()
+            .map(|tx| (tx.transaction_index.to::<u64>(), tx.canonical.clone(), tx.gas_diff))
+            .collect::<Vec<_>>();
+        let canonical = |status, gas_used: u64| TransactionOutcome {
+            status,
+            gas_used: U64::from(gas_used),
+            output: None,
+        };
+        assert_eq!(
+            diffs,
+            vec![
+                (0, canonical(TransactionStatus::Success, 21_000), -21_000),
+                (1, canonical(TransactionStatus::Reverted, 29_000), -29_000),
+            ]
+        );
+        assert!(simulation
+            .transactions
+            .iter()
+            .all(|tx| tx.shadow.status == TransactionStatus::Skipped));
+    }
+}
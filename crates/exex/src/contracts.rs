@@ -50,6 +50,11 @@ impl ShadowContracts {
         self.contracts.len()
     }
 
+    /// Returns the addresses of the shadow contracts.
+    pub(crate) fn addresses(&self) -> Vec<Address> {
+        self.contracts.keys().copied().collect()
+    }
+
     /// Returns true if the given address is a shadow contract.
     pub(crate) fn is_shadowed(&self, address: &Address) -> bool {
         self.contracts.contains_key(address)
@@ -60,6 +65,21 @@ impl ShadowContracts {
         self.contracts.get(address).cloned()
     }
 
+    /// Returns these shadow contracts with the given candidate bytecode added, replacing the
+    /// shadow bytecode of addresses which are already shadowed.
+    pub(crate) fn with_overrides<'a>(
+        &self,
+        overrides: impl IntoIterator<Item = (&'a Address, &'a Bytes)>,
+    ) -> Self {
+        let mut contracts = self.clone();
+        for (address, bytecode) in overrides {
+            let bytecode = Bytecode::new_raw(bytecode.clone());
+            contracts.code_hashes.insert(*address, bytecode.hash_slow());
+            contracts.contracts.insert(*address, bytecode);
+        }
+        contracts
+    }
+
     /// Get the code hash for a shadow contract at a given address.
     pub(crate) fn code_hash(&self, address: &Address) -> Option<B256> {
         self.code_hashes.get(address).copied()
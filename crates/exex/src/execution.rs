@@ -9,9 +9,7 @@ use reth_primitives::{
 use reth_provider::StateProvider;
 use reth_revm::{
     db::{states::bundle_state::BundleRetention, State},
-    primitives::{
-        CfgEnvWithHandlerCfg, EVMError, ExecutionResult, HashMap, ResultAndState, B256, U256,
-    },
+    primitives::{CfgEnvWithHandlerCfg, EVMError, ExecutionResult, ResultAndState, B256, U256},
     DatabaseCommit, Evm, StateBuilder,
 };
 use reth_tracing::tracing::{debug, error};
@@ -24,6 +22,7 @@ use crate::db::ShadowDatabase;
 #[derive(Debug)]
 pub(crate) struct ShadowExecutor<'a, DB: StateProvider> {
     evm: Evm<'a, (), State<ShadowDatabase<DB>>>,
+    chain: Arc<ChainSpec>,
 }
 
 /// Holds the result of a block execution, as well as important
@@ -32,41 +31,51 @@ pub(crate) struct ShadowExecutor<'a, DB: StateProvider> {
 pub(crate) struct ExecutedBlock {
     block: Block,
     canonical_block_hash: B256,
-    results: HashMap<TransactionSigned, ExecutionResult>,
+    /// Execution results, in block order, along with the index of each transaction in the block.
+    results: Vec<(u64, TransactionSigned, ExecutionResult)>,
 }
 
 impl ExecutedBlock {
+    /// Returns the executed block.
+    pub(crate) const fn block(&self) -> &Block {
+        &self.block
+    }
+
+    /// Returns the execution results, in block order, along with the index of each transaction in
+    /// the block. Transactions which could not be executed are skipped.
+    pub(crate) fn results(&self) -> &[(u64, TransactionSigned, ExecutionResult)] {
+        &self.results
+    }
+
     /// Returns [`ShadowLog`]s from the executed block.
+    ///
+    /// Logs are returned in execution order, so the `block_log_index` of a log is stable when
+    /// the same block is executed again. Log indices start at 1, as in existing databases.
     pub(crate) fn logs(&self) -> Vec<ShadowLog> {
+        let mut logs = Vec::new();
         let mut block_log_index = 0;
-        self.results
-            .clone()
-            .into_iter()
-            .enumerate()
-            .flat_map(|(transaction_index, (transaction, result))| {
-                result.into_logs().into_iter().enumerate().map(
-                    move |(transaction_log_index, log)| {
-                        block_log_index += 1;
-                        ShadowLog {
-                            address: log.address.to_lower_hex(),
-                            block_hash: self.canonical_block_hash.to_lower_hex(),
-                            block_log_index,
-                            block_number: self.block.number,
-                            block_timestamp: self.block.timestamp,
-                            transaction_index: transaction_index as u64,
-                            transaction_hash: transaction.hash.to_lower_hex(),
-                            transaction_log_index: transaction_log_index as u64,
-                            removed: false,
-                            data: Some(log.data.data.to_lower_hex()),
-                            topic_0: log.topics().first().map(|t| t.to_lower_hex()),
-                            topic_1: log.topics().get(1).map(|t| t.to_lower_hex()),
-                            topic_2: log.topics().get(2).map(|t| t.to_lower_hex()),
-                            topic_3: log.topics().get(3).map(|t| t.to_lower_hex()),
-                        }
-                    },
-                )
-            })
-            .collect()
+        for (transaction_index, transaction, result) in &self.results {
+            for (transaction_log_index, log) in result.clone().into_logs().into_iter().enumerate() {
+                block_log_index += 1;
+                logs.push(ShadowLog {
+                    address: log.address.to_lower_hex(),
+                    block_hash: self.canonical_block_hash.to_lower_hex(),
+                    block_log_index,
+                    block_number: self.block.number,
+                    block_timestamp: self.block.timestamp,
+                    transaction_index: *transaction_index,
+                    transaction_hash: transaction.hash.to_lower_hex(),
+                    transaction_log_index: transaction_log_index as u64,
+                    removed: false,
+                    data: Some(log.data.data.to_lower_hex()),
+                    topic_0: log.topics().first().map(|t| t.to_lower_hex()),
+                    topic_1: log.topics().get(1).map(|t| t.to_lower_hex()),
+                    topic_2: log.topics().get(2).map(|t| t.to_lower_hex()),
+                    topic_3: log.topics().get(3).map(|t| t.to_lower_hex()),
+                });
+            }
+        }
+        logs
     }
 }
 
@@ -76,25 +85,26 @@ impl<'a, DB: StateProvider> ShadowExecutor<'a, DB> {
         config: &'a EthEvmConfig,
         db: ShadowDatabase<DB>,
         chain: Arc<ChainSpec>,
-        header: &Header,
     ) -> Self {
-        let evm = configure_evm(config, db, chain, header);
-        Self { evm }
+        let evm = config.evm(StateBuilder::new_with_database(db).with_bundle_update().build());
+        Self { evm, chain }
     }
 
-    #[allow(clippy::mutable_key_type)]
     /// Executes a single block (without verifying them) and returns their [`ExecutionResult`]s
     /// within a [`ExecutedBlock`].
     pub(crate) fn execute_one(&mut self, block: BlockWithSenders) -> Result<ExecutedBlock> {
+        // Blocks are executed on top of each other, so the environment is set for every block.
+        configure_env(&mut self.evm, &self.chain, &block.block.header);
+
         // Calculate the canonical block hash, before making state-changing operations.
         let canonical_block_hash = block.block.hash_slow();
 
         // Extract the transactions from the block.
         let transactions = block.clone().into_transactions();
-        let mut results = HashMap::with_capacity(transactions.len());
+        let mut results = Vec::with_capacity(transactions.len());
 
         if !transactions.is_empty() {
-            for transaction in transactions {
+            for (transaction_index, transaction) in transactions.into_iter().enumerate() {
                 // Recover the sender of the transaction.
                 let sender = match transaction.recover_signer() {
                     Some(sender) => sender,
@@ -124,7 +134,7 @@ impl<'a, DB: StateProvider> ShadowExecutor<'a, DB> {
                 // Commit the state changes to the shadowed database, and store the result of the
                 // transaction.
                 self.evm.db_mut().commit(state);
-                results.insert(transaction, result);
+                results.push((transaction_index as u64, transaction, result));
             }
 
             // Merge the transitions into the shadowed database.
@@ -135,17 +145,13 @@ impl<'a, DB: StateProvider> ShadowExecutor<'a, DB> {
     }
 }
 
-/// Configure EVM with the given database and header.
-fn configure_evm<'a, DB: StateProvider>(
-    config: &'a EthEvmConfig,
-    db: ShadowDatabase<DB>,
-    chain: Arc<ChainSpec>,
+/// Configure the EVM environment for executing the block with the given header.
+fn configure_env<DB: StateProvider>(
+    evm: &mut Evm<'_, (), State<ShadowDatabase<DB>>>,
+    chain: &ChainSpec,
     header: &Header,
-) -> Evm<'a, (), State<ShadowDatabase<DB>>> {
-    let mut evm = config.evm(StateBuilder::new_with_database(db).with_bundle_update().build());
+) {
     let mut cfg = CfgEnvWithHandlerCfg::new_with_spec_id(evm.cfg().clone(), evm.spec_id());
-    EthEvmConfig::fill_cfg_and_block_env(&mut cfg, evm.block_mut(), &chain, header, U256::ZERO);
+    EthEvmConfig::fill_cfg_and_block_env(&mut cfg, evm.block_mut(), chain, header, U256::ZERO);
     *evm.cfg_mut() = cfg.cfg_env;
-
-    evm
 }
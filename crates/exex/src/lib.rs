@@ -3,26 +3,45 @@
 
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 
+mod call;
 mod contracts;
 mod db;
 mod execution;
+mod pruner;
+mod sinks;
 
-use std::path::PathBuf;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use contracts::ShadowContracts;
 use execution::ShadowExecutor;
-use eyre::{eyre, OptionExt, Result};
+use eyre::{eyre, Result};
 use futures::Future;
 use reth_evm_ethereum::EthEvmConfig;
-use reth_exex::{ExExContext, ExExEvent, ExExNotification};
+use reth_exex::{ExExContext, ExExEvent};
 use reth_node_api::FullNodeComponents;
-use reth_provider::{DatabaseProviderFactory, HistoricalStateProviderRef};
+use reth_primitives::{keccak256, Address, B256};
+use reth_provider::{Chain, DatabaseProviderFactory, HistoricalStateProviderRef};
 use reth_tracing::tracing::{debug, info};
 use serde_json::Value;
-use shadow_reth_common::{ShadowSqliteDb, ToLowerHex};
+use shadow_reth_common::{
+    BlockFilter, Checkpoint, IndexedBlock, IndexedBlockEvent, LogFilter, PruneConfig, RetryPolicy,
+    ShadowLog, ShadowStorage,
+};
 use tokio::sync::broadcast::Sender;
 
-use crate::db::ShadowDatabase;
+use crate::{db::ShadowDatabase, pruner::ShadowPruner, sinks::log_events};
+
+pub use call::{
+    AccountOverride, CallRequest, ShadowCaller, SimulatedTransaction, Simulation, StateOverride,
+    TransactionOutcome, TransactionStatus,
+};
+pub use sinks::{
+    Delivery, KafkaProducer, KafkaSink, NdjsonSink, RsKafkaProducer, ShadowSink, ShadowSinks,
+    SinkConfig, SinkEvent, SinkEventKind, SinkFilter, SinkKind, WebhookSink,
+};
 
 #[derive(Debug)]
 /// The main ExEx struct, which handles loading and parsing shadow configuration,
@@ -30,155 +49,241 @@ use crate::db::ShadowDatabase;
 pub struct ShadowExEx {
     /// Stores the shadow contracts, a map of addresses to shadow (overridden) bytecode.
     contracts: ShadowContracts,
-    /// The [`ShadowSqliteDb`] for the shadow database.
-    sqlite_db: ShadowSqliteDb,
-
-    indexed_block_hash_sender: Sender<String>,
+    /// Version of the shadow configuration, the keccak-256 hash of `shadow.json`, which is
+    /// recorded with every indexed block.
+    config_version: B256,
+    /// The storage backend of the shadow database.
+    storage: Arc<dyn ShadowStorage>,
+    /// Backoff policy for retrying failed writes to the shadow database.
+    retry_policy: RetryPolicy,
+    /// Sends the committed and reverted blocks to the RPC once they have been indexed.
+    indexed_block_sender: Sender<IndexedBlockEvent>,
+    /// Sinks which receive the shadow logs of every committed and reverted block.
+    sinks: ShadowSinks,
 }
 
 impl ShadowExEx {
     /// Creates a new instance of the ShadowExEx. This will attempt to load
     /// the configuration from `shadow.json` in the current working directory.
-    pub async fn new(db_path: PathBuf, indexed_block_hash_sender: Sender<String>) -> Result<Self> {
+    pub fn new(
+        storage: Arc<dyn ShadowStorage>,
+        indexed_block_sender: Sender<IndexedBlockEvent>,
+        sinks: ShadowSinks,
+    ) -> Result<Self> {
+        let (raw_config, contracts) = Self::load_config()?;
+
+        Ok(Self {
+            contracts,
+            config_version: keccak256(raw_config),
+            storage,
+            retry_policy: RetryPolicy::default(),
+            indexed_block_sender,
+            sinks,
+        })
+    }
+
+    /// Returns the addresses of the contracts shadowed by `shadow.json` in the current working
+    /// directory.
+    pub fn shadowed_addresses() -> Result<Vec<Address>> {
+        Ok(Self::load_config()?.1.addresses())
+    }
+
+    /// Reads `shadow.json` from the current working directory, returning its raw contents and
+    /// the shadow contracts it configures.
+    fn load_config() -> Result<(String, ShadowContracts)> {
         // read config from `./shadow.json` as a serde_json::Value
-        let config: Value =
-            serde_json::from_str(&std::fs::read_to_string("shadow.json").map_err(|e| {
-                eyre!("failed to locate `shadow.json` in the current working directory: {}", e)
-            })?)
+        let raw_config = std::fs::read_to_string("shadow.json").map_err(|e| {
+            eyre!("failed to locate `shadow.json` in the current working directory: {}", e)
+        })?;
+        let config: Value = serde_json::from_str(&raw_config)
             .map_err(|e| eyre!("failed to parse `shadow.json`: {}", e))?;
 
         // parse shadow contracts from the config
         let contracts = ShadowContracts::try_from(config)?;
 
-        // get the path to the shadow database
-        let shadow_db_path = db_path.join("shadow.db");
-        debug!("Path to shadow database: {}", shadow_db_path.display());
-
-        // create a new ShadowSqliteDb for the shadow database
-        let sqlite_db = ShadowSqliteDb::new(
-            shadow_db_path.to_str().expect("Failed to convert shadow_db_path to string"),
-        )
-        .await?;
-
-        Ok(Self { contracts, sqlite_db, indexed_block_hash_sender })
+        Ok((raw_config, contracts))
     }
 
     /// The initialization logic of the ExEx is just an async function.
+    ///
+    /// Also spawns a background task which prunes the shadow database according to `prune_config`,
+    /// and replays blocks which have not been delivered to `sinks` yet.
     pub async fn init<Node: FullNodeComponents>(
         ctx: ExExContext<Node>,
-        indexed_block_hash_sender: Sender<String>,
+        storage: Arc<dyn ShadowStorage>,
+        indexed_block_sender: Sender<IndexedBlockEvent>,
+        prune_config: PruneConfig,
+        sinks: ShadowSinks,
     ) -> Result<impl Future<Output = Result<()>>> {
-        let db_path = ctx.data_dir.db();
-        let this = Self::new(db_path, indexed_block_hash_sender).await?;
+        let this = Self::new(storage, indexed_block_sender, sinks)?;
 
         info!("Initialized ShadowExEx with {} shadowed contracts", this.contracts.len());
+        if let Some(checkpoint) = this.storage.checkpoint().await? {
+            info!(
+                block_number = checkpoint.block_number,
+                block_hash = %checkpoint.block_hash,
+                "Last block processed by ShadowExEx"
+            );
+        }
+
+        this.sinks.catch_up(this.storage.as_ref()).await?;
+
+        let pruner = ShadowPruner::new(ctx.provider().clone(), this.storage.clone(), prune_config);
+        let pruner = tokio::spawn(pruner.run());
 
         Ok(async move {
-            this.exex(ctx).await?;
-            Ok(())
+            let result = this.exex(ctx).await;
+            pruner.abort();
+            result
         })
     }
 
     /// The exex
     async fn exex<Node: FullNodeComponents>(&self, mut ctx: ExExContext<Node>) -> Result<()> {
         while let Some(notification) = ctx.notifications.recv().await {
-            match notification {
-                ExExNotification::ChainCommitted { new: chain } => {
-                    // Create a read-only database provider that we can use to get historical state
-                    // at the start of the notification chain. i.e. the state at the first block in
-                    // the notification, pre-execution.
-                    let database_provider = ctx.provider().database_provider_ro()?;
-                    let provider = HistoricalStateProviderRef::new(
-                        database_provider.tx_ref(),
-                        chain.first().number,
-                        database_provider.static_file_provider().clone(),
-                    );
-
-                    // Use the database provider to create a [`ShadowDatabase`]. This is a
-                    // [`reth_revm::Database`] implementation that will override the
-                    // bytecode of contracts at specific addresses with custom shadow bytecode, as
-                    // defined in `shadow.json`.
-                    let db = ShadowDatabase::new(provider, self.contracts.clone());
-
-                    let blocks = chain.blocks_iter().collect::<Vec<_>>();
-
-                    // Construct a new `ShadowExecutor` with the default config and proper chain
-                    // spec, using the `ShadowDatabase` as the state provider.
-                    let evm_config = EthEvmConfig::default();
-                    let mut executor = ShadowExecutor::new(
-                        &evm_config,
-                        db,
-                        ctx.config.chain.clone(),
-                        blocks
-                            .first()
-                            .map(|b| b.header())
-                            .ok_or_eyre("No blocks found in ExEx notification")?,
-                    );
-
-                    // Execute the blocks in the chain, collecting logs from shadowed contracts.
-                    let shadow_logs = blocks
-                        .into_iter()
-                        .map(|block| executor.execute_one(block.clone().unseal()))
-                        .collect::<Result<Vec<_>>>()?
-                        .into_iter()
-                        .flat_map(|executed_block| executed_block.logs())
-                        .filter(|log| {
-                            self.contracts.is_shadowed(
-                                &log.address.parse().expect("failed to parse log address"),
-                            )
+            // The chain may have been reverted to a previous state, in which case we need to
+            // invalidate the blocks in the old chain.
+            let reverted = notification
+                .reverted_chain()
+                .map(|chain| chain.blocks_iter().map(|block| block.hash()).collect::<Vec<_>>())
+                .unwrap_or_default();
+            if !reverted.is_empty() {
+                debug!(blocks = reverted.len(), "Invalidating shadow logs");
+            }
+
+            // Re-execute any newly committed blocks with the shadow bytecode.
+            let committed_chain = notification.committed_chain();
+            let processed_at =
+                SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            let committed = committed_chain
+                .as_ref()
+                .map(|chain| {
+                    chain
+                        .blocks_iter()
+                        .map(|block| IndexedBlock {
+                            parent_hash: block.parent_hash,
+                            block_timestamp: block.timestamp,
+                            base_fee: block.base_fee_per_gas,
+                            processed_at,
+                            config_version: self.config_version,
+                            ..IndexedBlock::new(block.number, block.hash())
                         })
-                        .collect::<Vec<_>>();
-
-                    // Create a new task to send the shadow logs to the shadow database.
-                    tokio::spawn({
-                        let sqlite_db = self.sqlite_db.clone();
-                        let indexed_block_hash_sender = self.indexed_block_hash_sender.clone();
-                        async move {
-                            let block_hashes =
-                                shadow_logs.iter().fold(Vec::new(), |mut acc, log| {
-                                    match acc.last() {
-                                        None => acc.push(log.block_hash.clone()),
-                                        Some(last) if last != &log.block_hash => {
-                                            acc.push(log.block_hash.clone())
-                                        }
-                                        _ => {}
-                                    }
-
-                                    acc
-                                });
-
-                            let _ = sqlite_db.bulk_insert_into_shadow_log_table(shadow_logs).await;
-                            for block_hash in block_hashes {
-                                let _ = indexed_block_hash_sender.send(block_hash);
-                            }
-                        }
-                    });
-
-                    // We're done, so send a FinishedHeight event to the ExEx.
-                    ctx.events.send(ExExEvent::FinishedHeight(chain.tip().number))?;
-                }
-                ExExNotification::ChainReverted { old: chain } => {
-                    // The chain was reverted to a previous state, so we need to invalidate the
-                    // blocks in the old chain
-                    chain.blocks_iter().for_each(|block| {
-                        let block = block.clone().unseal();
-                        debug!(block = block.number, "Invalidating shadow logs");
-
-                        // Create a new task to handle the block reorg in the shadow database.
-                        tokio::spawn({
-                            let sqlite_db = self.sqlite_db.clone();
-                            let indexed_block_hash_sender = self.indexed_block_hash_sender.clone();
-                            async move {
-                                let block_hash = block.hash_slow();
-                                let _ = sqlite_db.handle_block_reorg(block_hash).await;
-                                let _ = indexed_block_hash_sender.send(block_hash.to_lower_hex());
-                            }
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let shadow_logs = match &committed_chain {
+                Some(chain) => self.execute_chain(&ctx, chain)?,
+                None => Vec::new(),
+            };
+
+            // The last block processed, which is the parent of the first reverted block if no
+            // blocks were committed.
+            let checkpoint = match (&committed_chain, notification.reverted_chain()) {
+                (Some(chain), _) => Some(Checkpoint {
+                    block_number: chain.tip().number,
+                    block_hash: chain.tip().hash(),
+                }),
+                (None, Some(chain)) => Some(Checkpoint {
+                    block_number: chain.first().number.saturating_sub(1),
+                    block_hash: chain.first().parent_hash,
+                }),
+                (None, None) => None,
+            };
+
+            // Apply the reverted and committed blocks to the shadow database in a single
+            // transaction. Transient failures are retried, so the finished height is not
+            // advanced until the update has been persisted.
+            self.retry_policy
+                .retry("apply chain update", || {
+                    self.storage.apply_chain_update(&reverted, &committed, &shadow_logs, checkpoint)
+                })
+                .await
+                .map_err(|e| eyre!("failed to persist shadow logs: {e}"))?;
+
+            // Notify subscribers in order: reverted blocks first, newest first, then the committed
+            // blocks, including the ones without shadow logs.
+            for block_hash in reverted.iter().rev() {
+                let _ = self.indexed_block_sender.send(IndexedBlockEvent::Reverted(*block_hash));
+            }
+            for block in &committed {
+                let _ =
+                    self.indexed_block_sender.send(IndexedBlockEvent::Committed(block.block_hash));
+            }
+
+            // Deliver the reverted and committed blocks to the sinks. Sinks with at-least-once
+            // delivery hold back the finished height until they have received the events.
+            if !self.sinks.is_empty() {
+                let mut events = Vec::new();
+                if let Some(chain) = notification.reverted_chain() {
+                    for block in chain.blocks_iter().rev() {
+                        let filter = LogFilter {
+                            block: BlockFilter::Hash(block.hash()),
+                            addresses: Vec::new(),
+                            topics: Default::default(),
+                        };
+                        events.push(SinkEvent {
+                            kind: SinkEventKind::Reverted,
+                            block_number: block.number,
+                            block_hash: block.hash(),
+                            logs: self.storage.get_logs(&filter).await?,
                         });
-                    });
+                    }
                 }
-                _ => {}
+                events.extend(log_events(shadow_logs)?);
+                self.sinks.deliver(&events, checkpoint).await?;
+            }
+
+            // We're done, so send a FinishedHeight event to the ExEx.
+            if let Some(chain) = committed_chain {
+                ctx.events.send(ExExEvent::FinishedHeight(chain.tip().number))?;
             }
         }
         Ok(())
     }
+
+    /// Executes the blocks in the given chain with the shadow bytecode, returning the logs emitted
+    /// by shadowed contracts.
+    fn execute_chain<Node: FullNodeComponents>(
+        &self,
+        ctx: &ExExContext<Node>,
+        chain: &Chain,
+    ) -> Result<Vec<ShadowLog>> {
+        // Create a read-only database provider that we can use to get historical state
+        // at the start of the notification chain. i.e. the state at the first block in
+        // the notification, pre-execution.
+        let database_provider = ctx.provider().database_provider_ro()?;
+        let provider = HistoricalStateProviderRef::new(
+            database_provider.tx_ref(),
+            chain.first().number,
+            database_provider.static_file_provider().clone(),
+        );
+
+        // Use the database provider to create a [`ShadowDatabase`]. This is a
+        // [`reth_revm::Database`] implementation that will override the
+        // bytecode of contracts at specific addresses with custom shadow bytecode, as
+        // defined in `shadow.json`.
+        let db = ShadowDatabase::new(provider, self.contracts.clone());
+
+        let blocks = chain.blocks_iter().collect::<Vec<_>>();
+
+        // Construct a new `ShadowExecutor` with the default config and proper chain
+        // spec, using the `ShadowDatabase` as the state provider.
+        let evm_config = EthEvmConfig::default();
+        let mut executor = ShadowExecutor::new(&evm_config, db, ctx.config.chain.clone());
+
+        // Execute the blocks in the chain, collecting logs from shadowed contracts.
+        let shadow_logs = blocks
+            .into_iter()
+            .map(|block| executor.execute_one(block.clone().unseal()))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flat_map(|executed_block| executed_block.logs())
+            .filter(|log| {
+                self.contracts
+                    .is_shadowed(&log.address.parse().expect("failed to parse log address"))
+            })
+            .collect::<Vec<_>>();
+
+        Ok(shadow_logs)
+    }
 }
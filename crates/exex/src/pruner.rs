@@ -0,0 +1,99 @@
+//! Background pruning of the shadow database.
+
+use std::sync::Arc;
+
+use reth_metrics::{
+    metrics::{Counter, Gauge, Histogram},
+    Metrics,
+};
+use reth_provider::BlockIdReader;
+use reth_tracing::tracing::{debug, warn};
+use shadow_reth_common::{PruneConfig, ShadowDbError, ShadowStorage};
+
+/// Metrics of the [`ShadowPruner`].
+#[derive(Metrics)]
+#[metrics(scope = "shadow.pruner")]
+struct PrunerMetrics {
+    /// Number of logs deleted because they were below the pruned horizon.
+    pruned_logs: Counter,
+    /// Number of removed logs deleted because their block was finalized.
+    pruned_removed_logs: Counter,
+    /// First block whose logs are retained.
+    horizon: Gauge,
+    /// Duration of a pruning run, in seconds.
+    duration_seconds: Histogram,
+    /// Number of failed pruning runs.
+    failures: Counter,
+}
+
+/// Periodically deletes shadow logs which are no longer retained by the retention policy, as
+/// well as removed logs in finalized blocks.
+#[derive(Debug)]
+pub(crate) struct ShadowPruner<P> {
+    /// Provider used to look up the finalized block.
+    provider: P,
+    /// The storage backend of the shadow database.
+    storage: Arc<dyn ShadowStorage>,
+    /// Which logs are kept, and how often the database is pruned.
+    config: PruneConfig,
+}
+
+impl<P: BlockIdReader> ShadowPruner<P> {
+    /// Creates a new pruner.
+    pub(crate) fn new(provider: P, storage: Arc<dyn ShadowStorage>, config: PruneConfig) -> Self {
+        Self { provider, storage, config }
+    }
+
+    /// Prunes the shadow database every [`PruneConfig::interval`], forever.
+    ///
+    /// Failed runs are logged and retried at the next interval, as pruning is never required for
+    /// the ExEx to make progress.
+    pub(crate) async fn run(self) {
+        let metrics = PrunerMetrics::default();
+        let mut interval = tokio::time::interval(self.config.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            let _ = interval.tick().await;
+            let start_time = std::time::Instant::now();
+            match self.prune(&metrics).await {
+                Ok(()) => metrics.duration_seconds.record(start_time.elapsed().as_secs_f64()),
+                Err(err) => {
+                    metrics.failures.increment(1);
+                    warn!(%err, "Failed to prune shadow database");
+                }
+            }
+        }
+    }
+
+    /// Runs a single pruning pass.
+    async fn prune(&self, metrics: &PrunerMetrics) -> Result<(), ShadowDbError> {
+        // The horizon follows the last block processed by the ExEx, not the node's tip, so that
+        // logs are never pruned ahead of indexing.
+        let horizon = self
+            .storage
+            .checkpoint()
+            .await?
+            .and_then(|checkpoint| self.config.retention.horizon(checkpoint.block_number));
+        let finalized = self.provider.finalized_block_number().unwrap_or_else(|err| {
+            warn!(%err, "Failed to look up finalized block, not pruning removed logs");
+            None
+        });
+
+        let outcome = self.storage.prune(horizon, finalized).await?;
+        metrics.pruned_logs.increment(outcome.logs);
+        metrics.pruned_removed_logs.increment(outcome.removed_logs);
+        if let Some(horizon) = self.storage.pruned_horizon().await? {
+            metrics.horizon.set(horizon as f64);
+        }
+
+        debug!(
+            ?horizon,
+            ?finalized,
+            logs = outcome.logs,
+            removed_logs = outcome.removed_logs,
+            "Pruned shadow database"
+        );
+        Ok(())
+    }
+}
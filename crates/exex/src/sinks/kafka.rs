@@ -0,0 +1,141 @@
+//! A sink producing shadow logs to a Kafka topic.
+
+use std::{collections::BTreeMap, fmt::Debug};
+
+use async_trait::async_trait;
+use eyre::Result;
+use rskafka::{
+    client::{
+        partition::{Compression, PartitionClient, UnknownTopicHandling},
+        ClientBuilder,
+    },
+    record::Record,
+};
+
+use super::{ShadowSink, SinkEvent};
+
+/// Produces records to a single Kafka topic partition.
+#[async_trait]
+pub trait KafkaProducer: Debug + Send + Sync {
+    /// Produces a record with the given key, value and headers.
+    async fn produce(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        headers: BTreeMap<String, Vec<u8>>,
+    ) -> Result<()>;
+}
+
+/// A [`KafkaProducer`] backed by [`rskafka`].
+pub struct RsKafkaProducer {
+    client: PartitionClient,
+}
+
+impl Debug for RsKafkaProducer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RsKafkaProducer")
+            .field("topic", &self.client.topic())
+            .field("partition", &self.client.partition())
+            .finish()
+    }
+}
+
+impl RsKafkaProducer {
+    /// Connects to the Kafka cluster at `brokers`, producing to the given topic partition.
+    pub async fn connect(brokers: Vec<String>, topic: String, partition: i32) -> Result<Self> {
+        let client = ClientBuilder::new(brokers).build().await?;
+        let client = client.partition_client(topic, partition, UnknownTopicHandling::Retry).await?;
+        Ok(Self { client })
+    }
+}
+
+#[async_trait]
+impl KafkaProducer for RsKafkaProducer {
+    async fn produce(
+        &self,
+        key: Vec<u8>,
+        value: Vec<u8>,
+        headers: BTreeMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        let record =
+            Record { key: Some(key), value: Some(value), headers, timestamp: chrono::Utc::now() };
+        let _ = self.client.produce(vec![record], Compression::NoCompression).await?;
+        Ok(())
+    }
+}
+
+/// Produces one record per event, keyed by block hash, with the JSON encoding of the event as its
+/// value and the event type in the `type` header.
+#[derive(Debug)]
+pub struct KafkaSink<P = RsKafkaProducer> {
+    producer: P,
+}
+
+impl<P: KafkaProducer> KafkaSink<P> {
+    /// Creates a sink producing records with `producer`.
+    pub const fn new(producer: P) -> Self {
+        Self { producer }
+    }
+}
+
+#[async_trait]
+impl<P: KafkaProducer> ShadowSink for KafkaSink<P> {
+    async fn send(&self, event: &SinkEvent) -> Result<()> {
+        let headers =
+            BTreeMap::from([("type".to_string(), event.kind.as_str().as_bytes().to_vec())]);
+        self.producer
+            .produce(event.block_hash.to_vec(), serde_json::to_vec(&event.to_json())?, headers)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    use async_trait::async_trait;
+    use reth_primitives::{Address, B256};
+
+    use super::{KafkaProducer, KafkaSink};
+    use crate::sinks::{tests::shadow_log, ShadowSink, SinkEvent, SinkEventKind};
+
+    /// An in-memory stand-in for a Kafka partition.
+    #[derive(Debug, Default)]
+    struct InMemoryProducer {
+        records: Mutex<Vec<(Vec<u8>, Vec<u8>, BTreeMap<String, Vec<u8>>)>>,
+    }
+
+    #[async_trait]
+    impl KafkaProducer for InMemoryProducer {
+        async fn produce(
+            &self,
+            key: Vec<u8>,
+            value: Vec<u8>,
+            headers: BTreeMap<String, Vec<u8>>,
+        ) -> eyre::Result<()> {
+            self.records.lock().unwrap().push((key, value, headers));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_produce() {
+        let sink = KafkaSink::new(InMemoryProducer::default());
+        let block_hash = B256::repeat_byte(7);
+        let event = SinkEvent {
+            kind: SinkEventKind::Reverted,
+            block_number: 1,
+            block_hash,
+            logs: vec![shadow_log(1, Address::ZERO)],
+        };
+        sink.send(&event).await.unwrap();
+
+        let records = sink.producer.records.lock().unwrap();
+        let (key, value, headers) = &records[0];
+        assert_eq!(key, &block_hash.to_vec());
+        assert_eq!(headers["type"], b"reverted");
+        let value: serde_json::Value = serde_json::from_slice(value).unwrap();
+        assert_eq!(value["blockHash"], block_hash.to_string());
+        assert_eq!(value["logs"].as_array().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,646 @@
+//! Sinks which receive the shadow logs of every block indexed by the ExEx, in addition to the
+//! shadow database.
+//!
+//! Sinks are configured in a JSON file, see [`SinkConfig`]. Each sink has its own cursor, the last
+//! block delivered to it, which is stored next to the shadow database. Sinks with
+//! [`Delivery::AtLeastOnce`] hold back the ExEx until an event has been delivered, and are caught
+//! up from the shadow database on startup if they fell behind the indexing checkpoint.
+
+mod kafka;
+mod ndjson;
+mod webhook;
+
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use reth_primitives::{Address, BlockHash, B256};
+use reth_tracing::tracing::{info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use shadow_reth_common::{
+    BlockFilter, Checkpoint, LogFilter, ShadowLog, ShadowStorage, ToLowerHex,
+};
+
+pub use kafka::{KafkaProducer, KafkaSink, RsKafkaProducer};
+pub use ndjson::NdjsonSink;
+pub use webhook::WebhookSink;
+
+/// A destination for shadow logs.
+#[async_trait]
+pub trait ShadowSink: Debug + Send + Sync {
+    /// Delivers a single event. Delivering an event which was delivered before must be harmless,
+    /// as events are redelivered after a failure.
+    async fn send(&self, event: &SinkEvent) -> Result<()>;
+}
+
+/// Whether logs were added to or removed from the canonical chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkEventKind {
+    /// The block was committed to the canonical chain.
+    Committed,
+    /// The block was reverted, its logs are marked as removed.
+    Reverted,
+}
+
+impl SinkEventKind {
+    /// Returns the name of the event kind, as used in the JSON encoding of events.
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Committed => "committed",
+            Self::Reverted => "reverted",
+        }
+    }
+}
+
+/// The shadow logs of a single committed or reverted block.
+#[derive(Debug, Clone)]
+pub struct SinkEvent {
+    /// Whether the block was committed or reverted.
+    pub kind: SinkEventKind,
+    /// Number of the block.
+    pub block_number: u64,
+    /// Hash of the block.
+    pub block_hash: BlockHash,
+    /// Shadow logs emitted in the block.
+    pub logs: Vec<ShadowLog>,
+}
+
+impl SinkEvent {
+    /// Returns the event as a JSON object, with the logs encoded by [`log_json`].
+    pub fn to_json(&self) -> Value {
+        json!({
+            "type": self.kind.as_str(),
+            "blockNumber": self.block_number,
+            "blockHash": self.block_hash.to_lower_hex(),
+            "logs": self.logs.iter().map(log_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Returns a shadow log as a JSON object, with the same field names as `shadow_getLogs`.
+pub fn log_json(log: &ShadowLog) -> Value {
+    json!({
+        "address": log.address,
+        "blockHash": log.block_hash,
+        "blockNumber": log.block_number,
+        "blockTimestamp": log.block_timestamp,
+        "data": log.data,
+        "logIndex": log.block_log_index,
+        "removed": log.removed,
+        "topics": [&log.topic_0, &log.topic_1, &log.topic_2, &log.topic_3]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>(),
+        "transactionHash": log.transaction_hash,
+        "transactionIndex": log.transaction_index,
+        "transactionLogIndex": log.transaction_log_index,
+    })
+}
+
+/// Selects the logs delivered to a sink.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SinkFilter {
+    /// Addresses from which logs are delivered. An empty list matches any address.
+    #[serde(default)]
+    pub addresses: Vec<Address>,
+    /// Topics which logs must match, by position. `null` matches any topic.
+    #[serde(default)]
+    pub topics: Vec<Option<B256>>,
+}
+
+impl SinkFilter {
+    /// Returns `true` if the log is selected by the filter.
+    pub fn matches(&self, log: &ShadowLog) -> bool {
+        let address_matches = self.addresses.is_empty() ||
+            log.address
+                .parse::<Address>()
+                .is_ok_and(|address| self.addresses.contains(&address));
+        let topics = [&log.topic_0, &log.topic_1, &log.topic_2, &log.topic_3];
+        address_matches &&
+            self.topics.iter().zip(topics).all(|(expected, topic)| match expected {
+                None => true,
+                Some(expected) => {
+                    topic.as_deref().and_then(|topic| topic.parse::<B256>().ok()) == Some(*expected)
+                }
+            })
+    }
+
+    /// Returns the event with only the logs selected by the filter, or `None` if no log was
+    /// selected.
+    fn apply(&self, event: &SinkEvent) -> Option<SinkEvent> {
+        let logs = event.logs.iter().filter(|log| self.matches(log)).cloned().collect::<Vec<_>>();
+        (!logs.is_empty()).then(|| SinkEvent { logs, ..event.clone() })
+    }
+}
+
+/// Delivery guarantee of a sink.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Delivery {
+    /// Failed deliveries are retried until they succeed, holding back the ExEx. Events missed
+    /// while the node was stopped are replayed from the shadow database on startup.
+    #[default]
+    AtLeastOnce,
+    /// Events are delivered once, failed deliveries are logged and dropped.
+    AtMostOnce,
+}
+
+/// Configuration of a single sink, as read from the sinks configuration file, e.g.:
+///
+/// ```json
+/// [
+///   { "name": "archive", "type": "ndjson", "directory": "/data/shadow-logs" },
+///   {
+///     "name": "alerts",
+///     "type": "webhook",
+///     "url": "https://example.com/shadow",
+///     "secret": "...",
+///     "delivery": "atMostOnce",
+///     "filter": { "addresses": ["0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2"] }
+///   },
+///   { "name": "stream", "type": "kafka", "brokers": ["localhost:9092"], "topic": "shadow-logs" }
+/// ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SinkConfig {
+    /// Unique name of the sink, used to store its cursor.
+    pub name: String,
+    /// Logs delivered to the sink.
+    #[serde(default)]
+    pub filter: SinkFilter,
+    /// Delivery guarantee of the sink.
+    #[serde(default)]
+    pub delivery: Delivery,
+    /// The kind of sink and its settings.
+    #[serde(flatten)]
+    pub kind: SinkKind,
+}
+
+/// The kinds of built-in sinks and their settings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum SinkKind {
+    /// Newline-delimited JSON files, see [`NdjsonSink`].
+    Ndjson {
+        /// Directory in which the files are written.
+        directory: PathBuf,
+        /// Size after which a new file is started, in bytes.
+        #[serde(default = "default_max_file_size")]
+        max_file_size: u64,
+    },
+    /// HTTP webhooks, see [`WebhookSink`].
+    Webhook {
+        /// URL to which events are posted.
+        url: String,
+        /// Secret with which requests are signed.
+        secret: Option<String>,
+    },
+    /// A Kafka topic, see [`KafkaSink`].
+    Kafka {
+        /// Bootstrap brokers of the Kafka cluster.
+        brokers: Vec<String>,
+        /// Topic to which events are produced.
+        topic: String,
+        /// Partition to which events are produced.
+        #[serde(default)]
+        partition: i32,
+    },
+}
+
+const fn default_max_file_size() -> u64 {
+    128 * 1024 * 1024
+}
+
+/// A sink with its configuration.
+#[derive(Debug)]
+struct ConfiguredSink {
+    name: String,
+    filter: SinkFilter,
+    delivery: Delivery,
+    sink: Box<dyn ShadowSink>,
+}
+
+/// The sinks of the ExEx.
+#[derive(Debug, Default)]
+pub struct ShadowSinks {
+    sinks: Vec<ConfiguredSink>,
+    /// Directory in which the cursors of the sinks are stored.
+    state_dir: PathBuf,
+}
+
+impl ShadowSinks {
+    /// Creates the sinks configured in the JSON file at `config_path`, storing their cursors in
+    /// `state_dir`.
+    pub async fn from_file(config_path: &Path, state_dir: PathBuf) -> Result<Self> {
+        let configs: Vec<SinkConfig> =
+            serde_json::from_slice(&std::fs::read(config_path).map_err(|e| {
+                eyre!("failed to read sinks configuration {}: {e}", config_path.display())
+            })?)
+            .map_err(|e| eyre!("failed to parse sinks configuration: {e}"))?;
+
+        let mut this = Self::new(state_dir);
+        for config in configs {
+            let sink: Box<dyn ShadowSink> = match config.kind {
+                SinkKind::Ndjson { directory, max_file_size } => {
+                    Box::new(NdjsonSink::new(directory, max_file_size))
+                }
+                SinkKind::Webhook { url, secret } => Box::new(WebhookSink::new(url, secret)),
+                SinkKind::Kafka { brokers, topic, partition } => Box::new(KafkaSink::new(
+                    RsKafkaProducer::connect(brokers, topic, partition).await?,
+                )),
+            };
+            this.add(config.name, config.filter, config.delivery, sink)?;
+        }
+        Ok(this)
+    }
+
+    /// Creates an empty set of sinks, storing their cursors in `state_dir`.
+    pub fn new(state_dir: PathBuf) -> Self {
+        Self { sinks: Vec::new(), state_dir }
+    }
+
+    /// Adds a sink. Its name is used as the name of its cursor file, so it may only contain ASCII
+    /// letters, digits, `_` and `-`.
+    pub fn add(
+        &mut self,
+        name: String,
+        filter: SinkFilter,
+        delivery: Delivery,
+        sink: Box<dyn ShadowSink>,
+    ) -> Result<()> {
+        if name.is_empty() ||
+            !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+        {
+            return Err(eyre!(
+                "invalid sink name `{name}`: only ASCII letters, digits, `_` and `-` are allowed"
+            ))
+        }
+        if self.sinks.iter().any(|sink| sink.name == name) {
+            return Err(eyre!("duplicate sink name `{name}`"))
+        }
+        self.sinks.push(ConfiguredSink { name, filter, delivery, sink });
+        Ok(())
+    }
+
+    /// Returns `true` if no sinks are configured.
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    /// Replays the logs of all blocks up to the indexing checkpoint which have not been delivered
+    /// to [`Delivery::AtLeastOnce`] sinks, e.g. because the node stopped before delivery. The logs
+    /// of reverted blocks which have not been pruned yet are replayed as reverted events.
+    ///
+    /// Sinks without a cursor start at the current checkpoint.
+    pub async fn catch_up(&self, storage: &dyn ShadowStorage) -> Result<()> {
+        let Some(checkpoint) = storage.checkpoint().await? else { return Ok(()) };
+
+        for sink in &self.sinks {
+            let cursor = match self.cursor(&sink.name)? {
+                Some(cursor) if sink.delivery == Delivery::AtLeastOnce => cursor,
+                _ => {
+                    self.set_cursor(&sink.name, checkpoint.block_number)?;
+                    continue
+                }
+            };
+            if cursor >= checkpoint.block_number {
+                continue
+            }
+
+            info!(
+                sink = %sink.name,
+                from_block = cursor + 1,
+                to_block = checkpoint.block_number,
+                "Replaying shadow logs to sink"
+            );
+            let filter = LogFilter {
+                block: BlockFilter::Range(cursor + 1, checkpoint.block_number),
+                addresses: Vec::new(),
+                topics: Default::default(),
+            };
+            for event in log_events(storage.get_logs(&filter).await?)? {
+                if let Some(event) = sink.filter.apply(&event) {
+                    self.send(sink, &event).await?;
+                }
+            }
+            self.set_cursor(&sink.name, checkpoint.block_number)?;
+        }
+        Ok(())
+    }
+
+    /// Delivers `events` to all sinks, and advances their cursors to `checkpoint`.
+    pub async fn deliver(
+        &self,
+        events: &[SinkEvent],
+        checkpoint: Option<Checkpoint>,
+    ) -> Result<()> {
+        for sink in &self.sinks {
+            for event in events {
+                if let Some(event) = sink.filter.apply(event) {
+                    self.send(sink, &event).await?;
+                }
+            }
+            if let Some(checkpoint) = checkpoint {
+                self.set_cursor(&sink.name, checkpoint.block_number)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends an event to a sink, according to the sink's delivery guarantee.
+    async fn send(&self, sink: &ConfiguredSink, event: &SinkEvent) -> Result<()> {
+        let mut backoff = Duration::from_millis(100);
+        loop {
+            match sink.sink.send(event).await {
+                Ok(()) => return Ok(()),
+                Err(err) if sink.delivery == Delivery::AtMostOnce => {
+                    warn!(sink = %sink.name, %err, block_hash = %event.block_hash, "Dropping event");
+                    return Ok(())
+                }
+                Err(err) => {
+                    warn!(sink = %sink.name, %err, ?backoff, "Failed to deliver event, retrying");
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_secs(30));
+                }
+            }
+        }
+    }
+
+    /// Returns the path of the file storing the cursor of the sink with the given name.
+    fn cursor_path(&self, name: &str) -> PathBuf {
+        self.state_dir.join(format!("{name}.cursor"))
+    }
+
+    /// Returns the last block delivered to the sink with the given name.
+    fn cursor(&self, name: &str) -> Result<Option<u64>> {
+        match std::fs::read_to_string(self.cursor_path(name)) {
+            Ok(cursor) => Ok(Some(cursor.trim().parse()?)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Stores the last block delivered to the sink with the given name.
+    fn set_cursor(&self, name: &str, block_number: u64) -> Result<()> {
+        std::fs::create_dir_all(&self.state_dir)?;
+        let path = self.cursor_path(name);
+        let tmp_path = path.with_extension("tmp");
+        std::fs::write(&tmp_path, block_number.to_string())?;
+        std::fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Groups logs, ordered by block number, into one event per block: a committed event for the
+/// canonical block and a reverted event for each block which was removed from the canonical chain.
+///
+/// The order in which blocks were reverted is not stored, so the reverted blocks of a block number
+/// are placed before its canonical block.
+pub(crate) fn log_events(logs: impl IntoIterator<Item = ShadowLog>) -> Result<Vec<SinkEvent>> {
+    let mut events: Vec<SinkEvent> = Vec::new();
+    for log in logs {
+        let block_hash = log
+            .block_hash
+            .parse()
+            .map_err(|e| eyre!("invalid block hash `{}` of shadow log: {e}", log.block_hash))?;
+        let event = events
+            .iter_mut()
+            .rev()
+            .take_while(|event| event.block_number == log.block_number)
+            .find(|event| event.block_hash == block_hash);
+        match event {
+            Some(event) => event.logs.push(log),
+            None => events.push(SinkEvent {
+                kind: if log.removed { SinkEventKind::Reverted } else { SinkEventKind::Committed },
+                block_number: log.block_number,
+                block_hash,
+                logs: vec![log],
+            }),
+        }
+    }
+    events.sort_by_key(|event| (event.block_number, event.kind == SinkEventKind::Committed));
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    };
+
+    use async_trait::async_trait;
+    use reth_primitives::{Address, BlockHash, B256};
+    use shadow_reth_common::{Checkpoint, ShadowLog, ShadowSqliteDb, ShadowStorage, ToLowerHex};
+
+    use super::{
+        log_events, Delivery, ShadowSink, ShadowSinks, SinkConfig, SinkEvent, SinkEventKind,
+        SinkFilter, SinkKind,
+    };
+
+    pub(crate) fn shadow_log(block_number: u64, address: Address) -> ShadowLog {
+        ShadowLog {
+            address: address.to_lower_hex(),
+            block_hash: B256::left_padding_from(&block_number.to_be_bytes()).to_lower_hex(),
+            block_log_index: 0,
+            block_number,
+            block_timestamp: 1703595275,
+            transaction_index: 0,
+            transaction_hash: B256::repeat_byte(1).to_lower_hex(),
+            transaction_log_index: 0,
+            removed: false,
+            data: Some("0x".to_string()),
+            topic_0: Some(B256::repeat_byte(2).to_lower_hex()),
+            topic_1: None,
+            topic_2: None,
+            topic_3: None,
+        }
+    }
+
+    /// A sink recording the block numbers of the events it receives, which fails the first
+    /// `failures` deliveries.
+    #[derive(Debug, Default, Clone)]
+    struct RecordingSink {
+        received: Arc<Mutex<Vec<u64>>>,
+        failures: Arc<AtomicU32>,
+    }
+
+    #[async_trait]
+    impl ShadowSink for RecordingSink {
+        async fn send(&self, event: &SinkEvent) -> eyre::Result<()> {
+            if self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok()
+            {
+                eyre::bail!("unavailable")
+            }
+            self.received.lock().unwrap().push(event.block_number);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let configs: Vec<SinkConfig> = serde_json::from_str(
+            r#"[
+                { "name": "archive", "type": "ndjson", "directory": "/tmp/shadow" },
+                {
+                    "name": "alerts",
+                    "type": "webhook",
+                    "url": "http://localhost:8080",
+                    "delivery": "atMostOnce",
+                    "filter": { "topics": [null, "0x0202020202020202020202020202020202020202020202020202020202020202"] }
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            configs[0].kind,
+            SinkKind::Ndjson { directory: "/tmp/shadow".into(), max_file_size: 128 * 1024 * 1024 }
+        );
+        assert_eq!(configs[0].delivery, Delivery::AtLeastOnce);
+        assert_eq!(configs[1].delivery, Delivery::AtMostOnce);
+        assert_eq!(configs[1].filter.topics, vec![None, Some(B256::repeat_byte(2))]);
+    }
+
+    #[test]
+    fn test_filter() {
+        let log = shadow_log(1, Address::repeat_byte(1));
+        assert!(SinkFilter::default().matches(&log));
+        assert!(
+            SinkFilter { addresses: vec![Address::repeat_byte(1)], topics: vec![] }.matches(&log)
+        );
+        assert!(
+            !SinkFilter { addresses: vec![Address::repeat_byte(2)], topics: vec![] }.matches(&log)
+        );
+        assert!(SinkFilter { addresses: vec![], topics: vec![Some(B256::repeat_byte(2))] }
+            .matches(&log));
+        assert!(!SinkFilter { addresses: vec![], topics: vec![None, Some(B256::repeat_byte(2))] }
+            .matches(&log));
+    }
+
+    #[test]
+    fn test_sink_names() {
+        let mut sinks = ShadowSinks::new("/tmp/shadow-sinks".into());
+        for name in ["", "../escape", "a/b", "a.b", "\u{e9}"] {
+            let sink = Box::new(RecordingSink::default());
+            let result =
+                sinks.add(name.to_string(), SinkFilter::default(), Delivery::AtMostOnce, sink);
+            assert!(result.is_err(), "{name}");
+        }
+        let sink = Box::new(RecordingSink::default());
+        sinks
+            .add("alerts_1-a".to_string(), SinkFilter::default(), Delivery::AtMostOnce, sink)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_log_events() {
+        let logs = [1, 1, 2].map(|block_number| shadow_log(block_number, Address::ZERO));
+        let events = log_events(logs).unwrap();
+        assert_eq!(events.iter().map(|event| event.logs.len()).collect::<Vec<_>>(), vec![2, 1]);
+
+        // A removed block is placed before the canonical block with the same number.
+        let canonical = ShadowLog {
+            block_hash: BlockHash::repeat_byte(9).to_lower_hex(),
+            ..shadow_log(2, Address::ZERO)
+        };
+        let logs = [
+            shadow_log(1, Address::ZERO),
+            canonical.clone(),
+            ShadowLog { removed: true, ..shadow_log(2, Address::ZERO) },
+            ShadowLog { block_log_index: 1, ..canonical },
+        ];
+        let events = log_events(logs).unwrap();
+        assert_eq!(
+            events.iter().map(|event| (event.block_number, event.kind)).collect::<Vec<_>>(),
+            vec![
+                (1, SinkEventKind::Committed),
+                (2, SinkEventKind::Reverted),
+                (2, SinkEventKind::Committed)
+            ]
+        );
+        assert_eq!(events[2].logs.len(), 2);
+
+        let mut log = shadow_log(1, Address::ZERO);
+        log.block_hash = "0x01".to_string();
+        assert!(log_events([log]).is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_at_least_once_delivery_and_catch_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        let sink = RecordingSink::default();
+        let mut sinks = ShadowSinks::new(dir.path().to_path_buf());
+        sinks
+            .add(
+                "test".to_string(),
+                SinkFilter::default(),
+                Delivery::AtLeastOnce,
+                Box::new(sink.clone()),
+            )
+            .unwrap();
+
+        // Failed deliveries are retried until they succeed.
+        sink.failures.store(2, Ordering::SeqCst);
+        let logs = vec![shadow_log(1, Address::ZERO), shadow_log(2, Address::ZERO)];
+        let checkpoint = Checkpoint { block_number: 2, block_hash: BlockHash::ZERO };
+        sinks.deliver(&log_events(logs).unwrap(), Some(checkpoint)).await.unwrap();
+        assert_eq!(*sink.received.lock().unwrap(), vec![1, 2]);
+
+        // Blocks indexed while the sink was not running are replayed from the shadow database.
+        let logs =
+            (1..=4).map(|block_number| shadow_log(block_number, Address::ZERO)).collect::<Vec<_>>();
+        let checkpoint = Checkpoint { block_number: 4, block_hash: BlockHash::ZERO };
+        db.apply_chain_update(&[], &[], &logs, Some(checkpoint)).await.unwrap();
+        sinks.catch_up(&db).await.unwrap();
+        assert_eq!(*sink.received.lock().unwrap(), vec![1, 2, 3, 4]);
+
+        sinks.catch_up(&db).await.unwrap();
+        assert_eq!(sink.received.lock().unwrap().len(), 4);
+
+        // Reverted blocks are replayed too, before the block which replaced them.
+        let old = shadow_log(5, Address::ZERO);
+        let new = ShadowLog {
+            block_hash: BlockHash::repeat_byte(5).to_lower_hex(),
+            ..shadow_log(5, Address::ZERO)
+        };
+        let checkpoint = Checkpoint { block_number: 5, block_hash: BlockHash::repeat_byte(5) };
+        db.apply_chain_update(&[], &[], &[old.clone()], None).await.unwrap();
+        db.apply_chain_update(&[old.block_hash.parse().unwrap()], &[], &[new], Some(checkpoint))
+            .await
+            .unwrap();
+        sinks.catch_up(&db).await.unwrap();
+        assert_eq!(*sink.received.lock().unwrap(), vec![1, 2, 3, 4, 5, 5]);
+    }
+
+    #[tokio::test]
+    async fn test_at_most_once_delivery() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = RecordingSink::default();
+        let mut sinks = ShadowSinks::new(dir.path().to_path_buf());
+        let filter = SinkFilter { addresses: vec![Address::ZERO], topics: vec![] };
+        sinks
+            .add("test".to_string(), filter, Delivery::AtMostOnce, Box::new(sink.clone()))
+            .unwrap();
+
+        sink.failures.store(1, Ordering::SeqCst);
+        let logs = vec![
+            shadow_log(1, Address::ZERO),
+            shadow_log(2, Address::ZERO),
+            shadow_log(3, Address::repeat_byte(1)),
+        ];
+        sinks.deliver(&log_events(logs).unwrap(), None).await.unwrap();
+        assert_eq!(*sink.received.lock().unwrap(), vec![2]);
+    }
+}
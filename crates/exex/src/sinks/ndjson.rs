@@ -0,0 +1,152 @@
+//! A sink writing shadow logs to rotating newline-delimited JSON files.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use eyre::Result;
+
+use super::{log_json, ShadowSink, SinkEvent, SinkEventKind};
+
+/// Writes one JSON object per shadow log to `shadow-logs-{index}.ndjson` files in a directory.
+///
+/// Once a file exceeds the maximum file size, a new file with the next index is started. Logs of
+/// reverted blocks are written with `"removed": true`. Files are written on tokio's blocking
+/// thread pool.
+#[derive(Debug)]
+pub struct NdjsonSink {
+    directory: PathBuf,
+    max_file_size: u64,
+    /// The file currently being written, opened on first use.
+    current: Arc<Mutex<Option<(u64, File)>>>,
+}
+
+impl NdjsonSink {
+    /// Creates a sink writing to files in `directory`, starting a new file once the current one
+    /// exceeds `max_file_size` bytes.
+    pub fn new(directory: PathBuf, max_file_size: u64) -> Self {
+        Self { directory, max_file_size, current: Arc::new(Mutex::new(None)) }
+    }
+
+    /// Returns the index of the last file in the directory, if any.
+    fn last_index(directory: &Path) -> Result<Option<u64>> {
+        let mut last = None;
+        for entry in std::fs::read_dir(directory)? {
+            let name = entry?.file_name();
+            let index = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("shadow-logs-"))
+                .and_then(|name| name.strip_suffix(".ndjson"))
+                .and_then(|index| index.parse::<u64>().ok());
+            last = last.max(index);
+        }
+        Ok(last)
+    }
+
+    /// Opens the file with the given index in `directory` for appending.
+    fn open(directory: &Path, index: u64) -> Result<File> {
+        let path = directory.join(format!("shadow-logs-{index:06}.ndjson"));
+        Ok(OpenOptions::new().create(true).append(true).open(path)?)
+    }
+
+    /// Appends `lines` to the current file, rotating it if it exceeds `max_file_size` bytes, and
+    /// syncs it to disk.
+    fn write(
+        directory: &Path,
+        max_file_size: u64,
+        current: &Mutex<Option<(u64, File)>>,
+        lines: &[u8],
+    ) -> Result<()> {
+        let mut current = current.lock().expect("ndjson sink lock poisoned");
+        let (index, file) = match current.take() {
+            Some(current) => current,
+            None => {
+                std::fs::create_dir_all(directory)?;
+                let index = Self::last_index(directory)?.unwrap_or_default();
+                (index, Self::open(directory, index)?)
+            }
+        };
+        let (index, mut file) = if file.metadata()?.len() >= max_file_size {
+            (index + 1, Self::open(directory, index + 1)?)
+        } else {
+            (index, file)
+        };
+
+        file.write_all(lines)?;
+        file.sync_data()?;
+        *current = Some((index, file));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ShadowSink for NdjsonSink {
+    async fn send(&self, event: &SinkEvent) -> Result<()> {
+        let mut lines = Vec::new();
+        for log in &event.logs {
+            let mut log = log.clone();
+            log.removed |= event.kind == SinkEventKind::Reverted;
+            serde_json::to_writer(&mut lines, &log_json(&log))?;
+            lines.push(b'\n');
+        }
+
+        let (directory, max_file_size) = (self.directory.clone(), self.max_file_size);
+        let current = Arc::clone(&self.current);
+        tokio::task::spawn_blocking(move || {
+            Self::write(&directory, max_file_size, &current, &lines)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reth_primitives::{Address, BlockHash};
+
+    use super::NdjsonSink;
+    use crate::sinks::{tests::shadow_log, ShadowSink, SinkEvent, SinkEventKind};
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let sink = NdjsonSink::new(dir.path().to_path_buf(), 1);
+
+        for (block_number, kind) in [
+            (1, SinkEventKind::Committed),
+            (1, SinkEventKind::Reverted),
+            (2, SinkEventKind::Committed),
+        ] {
+            let event = SinkEvent {
+                kind,
+                block_number,
+                block_hash: BlockHash::ZERO,
+                logs: vec![shadow_log(block_number, Address::ZERO)],
+            };
+            sink.send(&event).await.unwrap();
+        }
+
+        let read = |index: u64| -> serde_json::Value {
+            let path = dir.path().join(format!("shadow-logs-{index:06}.ndjson"));
+            serde_json::from_str(std::fs::read_to_string(path).unwrap().trim_end()).unwrap()
+        };
+        assert_eq!(read(0)["removed"], false);
+        assert_eq!(read(1)["removed"], true);
+        assert_eq!(read(2)["blockNumber"], 2);
+
+        // A new sink continues writing to the last file.
+        let sink = NdjsonSink::new(dir.path().to_path_buf(), u64::MAX);
+        let event = SinkEvent {
+            kind: SinkEventKind::Committed,
+            block_number: 3,
+            block_hash: BlockHash::ZERO,
+            logs: vec![shadow_log(3, Address::ZERO)],
+        };
+        sink.send(&event).await.unwrap();
+        let path = dir.path().join("shadow-logs-000002.ndjson");
+        assert_eq!(std::fs::read_to_string(path).unwrap().lines().count(), 2);
+    }
+}
@@ -0,0 +1,204 @@
+//! A sink posting shadow logs to an HTTP webhook.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use eyre::{eyre, Result};
+use hmac::{Hmac, Mac};
+use reth_primitives::hex;
+use sha2::Sha256;
+
+use super::{ShadowSink, SinkEvent};
+
+/// Header containing the UNIX timestamp at which a request was signed.
+pub const TIMESTAMP_HEADER: &str = "X-Shadow-Timestamp";
+/// Header containing the signature of a request, see [`WebhookSink`].
+pub const SIGNATURE_HEADER: &str = "X-Shadow-Signature";
+/// Header identifying an event, which is the same for every delivery of the event.
+pub const EVENT_ID_HEADER: &str = "X-Shadow-Event-Id";
+
+/// Posts every event as a JSON object to a URL.
+///
+/// If a secret is configured, requests are signed with the `X-Shadow-Signature` header, which is
+/// `sha256=` followed by the hex-encoded HMAC-SHA256 of `{timestamp}.{body}`, keyed with the
+/// secret. The timestamp is sent in the `X-Shadow-Timestamp` header, so receivers can reject
+/// replayed requests.
+///
+/// Each event is posted once, and any response outside of `2xx` fails the delivery. Failed
+/// deliveries are retried by [`ShadowSinks`](super::ShadowSinks), according to the delivery
+/// guarantee of the sink.
+#[derive(Debug)]
+pub struct WebhookSink {
+    client: reqwest::Client,
+    url: String,
+    secret: Option<String>,
+}
+
+impl WebhookSink {
+    /// Creates a sink posting to `url`, signing requests with `secret`.
+    pub fn new(url: String, secret: Option<String>) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .build()
+            .expect("failed to build HTTP client");
+        Self { client, url, secret }
+    }
+
+    /// Returns the signature of a request with the given timestamp and body.
+    fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body);
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+#[async_trait]
+impl ShadowSink for WebhookSink {
+    async fn send(&self, event: &SinkEvent) -> Result<()> {
+        let event_id = format!("{}-{}", event.kind.as_str(), event.block_hash);
+        let body = serde_json::to_vec(&event.to_json())?;
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut request = self
+            .client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(TIMESTAMP_HEADER, timestamp)
+            .header(EVENT_ID_HEADER, event_id);
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, Self::sign(secret, timestamp, &body));
+        }
+
+        let status = request.body(body).send().await?.status();
+        if !status.is_success() {
+            return Err(eyre!("webhook responded with {status}"))
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use reth_primitives::{Address, BlockHash};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::{WebhookSink, SIGNATURE_HEADER, TIMESTAMP_HEADER};
+    use crate::sinks::{tests::shadow_log, ShadowSink, SinkEvent, SinkEventKind};
+
+    /// A request received by [`serve`], with lowercase header names.
+    #[derive(Debug)]
+    struct Request {
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+    }
+
+    impl Request {
+        fn header(&self, name: &str) -> &str {
+            let name = name.to_ascii_lowercase();
+            &self.headers.iter().find(|(key, _)| key == &name).unwrap().1
+        }
+    }
+
+    /// Serves HTTP/1.1 requests on a local port, responding with the given statuses in order, and
+    /// records the requests.
+    async fn serve(statuses: Vec<u16>) -> (String, Arc<Mutex<Vec<Request>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = requests.clone();
+        tokio::spawn(async move {
+            for status in statuses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                let (header_len, content_length) = loop {
+                    let mut chunk = [0; 4096];
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let head = String::from_utf8_lossy(&buf[..pos]).to_lowercase();
+                        let content_length = head
+                            .lines()
+                            .find_map(|line| line.strip_prefix("content-length: "))
+                            .map_or(0, |len| len.trim().parse::<usize>().unwrap());
+                        break (pos + 4, content_length)
+                    }
+                };
+                while buf.len() < header_len + content_length {
+                    let mut chunk = [0; 4096];
+                    let n = stream.read(&mut chunk).await.unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+
+                let head = String::from_utf8_lossy(&buf[..header_len]).to_string();
+                let headers = head
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| line.split_once(": "))
+                    .map(|(key, value)| (key.to_ascii_lowercase(), value.trim().to_string()))
+                    .collect();
+                let body = buf[header_len..header_len + content_length].to_vec();
+                received.lock().unwrap().push(Request { headers, body });
+
+                let response = format!(
+                    "HTTP/1.1 {status} Status\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (url, requests)
+    }
+
+    fn event() -> SinkEvent {
+        SinkEvent {
+            kind: SinkEventKind::Committed,
+            block_number: 1,
+            block_hash: BlockHash::ZERO,
+            logs: vec![shadow_log(1, Address::ZERO)],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signed_delivery() {
+        let (url, requests) = serve(vec![500, 200]).await;
+        let sink = WebhookSink::new(url, Some("secret".to_string()));
+
+        // Failed requests are not retried by the sink itself.
+        assert!(sink.send(&event()).await.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        sink.send(&event()).await.unwrap();
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 2);
+        let request = &requests[1];
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["type"], "committed");
+        assert_eq!(body["logs"][0]["blockNumber"], 1);
+
+        let timestamp = request.header(TIMESTAMP_HEADER).parse().unwrap();
+        assert_eq!(
+            request.header(SIGNATURE_HEADER),
+            WebhookSink::sign("secret", timestamp, &request.body)
+        );
+        assert_eq!(
+            requests[0].header("X-Shadow-Event-Id"),
+            requests[1].header("X-Shadow-Event-Id")
+        );
+    }
+
+    #[test]
+    fn test_signature() {
+        let signature = WebhookSink::sign("secret", 1700000000, b"{}");
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), 7 + 64);
+        assert_ne!(signature, WebhookSink::sign("other", 1700000000, b"{}"));
+    }
+}
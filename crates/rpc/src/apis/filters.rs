@@ -0,0 +1,153 @@
+//! Contains logic for shadow RPC equivalents of `eth_newFilter`, `eth_newBlockFilter`,
+//! `eth_getFilterChanges`, `eth_getFilterLogs` and `eth_uninstallFilter`.
+
+use std::str::FromStr;
+
+use jsonrpsee::{
+    core::RpcResult,
+    types::{error::INVALID_PARAMS_CODE, ErrorObject},
+};
+use reth_primitives::BlockNumberOrTag;
+use serde::{Deserialize, Serialize};
+
+use shadow_reth_common::ToLowerHex;
+
+use super::{get_logs, Commitment, FormattedRpcLog, GetLogsParameters, SubscribeParameters};
+use crate::{
+    filters::{FilterKind, FilterLagged},
+    shadow_logs_query::{block_logs, ValidatedQueryParams},
+    BlockResolver, ShadowRpc,
+};
+
+/// Changes reported by `shadow_getFilterChanges`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FilterChanges {
+    /// Shadow logs of the blocks indexed since the last poll of a log filter, including the logs
+    /// of reverted blocks with `removed: true`.
+    Logs(Vec<FormattedRpcLog>),
+    /// Hashes of the blocks indexed since the last poll of a block filter.
+    Hashes(Vec<String>),
+}
+
+/// Error code of filters which missed blocks, the resource unavailable error of EIP-1474.
+pub(crate) const FILTER_LAGGED_CODE: i32 = -32002;
+
+/// Returns an error for an unknown or expired filter.
+fn filter_not_found(id: &str) -> ErrorObject<'static> {
+    ErrorObject::owned::<()>(INVALID_PARAMS_CODE, format!("filter not found: {id}"), None)
+}
+
+pub(crate) async fn new_filter<P>(
+    rpc: &ShadowRpc<P>,
+    params: GetLogsParameters,
+) -> RpcResult<String>
+where
+    P: BlockResolver,
+{
+    if params.block_hash.is_some() {
+        return Err(ErrorObject::owned::<()>(
+            INVALID_PARAMS_CODE,
+            "invalid blockHash: filters select a block range",
+            None,
+        ))
+    }
+    if params.commitment.is_some_and(|commitment| commitment != Commitment::Latest) {
+        return Err(ErrorObject::owned::<()>(
+            INVALID_PARAMS_CODE,
+            "invalid commitment: filters report blocks as they are indexed, use shadow_subscribe",
+            None,
+        ))
+    }
+    // Reject malformed filters up front, rather than failing once they are polled.
+    let _ = ValidatedQueryParams::from_get_logs_parameters(&rpc.resolver, params.clone()).await?;
+
+    Ok(rpc.filters.install(FilterKind::Logs(params), rpc.indexed_block_receiver.resubscribe()))
+}
+
+pub(crate) fn new_block_filter<P>(rpc: &ShadowRpc<P>) -> String {
+    rpc.filters.install(FilterKind::Blocks, rpc.indexed_block_receiver.resubscribe())
+}
+
+pub(crate) async fn get_filter_changes<P>(
+    rpc: &ShadowRpc<P>,
+    id: String,
+) -> RpcResult<FilterChanges>
+where
+    P: BlockResolver,
+{
+    let (kind, events) = rpc.filters.poll(&id, true).ok_or_else(|| filter_not_found(&id))?;
+    let events = events.map_err(|FilterLagged { lag_count }| {
+        let recovery = match &kind {
+            FilterKind::Logs(_) => "read its logs with shadow_getFilterLogs or reinstall it",
+            FilterKind::Blocks => "reinstall it",
+        };
+        ErrorObject::owned::<()>(
+            FILTER_LAGGED_CODE,
+            format!("filter {id} was not polled in time and missed {lag_count} blocks; {recovery}"),
+            None,
+        )
+    })?;
+
+    match kind {
+        FilterKind::Blocks => Ok(FilterChanges::Hashes(
+            events
+                .into_iter()
+                .filter(|event| !event.is_reverted())
+                .map(|event| event.block_hash().to_lower_hex())
+                .collect(),
+        )),
+        FilterKind::Logs(params) => {
+            let bound =
+                |block: &Option<String>| match block.as_deref().map(BlockNumberOrTag::from_str) {
+                    Some(Ok(BlockNumberOrTag::Number(number))) => Some(number),
+                    _ => None,
+                };
+            let (from_block, to_block) = (bound(&params.from_block), bound(&params.to_block));
+            let subscribe_params = SubscribeParameters {
+                address: params.address,
+                topics: params.topics,
+                ..Default::default()
+            };
+
+            let mut logs = Vec::new();
+            for event in events {
+                logs.extend(
+                    block_logs(
+                        &rpc.resolver,
+                        rpc.storage.as_ref(),
+                        subscribe_params.clone(),
+                        event,
+                    )
+                    .await?
+                    .into_iter()
+                    .filter(|log| from_block.map_or(true, |from| log.block_number >= from))
+                    .filter(|log| to_block.map_or(true, |to| log.block_number <= to))
+                    .map(|log| FormattedRpcLog::new(log, rpc.log_format)),
+                );
+            }
+            Ok(FilterChanges::Logs(logs))
+        }
+    }
+}
+
+pub(crate) async fn get_filter_logs<P>(
+    rpc: &ShadowRpc<P>,
+    id: String,
+) -> RpcResult<Vec<FormattedRpcLog>>
+where
+    P: BlockResolver,
+{
+    match rpc.filters.poll(&id, false).ok_or_else(|| filter_not_found(&id))? {
+        (FilterKind::Logs(params), _) => get_logs(rpc, params).await,
+        (FilterKind::Blocks, _) => Err(ErrorObject::owned::<()>(
+            INVALID_PARAMS_CODE,
+            format!("filter {id} is a block filter, not a log filter"),
+            None,
+        )),
+    }
+}
+
+pub(crate) fn uninstall_filter<P>(rpc: &ShadowRpc<P>, id: String) -> bool {
+    rpc.filters.uninstall(&id)
+}
@@ -1,13 +1,15 @@
 //! Contains logic for a shadow RPC equivalent of `eth_getLogs`.
 
-use super::{AddressRepresentation, RpcLog};
+use super::{AddressRepresentation, Commitment, FormattedRpcLog, TopicRepresentation};
 use jsonrpsee::core::RpcResult;
-use reth_provider::{BlockNumReader, BlockReaderIdExt};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    shadow_logs_query::{exec_query, ValidatedQueryParams},
-    ShadowRpc,
+    shadow_logs_query::{
+        ensure_committed, ensure_indexed, ensure_not_pruned, ensure_within_block_range,
+        exec_limited_query, ValidatedQueryParams,
+    },
+    BlockResolver, ShadowRpc,
 };
 /// Unvalidated parameters for `shadow_getLogs` RPC requests.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -22,25 +24,47 @@ pub struct GetLogsParameters {
     pub from_block: Option<String>,
     /// End of block range from which logs should originate.
     pub to_block: Option<String>,
-    /// Array of 32-byte data topics.
-    pub topics: Option<Vec<String>>,
+    /// Array of 32-byte data topics, by position. Each position is `null`, a topic, or an array
+    /// of alternative topics.
+    pub topics: Option<Vec<Option<TopicRepresentation>>>,
+    /// Only return the logs of blocks which have reached this commitment, which defaults to
+    /// [`Commitment::Latest`].
+    pub commitment: Option<Commitment>,
 }
 
 pub(crate) async fn get_logs<P>(
     rpc: &ShadowRpc<P>,
     params: GetLogsParameters,
-) -> RpcResult<Vec<RpcLog>>
+) -> RpcResult<Vec<FormattedRpcLog>>
 where
-    P: BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
+    P: BlockResolver,
 {
-    let validated_param_objs =
-        ValidatedQueryParams::from_get_logs_parameters(&rpc.provider, params)?;
+    let mut validated_param_objs =
+        ValidatedQueryParams::from_get_logs_parameters(&rpc.resolver, params.clone()).await?;
+    validated_param_objs.block_id =
+        ensure_indexed(rpc.storage.as_ref(), &params, validated_param_objs.block_id).await?;
+    let Some(block_id) = ensure_committed(
+        &rpc.resolver,
+        rpc.storage.as_ref(),
+        params.commitment.unwrap_or_default(),
+        validated_param_objs.block_id,
+    )
+    .await?
+    else {
+        return Ok(Vec::new())
+    };
+    validated_param_objs.block_id = block_id;
 
-    let mut results: Vec<RpcLog> = vec![];
+    let mut results: Vec<FormattedRpcLog> = vec![];
     for query_params in [validated_param_objs] {
-        let intermediate_results = exec_query(query_params, &rpc.sqlite_manager.pool).await?;
-        let mut result =
-            intermediate_results.into_iter().map(RpcLog::from).collect::<Vec<RpcLog>>();
+        ensure_within_block_range(&rpc.limits, &query_params.block_id)?;
+        ensure_not_pruned(&rpc.resolver, rpc.storage.as_ref(), &query_params.block_id).await?;
+        let intermediate_results =
+            exec_limited_query(query_params, rpc.storage.as_ref(), &rpc.limits).await?;
+        let mut result = intermediate_results
+            .into_iter()
+            .map(|log| FormattedRpcLog::new(log, rpc.log_format))
+            .collect::<Vec<FormattedRpcLog>>();
         results.append(&mut result);
     }
 
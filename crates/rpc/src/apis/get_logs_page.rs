@@ -0,0 +1,148 @@
+//! Contains logic for `shadow_getLogsPage`, a paginated variant of `shadow_getLogs`.
+
+use jsonrpsee::{
+    core::RpcResult,
+    types::{
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject,
+    },
+};
+use serde::{Deserialize, Serialize};
+use shadow_reth_common::{LogPage, LogPosition};
+
+use super::{FormattedRpcLog, GetLogsParameters};
+use crate::{
+    shadow_logs_query::{
+        ensure_committed, ensure_indexed, ensure_not_pruned, ValidatedQueryParams,
+    },
+    BlockResolver, ShadowRpc,
+};
+
+/// Unvalidated parameters for `shadow_getLogsPage` RPC requests.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GetLogsPageParameters {
+    /// Filter selecting the logs, as for `shadow_getLogs`.
+    #[serde(flatten)]
+    pub filter: GetLogsParameters,
+    /// Cursor returned with the previous page. The first page is requested without a cursor.
+    pub cursor: Option<String>,
+    /// Maximum number of logs in the page, which defaults to and is capped at the configured
+    /// maximum number of results.
+    pub limit: Option<usize>,
+}
+
+/// A page of logs returned by `shadow_getLogsPage`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LogsPage {
+    /// Logs of the page, ordered by block number and log index.
+    pub logs: Vec<FormattedRpcLog>,
+    /// Cursor from which the next page continues, or `None` if this is the last page.
+    pub cursor: Option<String>,
+}
+
+/// Returns the page of logs after the cursor in `params`.
+///
+/// Pages only contain the logs of canonical blocks, as the position of a log, which its cursor
+/// is keyed on, is shared by the logs of reverted blocks. Unlike `shadow_getLogs`, the block range
+/// is not limited, as every page is bounded by its limit.
+pub(crate) async fn get_logs_page<P>(
+    rpc: &ShadowRpc<P>,
+    params: GetLogsPageParameters,
+) -> RpcResult<LogsPage>
+where
+    P: BlockResolver,
+{
+    let after = params.cursor.as_deref().map(decode_cursor).transpose()?;
+    let limit =
+        params.limit.map_or(rpc.limits.max_results, |limit| limit.min(rpc.limits.max_results));
+    if limit == 0 {
+        return Err(ErrorObject::owned::<()>(
+            INVALID_PARAMS_CODE,
+            "invalid limit: must be positive",
+            None,
+        ))
+    }
+
+    let mut query_params =
+        ValidatedQueryParams::from_get_logs_parameters(&rpc.resolver, params.filter.clone())
+            .await?;
+    query_params.block_id =
+        ensure_indexed(rpc.storage.as_ref(), &params.filter, query_params.block_id).await?;
+    let Some(block_id) = ensure_committed(
+        &rpc.resolver,
+        rpc.storage.as_ref(),
+        params.filter.commitment.unwrap_or_default(),
+        query_params.block_id,
+    )
+    .await?
+    else {
+        return Ok(LogsPage { logs: Vec::new(), cursor: None })
+    };
+    query_params.block_id = block_id;
+    ensure_not_pruned(&rpc.resolver, rpc.storage.as_ref(), &query_params.block_id).await?;
+
+    let page = LogPage { after, limit, include_removed: false };
+    let logs = rpc
+        .storage
+        .get_logs_page(&query_params.into(), &page)
+        .await
+        .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?;
+
+    // A page which is not full is the last one.
+    let cursor = match logs.last() {
+        Some(log) if logs.len() == limit => Some(encode_cursor(LogPosition {
+            block_number: log.block_number,
+            block_log_index: log.block_log_index,
+        })),
+        _ => None,
+    };
+    Ok(LogsPage {
+        logs: logs.into_iter().map(|log| FormattedRpcLog::new(log, rpc.log_format)).collect(),
+        cursor,
+    })
+}
+
+/// Encodes the position of the last log of a page as an opaque cursor.
+fn encode_cursor(position: LogPosition) -> String {
+    format!("0x{:016x}{:016x}", position.block_number, position.block_log_index)
+}
+
+/// Decodes a cursor returned by [`encode_cursor`].
+pub(crate) fn decode_cursor(cursor: &str) -> RpcResult<LogPosition> {
+    let invalid_cursor =
+        || ErrorObject::owned::<()>(INVALID_PARAMS_CODE, format!("invalid cursor: {cursor}"), None);
+    // Only ASCII hex digits are accepted, so that the cursor can be split at a byte offset and no
+    // sign is parsed.
+    let hex = cursor
+        .strip_prefix("0x")
+        .filter(|hex| hex.len() == 32 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or_else(invalid_cursor)?;
+    let (block_number, block_log_index) = hex.split_at(16);
+    Ok(LogPosition {
+        block_number: u64::from_str_radix(block_number, 16).map_err(|_| invalid_cursor())?,
+        block_log_index: u64::from_str_radix(block_log_index, 16).map_err(|_| invalid_cursor())?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonrpsee::types::error::INVALID_PARAMS_CODE;
+    use shadow_reth_common::LogPosition;
+
+    use super::{decode_cursor, encode_cursor};
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let position = LogPosition { block_number: 18870001, block_log_index: u64::MAX };
+        assert_eq!(decode_cursor(&encode_cursor(position)).unwrap(), position);
+
+        let signed = format!("0x+{}", "0".repeat(31));
+        // 32 bytes, split within the two-byte `é`.
+        let non_ascii = format!("0x{}\u{e9}{}", "0".repeat(15), "0".repeat(15));
+        for cursor in ["", "0x", "0x00", &format!("0x{}", "g".repeat(32)), &signed, &non_ascii] {
+            assert_eq!(decode_cursor(cursor).unwrap_err().code(), INVALID_PARAMS_CODE);
+        }
+    }
+}
@@ -1,27 +1,55 @@
+mod filters;
 mod get_logs;
+mod get_logs_page;
 mod subscribe;
 mod types;
 
+pub(crate) use filters::*;
 pub(crate) use get_logs::*;
+pub(crate) use get_logs_page::*;
 pub(crate) use subscribe::*;
+pub use types::LogFormat;
 pub(crate) use types::*;
 
-use crate::{ShadowRpc, ShadowRpcApiServer};
+use crate::{BlockResolver, ShadowRpc, ShadowRpcApiServer};
 use jsonrpsee::{
     core::{async_trait, RpcResult, SubscriptionResult},
     PendingSubscriptionSink,
 };
-use reth_provider::{BlockNumReader, BlockReaderIdExt};
 
 #[async_trait]
 impl<P> ShadowRpcApiServer for ShadowRpc<P>
 where
-    P: BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
+    P: BlockResolver,
 {
-    async fn get_logs(&self, params: GetLogsParameters) -> RpcResult<Vec<RpcLog>> {
+    async fn get_logs(&self, params: GetLogsParameters) -> RpcResult<Vec<FormattedRpcLog>> {
         get_logs(self, params).await
     }
 
+    async fn get_logs_page(&self, params: GetLogsPageParameters) -> RpcResult<LogsPage> {
+        get_logs_page(self, params).await
+    }
+
+    async fn new_filter(&self, params: GetLogsParameters) -> RpcResult<String> {
+        new_filter(self, params).await
+    }
+
+    async fn new_block_filter(&self) -> RpcResult<String> {
+        Ok(new_block_filter(self))
+    }
+
+    async fn get_filter_changes(&self, id: String) -> RpcResult<FilterChanges> {
+        get_filter_changes(self, id).await
+    }
+
+    async fn get_filter_logs(&self, id: String) -> RpcResult<Vec<FormattedRpcLog>> {
+        get_filter_logs(self, id).await
+    }
+
+    async fn uninstall_filter(&self, id: String) -> RpcResult<bool> {
+        Ok(uninstall_filter(self, id))
+    }
+
     async fn subscribe(
         &self,
         pending: PendingSubscriptionSink,
@@ -1,26 +1,45 @@
 //! Contains logic for a shadow RPC equivalent of `eth_subscribe` of `type` `logs`.
 
-use super::AddressRepresentation;
+use std::sync::Arc;
+
+use super::{decode_cursor, AddressRepresentation, Commitment, TopicRepresentation};
 use crate::{
-    apis::RpcLog,
-    shadow_logs_query::{exec_query, ValidatedQueryParams},
-    ShadowRpc,
+    apis::{FormattedRpcLog, LogFormat},
+    shadow_logs_query::{
+        block_logs, committed_head, ensure_not_pruned, resolve_block_tag, ValidatedBlockIdParam,
+        ValidatedQueryParams,
+    },
+    BlockResolver, ShadowRpc,
 };
 use jsonrpsee::{
-    core::SubscriptionResult,
-    types::{error::INTERNAL_ERROR_CODE, ErrorObject},
+    core::{RpcResult, SubscriptionResult},
+    types::{
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject,
+    },
     PendingSubscriptionSink, SubscriptionMessage, SubscriptionSink,
 };
-use reth_provider::{BlockNumReader, BlockReaderIdExt};
 use reth_tracing::tracing::warn;
 use serde::{Deserialize, Serialize};
-use shadow_reth_common::ShadowSqliteDb;
+use shadow_reth_common::{
+    IndexedBlockEvent, LogFilter, LogPage, LogPosition, ShadowDbError, ShadowLog, ShadowStorage,
+};
 use tokio::sync::broadcast::{error::RecvError, Receiver};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
 pub struct SubscribeParameters {
     pub address: Option<AddressRepresentation>,
-    pub topics: Option<Vec<String>>,
+    pub topics: Option<Vec<Option<TopicRepresentation>>>,
+    /// Block from which logs are delivered. The logs of blocks which have already been indexed
+    /// are backfilled before the logs of new blocks.
+    pub from_block: Option<String>,
+    /// Resumes a subscription after the log at this cursor, which encodes the `blockNumber` and
+    /// `logIndex` of the log as by `shadow_getLogsPage`.
+    pub cursor: Option<String>,
+    /// Holds back the logs of a block until it has reached this commitment, which defaults to
+    /// [`Commitment::Latest`].
+    pub commitment: Option<Commitment>,
 }
 
 pub(crate) async fn subscribe<P>(
@@ -29,63 +48,234 @@ pub(crate) async fn subscribe<P>(
     params: SubscribeParameters,
 ) -> SubscriptionResult
 where
-    P: BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
+    P: BlockResolver,
 {
+    // Subscribe before the starting point is read, so that no block indexed in between is missed.
+    let indexed_block_receiver = rpc.indexed_block_receiver.resubscribe();
+
+    // Reject malformed filters up front, rather than failing once the first block is indexed.
+    let start = match start_position(rpc, &params).await {
+        Ok(start) => start,
+        Err(err) => {
+            pending.reject(err).await;
+            return Ok(())
+        }
+    };
+
     let sink = pending.accept().await?;
+    let commitment = params.commitment.unwrap_or_default();
     tokio::spawn({
-        let provider = rpc.provider.clone();
-        let sqlite_manager = rpc.sqlite_manager.clone();
-        let indexed_block_hash_receiver = rpc.indexed_block_hash_receiver.resubscribe();
+        let subscription = Subscription {
+            resolver: rpc.resolver.clone(),
+            storage: rpc.storage.clone(),
+            sink,
+            params,
+            log_format: rpc.log_format,
+            page_size: rpc.limits.max_results.max(1),
+            commitment,
+            next_block: start.block_number,
+            after: start.after,
+        };
         async move {
-            let _ = handle_accepted(
-                provider,
-                sqlite_manager,
-                indexed_block_hash_receiver,
-                sink,
-                params,
-            )
-            .await;
+            let _ = subscription.run(indexed_block_receiver).await;
         }
     });
 
     Ok(())
 }
 
-async fn handle_accepted(
-    provider: impl BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
-    sqlite_manager: ShadowSqliteDb,
-    mut indexed_block_hash_receiver: Receiver<String>,
-    accepted_sink: SubscriptionSink,
+/// Where a subscription starts delivering logs.
+#[derive(Debug)]
+struct StartPosition {
+    /// The first block whose logs are delivered.
+    block_number: u64,
+    /// Only the logs of `block_number` after this position are delivered.
+    after: Option<LogPosition>,
+}
+
+/// Validates `params`, and returns the position from which the subscription starts. Without a
+/// `fromBlock` or cursor, it starts after the last block which has reached its commitment.
+async fn start_position<P>(
+    rpc: &ShadowRpc<P>,
+    params: &SubscribeParameters,
+) -> RpcResult<StartPosition>
+where
+    P: BlockResolver,
+{
+    let _ = ValidatedQueryParams::validate_addresses(params.address.clone())?;
+    let _ = ValidatedQueryParams::validate_topics(params.topics.clone())?;
+
+    let start = match (&params.from_block, &params.cursor) {
+        (Some(_), Some(_)) => {
+            return Err(ErrorObject::owned::<()>(
+                INVALID_PARAMS_CODE,
+                "invalid params: fromBlock and cursor are mutually exclusive",
+                None,
+            ))
+        }
+        (Some(from_block), None) => StartPosition {
+            block_number: resolve_block_tag(&rpc.resolver, Some(from_block)).await?,
+            after: None,
+        },
+        (None, Some(cursor)) => {
+            let after = decode_cursor(cursor)?;
+            StartPosition { block_number: after.block_number, after: Some(after) }
+        }
+        (None, None) => {
+            let commitment = params.commitment.unwrap_or_default();
+            let head = committed_head(&rpc.resolver, rpc.storage.as_ref(), commitment).await?;
+            return Ok(StartPosition { block_number: head.map_or(0, |head| head + 1), after: None })
+        }
+    };
+
+    let block_id = ValidatedBlockIdParam::BlockRange(start.block_number, start.block_number);
+    ensure_not_pruned(&rpc.resolver, rpc.storage.as_ref(), &block_id).await?;
+    Ok(start)
+}
+
+/// Returns an internal error for a failure of the shadow database.
+fn internal_error(e: ShadowDbError) -> ErrorObject<'static> {
+    ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
+}
+
+/// An accepted subscription, which delivers the logs of every block exactly once.
+struct Subscription<P> {
+    resolver: P,
+    storage: Arc<dyn ShadowStorage>,
+    sink: SubscriptionSink,
     params: SubscribeParameters,
-) -> Result<(), ErrorObject<'static>> {
-    loop {
-        match indexed_block_hash_receiver.recv().await {
-            Ok(block_hash) => {
-                let query_params = ValidatedQueryParams::from_subscribe_parameters(
-                    &provider,
-                    params.clone(),
-                    block_hash,
-                )?;
-                let intermediate_results = exec_query(query_params, &sqlite_manager.pool).await?;
-                for result in intermediate_results.into_iter().map(RpcLog::from) {
-                    let message = SubscriptionMessage::from_json(&result).map_err(|e| {
-                        ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
-                    })?;
-
-                    accepted_sink.send(message).await.map_err(|e| {
-                        ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
-                    })?;
+    log_format: LogFormat,
+    /// Maximum number of logs read from the storage at once while backfilling.
+    page_size: usize,
+    /// Blocks are delivered once they have reached this commitment.
+    commitment: Commitment,
+    /// The first block whose logs have not been delivered yet.
+    next_block: u64,
+    /// Only the logs of `next_block` after this position are delivered, if the subscription was
+    /// resumed from a cursor. Cleared once `next_block` has been delivered.
+    after: Option<LogPosition>,
+}
+
+impl<P> Subscription<P>
+where
+    P: BlockResolver,
+{
+    /// Backfills the logs of the blocks indexed before the subscription was accepted, then
+    /// delivers the logs of the blocks indexed by the ExEx until either side goes away.
+    async fn run(
+        mut self,
+        mut indexed_block_receiver: Receiver<IndexedBlockEvent>,
+    ) -> RpcResult<()> {
+        self.backfill().await?;
+
+        loop {
+            match indexed_block_receiver.recv().await {
+                Ok(event) if self.commitment == Commitment::Latest => {
+                    self.deliver_block(event).await?
+                }
+                // Every indexed block may release held back blocks, which are read from the
+                // storage. Reorgs are assumed not to reach past the commitment, so the held back
+                // logs are never delivered with `removed: true`.
+                Ok(_) => self.backfill().await?,
+                Err(RecvError::Lagged(lag_count)) => {
+                    // The missed blocks have been stored, so they are read back from the storage.
+                    // Reverts of blocks which were delivered before the lag are not replayed.
+                    warn!(lag_count, "subscription lagged; backfilling the missed blocks");
+                    self.backfill().await?;
+                }
+                Err(RecvError::Closed) => {
+                    // The ExEx has exited, so we should exit as well.
+                    break;
                 }
             }
-            Err(RecvError::Lagged(lag_count)) => {
-                warn!("lagged by {} messages; consider increasing buffer if syncing", lag_count);
+        }
+
+        Ok(())
+    }
+
+    /// Delivers the stored logs of the canonical blocks from `next_block` up to the last block
+    /// which has reached the commitment, skipping the logs up to and including `after`.
+    async fn backfill(&mut self) -> RpcResult<()> {
+        let head = committed_head(&self.resolver, self.storage.as_ref(), self.commitment).await?;
+        let Some(head) = head.filter(|head| *head >= self.next_block) else { return Ok(()) };
+        let mut after = self.after.take();
+
+        let query_params = ValidatedQueryParams {
+            block_id: ValidatedBlockIdParam::BlockRange(self.next_block, head),
+            addresses: ValidatedQueryParams::validate_addresses(self.params.address.clone())?,
+            topics: ValidatedQueryParams::validate_topics(self.params.topics.clone())?,
+        };
+        let filter = LogFilter::from(query_params);
+        loop {
+            let page = LogPage { after, limit: self.page_size, include_removed: false };
+            let logs = self.storage.get_logs_page(&filter, &page).await.map_err(internal_error)?;
+            let last_page = logs.len() < self.page_size;
+            after = logs.last().map(|log| LogPosition {
+                block_number: log.block_number,
+                block_log_index: log.block_log_index,
+            });
+            self.send(logs).await?;
+            if last_page {
+                break
+            }
+        }
+
+        self.next_block = head + 1;
+        Ok(())
+    }
+
+    /// Delivers the logs of a block indexed by the ExEx. Committed blocks which have already been
+    /// backfilled are skipped, as are reverted blocks whose logs have not been delivered.
+    async fn deliver_block(&mut self, event: IndexedBlockEvent) -> RpcResult<()> {
+        let block_number = self
+            .storage
+            .indexed_block(event.block_hash())
+            .await
+            .map_err(internal_error)?
+            .map(|block| block.block_number);
+
+        let mut after = None;
+        match (event, block_number) {
+            (IndexedBlockEvent::Committed(_), Some(block_number)) => {
+                if block_number < self.next_block {
+                    return Ok(())
+                }
+                self.next_block = block_number + 1;
+                after = self.after.take().filter(|after| after.block_number == block_number);
             }
-            Err(RecvError::Closed) => {
-                // The ExEx has exited, so we should exit as well.
-                break;
+            (IndexedBlockEvent::Reverted(_), Some(block_number)) => {
+                if block_number >= self.next_block {
+                    return Ok(())
+                }
+                // The block of the cursor has been reverted along with this block, so all logs
+                // of its replacement are delivered.
+                self.next_block = block_number;
+                self.after = None;
             }
+            // Blocks which are not recorded in the storage cannot be ordered, so they are
+            // delivered as they are.
+            (_, None) => {}
         }
+
+        let mut logs =
+            block_logs(&self.resolver, self.storage.as_ref(), self.params.clone(), event).await?;
+        if let Some(after) = after {
+            logs.retain(|log| log.block_log_index > after.block_log_index);
+        }
+        self.send(logs).await
     }
 
-    Ok(())
+    /// Sends `logs` to the subscriber.
+    async fn send(&self, logs: Vec<ShadowLog>) -> RpcResult<()> {
+        for result in logs.into_iter().map(|log| FormattedRpcLog::new(log, self.log_format)) {
+            let message = SubscriptionMessage::from_json(&result)
+                .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?;
+
+            self.sink
+                .send(message)
+                .await
+                .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?;
+        }
+        Ok(())
+    }
 }
@@ -10,10 +10,126 @@ pub enum AddressRepresentation {
     String(String),
 }
 
-/// Inner result type for `shadow_getLogs` and `shadow_subscribe` RPC responses.
+/// A position of the `topics` filter parameter, which matches either a single topic or any of an
+/// array of alternatives. Positions which are `null` match any topic.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum TopicRepresentation {
+    /// Matches logs with any of the given topics at this position.
+    ArrayOfStrings(Vec<String>),
+    /// Matches logs with the given topic at this position.
+    String(String),
+}
+
+/// How deep in the chain a block must be before its logs are returned by `shadow_getLogs` or
+/// delivered by `shadow_subscribe`, encoded as `"latest"`, `"safe"`, `"finalized"` or
+/// `{"confirmations": n}`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Commitment {
+    /// Every block indexed by the ExEx.
+    #[default]
+    Latest,
+    /// Blocks on top of which at least this many blocks have been indexed.
+    Confirmations(u64),
+    /// Blocks up to the `safe` block of the node.
+    Safe,
+    /// Blocks up to the `finalized` block of the node.
+    Finalized,
+}
+
+/// Encoding of the logs returned by `shadow_getLogs` and `shadow_subscribe`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The standard Ethereum log object, as returned by `eth_getLogs`, see [`RpcLog`].
+    #[default]
+    Standard,
+    /// The encoding of earlier releases, kept for existing consumers, see [`LegacyRpcLog`].
+    Legacy,
+}
+
+/// A log returned by `shadow_getLogs` and `shadow_subscribe`, in the configured [`LogFormat`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FormattedRpcLog {
+    /// A log in the [`LogFormat::Standard`] encoding.
+    Standard(RpcLog),
+    /// A log in the [`LogFormat::Legacy`] encoding.
+    Legacy(LegacyRpcLog),
+}
+
+impl FormattedRpcLog {
+    /// Encodes `log` in the given format.
+    pub fn new(log: ShadowLog, format: LogFormat) -> Self {
+        match format {
+            LogFormat::Standard => Self::Standard(RpcLog::from(log)),
+            LogFormat::Legacy => Self::Legacy(LegacyRpcLog::from(log)),
+        }
+    }
+}
+
+/// Inner result type for `shadow_getLogs` and `shadow_subscribe` RPC responses, which is the
+/// standard Ethereum log object with the block timestamp and transaction log index as extras.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct RpcLog {
+    /// Contract address from which the log originated.
+    pub address: String,
+    /// Topics of the log, of which there are zero to four.
+    pub topics: Vec<String>,
+    /// Contains one or more 32-byte non-indexed arguments of the log.
+    pub data: String,
+    /// Hash of block from which the log originated.
+    pub block_hash: String,
+    /// Block number from which the log originated, as a quantity.
+    pub block_number: String,
+    /// Timestamp of the block from which the log originated, as a quantity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_timestamp: Option<String>,
+    /// Hash of transaction from which the log originated.
+    pub transaction_hash: String,
+    /// Index of the transaction in its block, as a quantity.
+    pub transaction_index: String,
+    /// Index of the log in its block, as a quantity.
+    pub log_index: String,
+    /// Index of the log in its transaction, as a quantity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transaction_log_index: Option<String>,
+    /// Indicates whether the log has been removed from the canonical chain.
+    pub removed: bool,
+}
+
+impl From<ShadowLog> for RpcLog {
+    fn from(value: ShadowLog) -> Self {
+        Self {
+            address: value.address,
+            topics: [value.topic_0, value.topic_1, value.topic_2, value.topic_3]
+                .into_iter()
+                .flatten()
+                .collect(),
+            data: value.data.unwrap_or_else(|| "0x".to_string()),
+            block_hash: value.block_hash,
+            block_number: quantity(value.block_number),
+            block_timestamp: Some(quantity(value.block_timestamp)),
+            transaction_hash: value.transaction_hash,
+            transaction_index: quantity(value.transaction_index),
+            log_index: quantity(value.block_log_index),
+            transaction_log_index: Some(quantity(value.transaction_log_index)),
+            removed: value.removed,
+        }
+    }
+}
+
+/// Encodes an integer as a `0x`-prefixed hex quantity without leading zeros.
+fn quantity(value: u64) -> String {
+    format!("{value:#x}")
+}
+
+/// Log encoding of earlier releases, with an unprefixed, zero-padded hex block number, decimal
+/// indices, and exactly four topics padded with `null`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyRpcLog {
     /// Contract address from which the log originated.
     pub address: String,
     /// Hash of block from which the log originated.
@@ -34,7 +150,7 @@ pub struct RpcLog {
     pub transaction_index: String,
 }
 
-impl From<ShadowLog> for RpcLog {
+impl From<ShadowLog> for LegacyRpcLog {
     fn from(value: ShadowLog) -> Self {
         Self {
             address: value.address,
@@ -49,3 +165,49 @@ impl From<ShadowLog> for RpcLog {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use shadow_reth_common::ShadowLog;
+
+    use super::{FormattedRpcLog, LogFormat};
+
+    #[test]
+    fn test_standard_log_encoding() {
+        let log = ShadowLog {
+            address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            block_hash: format!("0x{}", "01".repeat(32)),
+            block_log_index: 16,
+            block_number: 18870001,
+            block_timestamp: 1703595275,
+            transaction_index: 0,
+            transaction_hash: format!("0x{}", "02".repeat(32)),
+            transaction_log_index: 2,
+            removed: false,
+            data: None,
+            topic_0: None,
+            topic_1: None,
+            topic_2: None,
+            topic_3: None,
+        };
+
+        let encoded = serde_json::to_value(FormattedRpcLog::new(log, LogFormat::Standard)).unwrap();
+        assert_eq!(
+            encoded,
+            json!({
+                "address": "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2",
+                "topics": [],
+                "data": "0x",
+                "blockHash": format!("0x{}", "01".repeat(32)),
+                "blockNumber": "0x11feef1",
+                "blockTimestamp": "0x658acd0b",
+                "transactionHash": format!("0x{}", "02".repeat(32)),
+                "transactionIndex": "0x0",
+                "logIndex": "0x10",
+                "transactionLogIndex": "0x2",
+                "removed": false,
+            })
+        );
+    }
+}
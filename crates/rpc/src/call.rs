@@ -0,0 +1,267 @@
+//! `shadow_call` and `shadow_estimateGas`, which execute calls against the historical state of the
+//! node with the contracts in `shadow.json` replaced by their shadow bytecode, and
+//! `shadow_simulate`, which replays historical blocks with candidate shadow bytecode.
+
+use std::{collections::HashMap, sync::Arc};
+
+use jsonrpsee::{
+    core::{async_trait, RpcResult},
+    proc_macros::rpc,
+    types::{
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject,
+    },
+};
+use reth_primitives::{Address, BlockId, BlockNumberOrTag, Bytes, ChainSpec, Header, U64};
+use reth_provider::{
+    BlockReaderIdExt, ChainSpecProvider, StateProviderBox, StateProviderFactory, TransactionVariant,
+};
+use serde::{Deserialize, Serialize};
+use shadow_reth_common::{ShadowCallError, ToLowerHex};
+use shadow_reth_exex::{CallRequest, ShadowCaller, SimulatedTransaction, StateOverride};
+
+use crate::{
+    apis::{FormattedRpcLog, LogFormat},
+    shadow_logs_query::LIMIT_EXCEEDED_CODE,
+};
+
+/// Error code of reverted calls, with the revert data as error data, as returned by `eth_call`.
+const REVERTED_CODE: i32 = 3;
+
+/// Error code of calls which halted exceptionally, e.g. because they ran out of gas.
+const HALTED_CODE: i32 = -32000;
+
+/// Maximum number of blocks replayed by a single `shadow_simulate` request.
+const MAX_SIMULATED_BLOCKS: u64 = 1_000;
+
+/// Parameters of `shadow_simulate`.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulateParameters {
+    /// Candidate bytecode keyed by address, which is added to the contracts in `shadow.json`,
+    /// replacing their shadow bytecode for addresses which are already shadowed.
+    pub contracts: HashMap<Address, Bytes>,
+    /// First block which is replayed.
+    pub from_block: BlockNumberOrTag,
+    /// Last block which is replayed, which defaults to `fromBlock`.
+    pub to_block: Option<BlockNumberOrTag>,
+}
+
+/// Result of `shadow_simulate`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationResult {
+    /// Logs emitted by shadowed contracts, as they would be returned by `shadow_getLogs`.
+    pub logs: Vec<FormattedRpcLog>,
+    /// Transactions whose status or gas used differs from their canonical execution.
+    pub transactions: Vec<SimulatedTransaction>,
+}
+
+#[rpc(server, namespace = "shadow")]
+pub trait ShadowCallApi {
+    /// Executes a call with the shadow bytecode on top of the state after the given block, which
+    /// defaults to `latest`, and returns its output, as `eth_call`.
+    #[method(name = "call")]
+    async fn call(
+        &self,
+        request: CallRequest,
+        block: Option<BlockId>,
+        state_overrides: Option<StateOverride>,
+    ) -> RpcResult<Bytes>;
+
+    /// Returns the lowest gas limit with which a call with the shadow bytecode succeeds on top of
+    /// the state after the given block, as `eth_estimateGas`.
+    #[method(name = "estimateGas")]
+    async fn estimate_gas(
+        &self,
+        request: CallRequest,
+        block: Option<BlockId>,
+        state_overrides: Option<StateOverride>,
+    ) -> RpcResult<U64>;
+
+    /// Replays the blocks in the given range with candidate shadow bytecode, without persisting
+    /// anything, and returns the shadow logs along with the transactions whose status or gas used
+    /// differs from their canonical execution.
+    #[method(name = "simulate")]
+    async fn simulate(&self, params: SimulateParameters) -> RpcResult<SimulationResult>;
+}
+
+/// Serves [`ShadowCallApiServer`] from the state of a reth node.
+#[derive(Debug, Clone)]
+pub struct ShadowCall<P> {
+    /// Provides the headers and historical state of blocks.
+    provider: P,
+    /// Executes calls with the shadow bytecode.
+    caller: Arc<ShadowCaller>,
+}
+
+impl<P> ShadowCall<P> {
+    /// Creates a server executing calls with `caller` against the state of `provider`.
+    pub fn new(provider: P, caller: ShadowCaller) -> Self {
+        Self { provider, caller: Arc::new(caller) }
+    }
+}
+
+impl<P> ShadowCall<P>
+where
+    P: StateProviderFactory + BlockReaderIdExt + ChainSpecProvider + Clone + 'static,
+{
+    /// Runs `execute` on a blocking thread, with the state after `block` and its header.
+    async fn execute<T: Send + 'static>(
+        &self,
+        block: Option<BlockId>,
+        execute: impl FnOnce(
+                &ShadowCaller,
+                StateProviderBox,
+                &ChainSpec,
+                &Header,
+            ) -> Result<T, ShadowCallError>
+            + Send
+            + 'static,
+    ) -> RpcResult<T> {
+        let provider = self.provider.clone();
+        let caller = self.caller.clone();
+        let internal_error = |e: &dyn std::fmt::Display| {
+            ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let block = block.unwrap_or(BlockId::Number(BlockNumberOrTag::Latest));
+            let header =
+                provider.header_by_id(block).map_err(|e| internal_error(&e))?.ok_or_else(|| {
+                    ErrorObject::owned::<()>(
+                        -1,
+                        format!("No block found for block id: {block:?}"),
+                        None,
+                    )
+                })?;
+            let state =
+                provider.state_by_block_hash(header.hash_slow()).map_err(|e| internal_error(&e))?;
+            execute(&caller, state, &provider.chain_spec(), &header).map_err(call_error)
+        })
+        .await
+        .map_err(|e| internal_error(&e))?
+    }
+}
+
+#[async_trait]
+impl<P> ShadowCallApiServer for ShadowCall<P>
+where
+    P: StateProviderFactory + BlockReaderIdExt + ChainSpecProvider + Clone + 'static,
+{
+    async fn call(
+        &self,
+        request: CallRequest,
+        block: Option<BlockId>,
+        state_overrides: Option<StateOverride>,
+    ) -> RpcResult<Bytes> {
+        let overrides = state_overrides.unwrap_or_default();
+        self.execute(block, move |caller, state, chain, header| {
+            caller.call(state, chain, header, &request, &overrides)
+        })
+        .await
+    }
+
+    async fn estimate_gas(
+        &self,
+        request: CallRequest,
+        block: Option<BlockId>,
+        state_overrides: Option<StateOverride>,
+    ) -> RpcResult<U64> {
+        let overrides = state_overrides.unwrap_or_default();
+        self.execute(block, move |caller, state, chain, header| {
+            caller.estimate_gas(state, chain, header, &request, &overrides).map(U64::from)
+        })
+        .await
+    }
+
+    async fn simulate(&self, params: SimulateParameters) -> RpcResult<SimulationResult> {
+        let provider = self.provider.clone();
+        let caller = self.caller.clone();
+        let internal_error = |e: &dyn std::fmt::Display| {
+            ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
+        };
+        let block_number = move |provider: &P, tag: BlockNumberOrTag| {
+            provider.convert_block_number(tag).map_err(|e| internal_error(&e))?.ok_or_else(|| {
+                ErrorObject::owned::<()>(
+                    -1,
+                    format!("No block found for block number or tag: {tag}"),
+                    None,
+                )
+            })
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let from_block = block_number(&provider, params.from_block)?;
+            let to_block = block_number(&provider, params.to_block.unwrap_or(params.from_block))?;
+            if from_block == 0 || from_block > to_block {
+                return Err(ErrorObject::owned::<()>(
+                    INVALID_PARAMS_CODE,
+                    format!("invalid block range: {from_block} to {to_block}"),
+                    None,
+                ))
+            }
+            if to_block - from_block >= MAX_SIMULATED_BLOCKS {
+                return Err(ErrorObject::owned::<()>(
+                    LIMIT_EXCEEDED_CODE,
+                    format!("block range exceeds the limit of {MAX_SIMULATED_BLOCKS} blocks"),
+                    None,
+                ))
+            }
+
+            let mut blocks = Vec::new();
+            for number in from_block..=to_block {
+                let not_found = || {
+                    ErrorObject::owned::<()>(
+                        -1,
+                        format!("No block found for block: {number}"),
+                        None,
+                    )
+                };
+                let block = provider
+                    .block_with_senders(number.into(), TransactionVariant::WithHash)
+                    .map_err(|e| internal_error(&e))?
+                    .ok_or_else(not_found)?;
+                let receipts = provider
+                    .receipts_by_block(number.into())
+                    .map_err(|e| internal_error(&e))?
+                    .ok_or_else(not_found)?;
+                blocks.push((block, receipts));
+            }
+
+            // The blocks are replayed on top of the state after the block before the range.
+            let state =
+                provider.history_by_block_number(from_block - 1).map_err(|e| internal_error(&e))?;
+            let simulation = caller
+                .simulate(state, provider.chain_spec(), blocks, &params.contracts)
+                .map_err(call_error)?;
+
+            Ok(SimulationResult {
+                logs: simulation
+                    .logs
+                    .into_iter()
+                    .map(|log| FormattedRpcLog::new(log, LogFormat::Standard))
+                    .collect(),
+                transactions: simulation.transactions,
+            })
+        })
+        .await
+        .map_err(|e| internal_error(&e))?
+    }
+}
+
+/// Returns the RPC error of a call which did not succeed.
+fn call_error(err: ShadowCallError) -> ErrorObject<'static> {
+    match &err {
+        ShadowCallError::Reverted(output) => {
+            ErrorObject::owned(REVERTED_CODE, err.to_string(), Some(output.to_lower_hex()))
+        }
+        ShadowCallError::Halted(_) => ErrorObject::owned::<()>(HALTED_CODE, err.to_string(), None),
+        ShadowCallError::InvalidCall(_) => {
+            ErrorObject::owned::<()>(INVALID_PARAMS_CODE, err.to_string(), None)
+        }
+        ShadowCallError::State(_) => {
+            ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, err.to_string(), None)
+        }
+    }
+}
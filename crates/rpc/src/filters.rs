@@ -0,0 +1,194 @@
+//! Server-side state of the filters installed with `shadow_newFilter` and
+//! `shadow_newBlockFilter`.
+
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use shadow_reth_common::IndexedBlockEvent;
+use tokio::sync::broadcast::{error::TryRecvError, Receiver};
+
+use crate::apis::GetLogsParameters;
+
+/// Filters which have not been polled for this long are uninstalled, as by geth.
+pub(crate) const DEFAULT_FILTER_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// What an installed filter reports.
+#[derive(Debug, Clone)]
+pub(crate) enum FilterKind {
+    /// Shadow logs matching the filter.
+    Logs(GetLogsParameters),
+    /// Hashes of newly indexed blocks.
+    Blocks,
+}
+
+/// A filter missed blocks because it was not polled before its queue of indexed blocks overflowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FilterLagged {
+    /// Number of blocks which were missed.
+    pub(crate) lag_count: u64,
+}
+
+/// A filter installed by a client.
+#[derive(Debug)]
+struct InstalledFilter {
+    kind: FilterKind,
+    /// When the filter was installed or last polled.
+    last_poll: Instant,
+    /// Receives the blocks indexed since the filter was last polled.
+    indexed_block_receiver: Receiver<IndexedBlockEvent>,
+}
+
+/// Filters installed by clients, keyed by their id.
+///
+/// Every filter holds its own receiver of indexed blocks, so that the blocks indexed since
+/// it was last polled are queued up without a background task. Filters which are not polled
+/// within the TTL are uninstalled the next time any filter is accessed.
+#[derive(Debug)]
+pub(crate) struct ShadowFilters {
+    filters: Mutex<HashMap<String, InstalledFilter>>,
+    ttl: Duration,
+    /// Randomizes filter ids, so that clients cannot guess the ids of other clients' filters.
+    random_state: RandomState,
+    /// Counter making filter ids unique.
+    next_id: AtomicU64,
+}
+
+impl ShadowFilters {
+    /// Creates an empty set of filters, which expire after `ttl` without being polled.
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            filters: Mutex::default(),
+            ttl,
+            random_state: RandomState::new(),
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Installs a filter, which reports the blocks received by `indexed_block_receiver`
+    /// from now on, and returns its id.
+    pub(crate) fn install(
+        &self,
+        kind: FilterKind,
+        indexed_block_receiver: Receiver<IndexedBlockEvent>,
+    ) -> String {
+        let counter = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut hasher = self.random_state.build_hasher();
+        hasher.write_u64(counter);
+        let id = format!("0x{:016x}{counter:016x}", hasher.finish());
+
+        let filter = InstalledFilter { kind, last_poll: Instant::now(), indexed_block_receiver };
+        let mut filters = self.lock();
+        let _ = filters.insert(id.clone(), filter);
+        id
+    }
+
+    /// Uninstalls the filter with the given id, returning `false` if there was no such filter.
+    pub(crate) fn uninstall(&self, id: &str) -> bool {
+        self.lock().remove(id).is_some()
+    }
+
+    /// Returns the kind of the filter with the given id, and the blocks committed and reverted
+    /// since it was last polled, in the order in which they were indexed. Returns `None` if there
+    /// is no such filter, or it has expired.
+    ///
+    /// If `drain` is not set, the indexed blocks are left queued and an empty list is returned.
+    /// If the filter missed blocks, [`FilterLagged`] is returned instead of the blocks, and the
+    /// blocks indexed after the missed ones are returned by the next poll.
+    pub(crate) fn poll(
+        &self,
+        id: &str,
+        drain: bool,
+    ) -> Option<(FilterKind, Result<Vec<IndexedBlockEvent>, FilterLagged>)> {
+        let mut filters = self.lock();
+        let filter = filters.get_mut(id)?;
+        filter.last_poll = Instant::now();
+
+        let mut events = Vec::new();
+        while drain {
+            match filter.indexed_block_receiver.try_recv() {
+                Ok(event) => events.push(event),
+                Err(TryRecvError::Lagged(lag_count)) => {
+                    return Some((filter.kind.clone(), Err(FilterLagged { lag_count })))
+                }
+                Err(TryRecvError::Empty | TryRecvError::Closed) => break,
+            }
+        }
+        Some((filter.kind.clone(), Ok(events)))
+    }
+
+    /// Locks the filters, uninstalling the ones which have expired.
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, InstalledFilter>> {
+        let mut filters = self.filters.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        filters.retain(|_, filter| now.duration_since(filter.last_poll) < self.ttl);
+        filters
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reth_primitives::BlockHash;
+    use shadow_reth_common::IndexedBlockEvent;
+
+    use super::{FilterKind, FilterLagged, ShadowFilters};
+
+    #[test]
+    fn test_filters_queue_indexed_blocks() {
+        let filters = ShadowFilters::new(Duration::from_secs(60));
+        let (tx, _) = tokio::sync::broadcast::channel(16);
+        let [first, second, third] = [1, 2, 3].map(BlockHash::repeat_byte);
+
+        tx.send(IndexedBlockEvent::Committed(first)).unwrap();
+        let id = filters.install(FilterKind::Blocks, tx.subscribe());
+        let other = filters.install(FilterKind::Blocks, tx.subscribe());
+        assert_ne!(id, other);
+
+        let events = vec![IndexedBlockEvent::Reverted(second), IndexedBlockEvent::Committed(third)];
+        for event in &events {
+            tx.send(*event).unwrap();
+        }
+        assert_eq!(filters.poll(&id, false).unwrap().1, Ok(Vec::new()));
+        assert_eq!(filters.poll(&id, true).unwrap().1, Ok(events.clone()));
+        assert_eq!(filters.poll(&id, true).unwrap().1, Ok(Vec::new()));
+
+        assert!(filters.uninstall(&id));
+        assert!(!filters.uninstall(&id));
+        assert!(filters.poll(&id, true).is_none());
+        assert_eq!(filters.poll(&other, true).unwrap().1, Ok(events));
+    }
+
+    #[test]
+    fn test_filters_report_lag() {
+        let filters = ShadowFilters::new(Duration::from_secs(60));
+        let (tx, _) = tokio::sync::broadcast::channel(2);
+        let id = filters.install(FilterKind::Blocks, tx.subscribe());
+
+        let [first, second, third] = [1, 2, 3].map(BlockHash::repeat_byte);
+        for block_hash in [first, second, third] {
+            tx.send(IndexedBlockEvent::Committed(block_hash)).unwrap();
+        }
+        assert_eq!(filters.poll(&id, true).unwrap().1, Err(FilterLagged { lag_count: 1 }));
+        assert_eq!(
+            filters.poll(&id, true).unwrap().1,
+            Ok(vec![IndexedBlockEvent::Committed(second), IndexedBlockEvent::Committed(third)])
+        );
+    }
+
+    #[test]
+    fn test_filters_expire() {
+        let filters = ShadowFilters::new(Duration::ZERO);
+        let (tx, _) = tokio::sync::broadcast::channel(16);
+
+        let id = filters.install(FilterKind::Blocks, tx.subscribe());
+        assert!(filters.poll(&id, true).is_none());
+    }
+}
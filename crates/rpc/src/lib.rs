@@ -1,16 +1,24 @@
 //! ShadowRPC is a reth RPC extension which allows for reading
-//! shadow data written to SQLite by [`reth-shadow-exex`]
+//! shadow data written to the shadow database by [`reth-shadow-exex`]
 
 #![cfg_attr(not(test), warn(unused_crate_dependencies))]
 
 /// Contains logic for custom RPC API methods.
 pub(crate) mod apis;
+mod call;
+mod filters;
+mod overlay;
+mod resolver;
 pub(crate) mod shadow_logs_query;
 
-use std::path::PathBuf;
+use std::{sync::Arc, time::Duration};
 
-use apis::{GetLogsParameters, RpcLog, SubscribeParameters};
+use apis::{
+    FilterChanges, FormattedRpcLog, GetLogsPageParameters, GetLogsParameters, LogsPage,
+    SubscribeParameters,
+};
 use eyre::{eyre, Result};
+use filters::{ShadowFilters, DEFAULT_FILTER_TTL};
 use jsonrpsee::{
     core::{RpcResult, SubscriptionResult},
     proc_macros::rpc,
@@ -18,72 +26,120 @@ use jsonrpsee::{
 use reth_node_api::FullNodeComponents;
 use reth_node_builder::rpc::RpcContext;
 use reth_provider::{BlockNumReader, BlockReaderIdExt};
-use shadow_reth_common::ShadowSqliteDb;
+use shadow_reth_common::{IndexedBlockEvent, ShadowStorage};
 use tokio::sync::broadcast::Receiver;
 
+pub use apis::LogFormat;
+pub use call::{ShadowCall, ShadowCallApiServer, SimulateParameters, SimulationResult};
+pub use overlay::{CanonicalLogs, OverlayListener, ShadowOverlay, ShadowOverlayApiServer};
+pub use resolver::{BlockResolver, IndexedBlockResolver};
+pub use shadow_logs_query::LogQueryLimits;
+
 #[rpc(server, namespace = "shadow")]
 pub trait ShadowRpcApi {
     /// Returns shadow logs.
     #[method(name = "getLogs")]
-    async fn get_logs(&self, params: GetLogsParameters) -> RpcResult<Vec<RpcLog>>;
+    async fn get_logs(&self, params: GetLogsParameters) -> RpcResult<Vec<FormattedRpcLog>>;
+
+    /// Returns a page of shadow logs, with a cursor from which the next page continues.
+    #[method(name = "getLogsPage")]
+    async fn get_logs_page(&self, params: GetLogsPageParameters) -> RpcResult<LogsPage>;
+
+    /// Installs a filter notifying of the shadow logs of newly indexed blocks, and returns its
+    /// id.
+    #[method(name = "newFilter")]
+    async fn new_filter(&self, params: GetLogsParameters) -> RpcResult<String>;
+
+    /// Installs a filter notifying of newly indexed blocks, and returns its id.
+    #[method(name = "newBlockFilter")]
+    async fn new_block_filter(&self) -> RpcResult<String>;
+
+    /// Returns the shadow logs or block hashes indexed since the filter was last polled.
+    #[method(name = "getFilterChanges")]
+    async fn get_filter_changes(&self, id: String) -> RpcResult<FilterChanges>;
+
+    /// Returns all shadow logs matching a log filter.
+    #[method(name = "getFilterLogs")]
+    async fn get_filter_logs(&self, id: String) -> RpcResult<Vec<FormattedRpcLog>>;
+
+    /// Uninstalls a filter, returning `false` if it did not exist.
+    #[method(name = "uninstallFilter")]
+    async fn uninstall_filter(&self, id: String) -> RpcResult<bool>;
 
     /// Create a shadow logs subscription.
-    #[subscription(name = "subscribe" => "subscription", unsubscribe = "unsubscribe", item = RpcLog)]
+    #[subscription(name = "subscribe" => "subscription", unsubscribe = "unsubscribe", item = FormattedRpcLog)]
     async fn subscribe(&self, params: SubscribeParameters) -> SubscriptionResult;
 }
 
-/// Wrapper around an RPC provider and a database connection pool.
+/// Wrapper around an RPC provider and the shadow log storage.
 #[derive(Debug)]
 pub struct ShadowRpc<P> {
-    provider: P,
-    /// Database manager.
-    sqlite_manager: ShadowSqliteDb,
-    /// Receives block hashes as they are indexed by the exex.
-    indexed_block_hash_receiver: Receiver<String>,
+    /// Resolves block tags and hashes, usually the provider of the reth node.
+    resolver: P,
+    /// Storage from which shadow logs are read.
+    storage: Arc<dyn ShadowStorage>,
+    /// Receives the blocks committed and reverted by the exex, once they have been indexed.
+    indexed_block_receiver: Receiver<IndexedBlockEvent>,
+    /// Encoding of returned logs.
+    log_format: LogFormat,
+    /// Limits on the logs selected by a single request.
+    limits: LogQueryLimits,
+    /// Filters installed with `shadow_newFilter` and `shadow_newBlockFilter`.
+    filters: Arc<ShadowFilters>,
 }
 
 impl<Provider> ShadowRpc<Provider> {
-    /// Instatiate a Shadow RPC API, building a connection pool to the SQLite database
-    /// and initializing tables.
-    pub async fn new(
+    /// Instatiate a Shadow RPC API, reading shadow logs from the given storage.
+    pub fn new(
         provider: Provider,
-        db_path: &str,
-        indexed_block_hash_receiver: Receiver<String>,
-    ) -> Result<ShadowRpc<Provider>> {
-        Ok(Self {
-            provider,
-            sqlite_manager: ShadowSqliteDb::new(db_path).await?,
-            indexed_block_hash_receiver,
-        })
+        storage: Arc<dyn ShadowStorage>,
+        indexed_block_receiver: Receiver<IndexedBlockEvent>,
+    ) -> ShadowRpc<Provider> {
+        Self {
+            resolver: provider,
+            storage,
+            indexed_block_receiver,
+            log_format: LogFormat::default(),
+            limits: LogQueryLimits::default(),
+            filters: Arc::new(ShadowFilters::new(DEFAULT_FILTER_TTL)),
+        }
+    }
+
+    /// Sets the encoding of returned logs, which defaults to [`LogFormat::Standard`].
+    pub const fn with_log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    /// Sets the limits on the logs selected by a single request, see [`LogQueryLimits`].
+    pub const fn with_limits(mut self, limits: LogQueryLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets how long installed filters are kept without being polled, which defaults to five
+    /// minutes.
+    pub fn with_filter_ttl(mut self, ttl: Duration) -> Self {
+        self.filters = Arc::new(ShadowFilters::new(ttl));
+        self
     }
 
     /// Initializes ShadowRpc, to be called from the `.extend_rpc_modules` reth hook
     /// on node startup.
     pub fn init<Node>(
         ctx: RpcContext<'_, Node>,
-        db_path_obj: PathBuf,
-        indexed_block_hash_receiver: Receiver<String>,
+        storage: Arc<dyn ShadowStorage>,
+        indexed_block_receiver: Receiver<IndexedBlockEvent>,
+        log_format: LogFormat,
+        limits: LogQueryLimits,
     ) -> Result<()>
     where
         Node: FullNodeComponents<Provider = Provider>,
         Node::Provider: BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
     {
-        // Clone the provider so we can move it into the RPC builder thread
-        let provider = ctx.provider().clone();
-
-        // Start a new thread, build the ShadowRpc, and join it.
-        //
-        // We have to do it this way to avoid spawning a runtime within a runtime.
-        let shadow_rpc = std::thread::spawn(move || {
-            let rt = tokio::runtime::Runtime::new().expect("failed to spawn blocking runtime");
-            rt.block_on(ShadowRpc::new(
-                provider,
-                db_path_obj.to_str().ok_or_else(|| eyre!("failed to parse DB path"))?,
-                indexed_block_hash_receiver,
-            ))
-        })
-        .join()
-        .map_err(|_| eyre!("failed to join ShadowRpc thread"))??;
+        let shadow_rpc = ShadowRpc::new(ctx.provider().clone(), storage, indexed_block_receiver)
+            .with_log_format(log_format)
+            .with_limits(limits);
 
         // Merge the ShadowRpc into the reth context, which will make the API available.
         ctx.modules
@@ -94,18 +150,59 @@ impl<Provider> ShadowRpc<Provider> {
     }
 }
 
+impl ShadowRpc<IndexedBlockResolver> {
+    /// Instantiates a Shadow RPC API which serves the shadow namespace from the given storage
+    /// alone, without a reth node. Block tags and hashes are resolved from the blocks indexed by
+    /// the ExEx, see [`IndexedBlockResolver`].
+    ///
+    /// No blocks are indexed while serving, so `shadow_subscribe` subscriptions end immediately.
+    pub fn standalone(storage: Arc<dyn ShadowStorage>) -> Self {
+        let (_, indexed_block_receiver) = tokio::sync::broadcast::channel(1);
+        Self::new(IndexedBlockResolver::new(storage.clone()), storage, indexed_block_receiver)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use jsonrpsee::rpc_params;
-    use reth_primitives::{hex, Block, Header};
+    use std::{ops::RangeInclusive, sync::Arc};
+
+    use jsonrpsee::{rpc_params, types::error::INVALID_PARAMS_CODE};
+    use reth_primitives::{hex, Block, BlockHash, Header};
     use reth_provider::test_utils::MockEthProvider;
-    use shadow_reth_common::{ShadowLog, ToLowerHex};
+    use shadow_reth_common::{
+        Checkpoint, IndexedBlock, IndexedBlockEvent, ShadowLog, ShadowSqliteDb, ShadowStorage,
+        ToLowerHex,
+    };
 
     use crate::{
-        apis::{AddressRepresentation, GetLogsParameters, RpcLog, SubscribeParameters},
-        ShadowRpc, ShadowRpcApiServer,
+        apis::{
+            AddressRepresentation, Commitment, FilterChanges, FormattedRpcLog,
+            GetLogsPageParameters, GetLogsParameters, LegacyRpcLog, LogFormat, RpcLog,
+            SubscribeParameters,
+        },
+        shadow_logs_query::{LIMIT_EXCEEDED_CODE, NOT_INDEXED_CODE},
+        LogQueryLimits, ShadowRpc, ShadowRpcApiServer,
     };
 
+    fn standard(log: ShadowLog) -> FormattedRpcLog {
+        FormattedRpcLog::Standard(RpcLog::from(log))
+    }
+
+    /// Records the given blocks as processed by the ExEx, with the last one as the checkpoint. The
+    /// hash of each block is its left-padded number.
+    ///
+    /// Committing a block replaces its logs, so this must be called before storing any logs.
+    async fn index_blocks(db: &ShadowSqliteDb, block_numbers: RangeInclusive<u64>) {
+        let blocks = block_numbers
+            .map(|n| IndexedBlock::new(n, BlockHash::left_padding_from(&n.to_be_bytes())))
+            .collect::<Vec<_>>();
+        let checkpoint = blocks.last().map(|block| Checkpoint {
+            block_number: block.block_number,
+            block_hash: block.block_hash,
+        });
+        db.apply_chain_update(&[], &blocks, &[], checkpoint).await.unwrap();
+    }
+
     #[tokio::test]
     async fn test_shadow_subscribe() {
         let mock_provider = MockEthProvider::default();
@@ -129,7 +226,8 @@ mod tests {
 
         let (tx, rx) = tokio::sync::broadcast::channel(1);
 
-        let rpc = ShadowRpc::new(mock_provider, ":memory:", rx).await.unwrap();
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let rpc = ShadowRpc::new(mock_provider, db.clone(), rx);
 
         let logs = vec![
             ShadowLog {
@@ -169,8 +267,8 @@ mod tests {
         ];
 
         // Keep a clone of the log we expect to receive via the subscription for assert
-        let expected_log = RpcLog::from(logs[1].clone());
-        rpc.sqlite_manager.bulk_insert_into_shadow_log_table(logs).await.unwrap();
+        let expected_log = standard(logs[1].clone());
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
 
         let params = SubscribeParameters {
             address: Some(AddressRepresentation::ArrayOfStrings(vec![
@@ -179,6 +277,7 @@ mod tests {
                 "0xc55126051b22ebb829d00368f4b12bde432de5da".to_string(),
             ])),
             topics: None,
+            ..Default::default()
         };
 
         // Create a subscription on `shadow_subscribe`
@@ -189,10 +288,10 @@ mod tests {
             .unwrap();
 
         // Send the last block hash to the rpc receiver to mock the exex indexing the block
-        tx.send(last_block_hash.to_lower_hex()).expect("failed to send block hash");
+        tx.send(IndexedBlockEvent::Committed(last_block_hash)).expect("failed to send block hash");
 
         // Receive the RPC log from the subscription
-        let (result, _id) = sub.next::<RpcLog>().await.unwrap().unwrap();
+        let (result, _id) = sub.next::<FormattedRpcLog>().await.unwrap().unwrap();
 
         assert_eq!(result, expected_log);
     }
@@ -220,7 +319,8 @@ mod tests {
 
         let (_, rx) = tokio::sync::broadcast::channel(1);
 
-        let rpc = ShadowRpc::new(mock_provider, ":memory:", rx).await.unwrap();
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let rpc = ShadowRpc::new(mock_provider, db.clone(), rx);
 
         let logs = vec![
             ShadowLog {
@@ -259,7 +359,8 @@ mod tests {
             },
         ];
 
-        rpc.sqlite_manager.bulk_insert_into_shadow_log_table(logs).await.unwrap();
+        index_blocks(&db, 18870000..=18870001).await;
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
 
         let params = GetLogsParameters {
             address: Some(AddressRepresentation::ArrayOfStrings(vec![
@@ -271,6 +372,7 @@ mod tests {
             from_block: Some("0x11feef0".to_string()),
             to_block: Some("0x11feef1".to_string()),
             topics: None,
+            commitment: None,
         };
 
         let resp = rpc.get_logs(params).await.unwrap();
@@ -278,28 +380,504 @@ mod tests {
         let expected = vec![
             RpcLog {
                 address: "0x0fbc0a9be1e87391ed2c7d2bb275bec02f53241f".to_string(),
+                topics: vec!["0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822".to_string(), "0x0000000000000000000000003fc91a3afd70395cd496c647d5a6cc9d4b2b7fad".to_string(), "0x0000000000000000000000003fc91a3afd70395cd496c647d5a6cc9d4b2b7fad".to_string()],
+                data: "0x000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000049dc9ce34ad2a2177480000000000000000000000000000000000000000000000000432f754f7158ad80000000000000000000000000000000000000000000000000000000000000000".to_string(),
                 block_hash: "0x4131d538cf705c267da7f448ec7460b177f40d28115ad290ba6a1fd734afe280".to_string(),
-                block_number: hex::encode(18870000u64.to_be_bytes()),
-                data: Some("0x000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000049dc9ce34ad2a2177480000000000000000000000000000000000000000000000000432f754f7158ad80000000000000000000000000000000000000000000000000000000000000000".to_string()),
-                log_index: 0u64.to_string(),
-                removed: false,
-                topics: [Some("0xd78ad95fa46c994b6551d0da85fc275fe613ce37657fb8d5e3d130840159d822".to_string()), Some("0x0000000000000000000000003fc91a3afd70395cd496c647d5a6cc9d4b2b7fad".to_string()), Some("0x0000000000000000000000003fc91a3afd70395cd496c647d5a6cc9d4b2b7fad".to_string()), None],
+                block_number: "0x11feef0".to_string(),
+                block_timestamp: Some("0x658accff".to_string()),
                 transaction_hash: "0x8bf2361656e0ea6f338ad17ac3cd616f8eea9bb17e1afa1580802e9d3231c203".to_string(),
-                transaction_index: 167u64.to_string()
+                transaction_index: "0xa7".to_string(),
+                log_index: "0x0".to_string(),
+                transaction_log_index: Some("0x1a".to_string()),
+                removed: false,
             },
             RpcLog {
                 address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+                topics: vec!["0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c".to_string(), "0x0000000000000000000000003fc91a3afd70395cd496c647d5a6cc9d4b2b7fad".to_string()],
+                data: "0x0000000000000000000000000000000000000000000000001bc16d674ec80000".to_string(),
                 block_hash: "0x3cac643a6a1af584681a6a6dc632cd110a479c9c642e2da92b73fefb45739165".to_string(),
-                block_number: hex::encode(18870001u64.to_be_bytes()),
-                data: Some("0x0000000000000000000000000000000000000000000000001bc16d674ec80000".to_string()),
-                log_index: 0u64.to_string(),
-                removed: false,
-                topics: [Some("0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c".to_string()), Some("0x0000000000000000000000003fc91a3afd70395cd496c647d5a6cc9d4b2b7fad".to_string()), None, None],
+                block_number: "0x11feef1".to_string(),
+                block_timestamp: Some("0x658acd0b".to_string()),
                 transaction_hash: "0xd02dc650cc9a34def3d7a78808a36a8cb2e292613c2989f4313155e8e4af9b0f".to_string(),
-                transaction_index: 2u64.to_string()
+                transaction_index: "0x2".to_string(),
+                log_index: "0x0".to_string(),
+                transaction_log_index: Some("0x0".to_string()),
+                removed: false,
             }
-        ];
+        ]
+        .into_iter()
+        .map(FormattedRpcLog::Standard)
+        .collect::<Vec<_>>();
 
         assert_eq!(resp, expected);
     }
+
+    #[tokio::test]
+    async fn test_shadow_get_logs_across_digit_boundary() {
+        let (_, rx) = tokio::sync::broadcast::channel(1);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx);
+
+        let logs = [9u64, 10, 11]
+            .into_iter()
+            .map(|block_number| ShadowLog {
+                address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+                block_hash: format!("0x{:064x}", block_number),
+                block_log_index: 0,
+                block_number,
+                block_timestamp: 1703595275,
+                transaction_index: 0,
+                transaction_hash: format!("0x{:064x}", block_number),
+                transaction_log_index: 0,
+                removed: false,
+                data: None,
+                topic_0: None,
+                topic_1: None,
+                topic_2: None,
+                topic_3: None,
+            })
+            .collect::<Vec<_>>();
+        index_blocks(&db, 9..=11).await;
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+
+        let params = GetLogsParameters {
+            address: None,
+            block_hash: None,
+            from_block: Some("0x9".to_string()),
+            to_block: Some("0xa".to_string()),
+            topics: None,
+            commitment: None,
+        };
+        let resp = rpc.get_logs(params).await.unwrap();
+
+        assert_eq!(resp, logs[..2].iter().cloned().map(standard).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_shadow_get_logs_below_pruned_horizon() {
+        let (_, rx) = tokio::sync::broadcast::channel(1);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx);
+        db.prune(Some(10), None).await.unwrap();
+        index_blocks(&db, 10..=11).await;
+
+        let params = |from_block: &str| GetLogsParameters {
+            address: None,
+            block_hash: None,
+            from_block: Some(from_block.to_string()),
+            to_block: Some("0xb".to_string()),
+            topics: None,
+            commitment: None,
+        };
+        let err = rpc.get_logs(params("0x9")).await.unwrap_err();
+        assert_eq!(err.code(), INVALID_PARAMS_CODE);
+        assert!(err.message().contains("logs before block 10 have been pruned"));
+
+        assert!(rpc.get_logs(params("0xa")).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_shadow_get_logs_not_indexed() {
+        let mock_provider = MockEthProvider::default();
+        let block =
+            Block { header: Header { number: 10, ..Default::default() }, ..Default::default() };
+        let block_hash = block.hash_slow();
+        mock_provider.extend_blocks([(block_hash, block)]);
+
+        let (_, rx) = tokio::sync::broadcast::channel(1);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let rpc = ShadowRpc::new(mock_provider, db.clone(), rx);
+
+        let range = |from_block: &str, to_block: &str| GetLogsParameters {
+            address: None,
+            block_hash: None,
+            from_block: Some(from_block.to_string()),
+            to_block: Some(to_block.to_string()),
+            topics: None,
+            commitment: None,
+        };
+        let by_hash = GetLogsParameters {
+            address: None,
+            block_hash: Some(block_hash.to_string()),
+            from_block: None,
+            to_block: None,
+            topics: None,
+            commitment: None,
+        };
+
+        let err = rpc.get_logs(range("0xa", "0xa")).await.unwrap_err();
+        assert_eq!(err.code(), NOT_INDEXED_CODE);
+        let err = rpc.get_logs(by_hash.clone()).await.unwrap_err();
+        assert_eq!(err.code(), NOT_INDEXED_CODE);
+
+        // Once indexed, a block without shadow logs has no logs rather than failing.
+        let checkpoint = Checkpoint { block_number: 10, block_hash };
+        db.apply_chain_update(&[], &[IndexedBlock::new(10, block_hash)], &[], Some(checkpoint))
+            .await
+            .unwrap();
+        assert!(rpc.get_logs(range("0xa", "0xa")).await.unwrap().is_empty());
+        assert!(rpc.get_logs(by_hash).await.unwrap().is_empty());
+
+        let err = rpc.get_logs(range("0xa", "0xb")).await.unwrap_err();
+        assert_eq!(err.code(), NOT_INDEXED_CODE);
+        assert!(err.message().contains("the last indexed block is 10"));
+
+        // Blocks below the checkpoint must have been indexed too, such as the skipped block 11.
+        let block_hash = BlockHash::repeat_byte(12);
+        let checkpoint = Checkpoint { block_number: 12, block_hash };
+        db.apply_chain_update(&[], &[IndexedBlock::new(12, block_hash)], &[], Some(checkpoint))
+            .await
+            .unwrap();
+        let err = rpc.get_logs(range("0xa", "0xc")).await.unwrap_err();
+        assert_eq!(err.code(), NOT_INDEXED_CODE);
+        assert!(err.message().contains("1 of the blocks 10 to 12 have not been indexed"));
+        assert!(rpc.get_logs(range("0xc", "0xc")).await.unwrap().is_empty());
+    }
+
+    /// Stores one log in each of the blocks 1 to 5, indexed up to block 5.
+    async fn five_blocks(db: &ShadowSqliteDb) -> Vec<ShadowLog> {
+        let logs = (1..=5u64)
+            .map(|block_number| ShadowLog {
+                address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+                block_hash: BlockHash::left_padding_from(&[block_number as u8]).to_lower_hex(),
+                block_log_index: 0,
+                block_number,
+                block_timestamp: 1703595275,
+                transaction_index: 0,
+                transaction_hash: BlockHash::left_padding_from(&[block_number as u8])
+                    .to_lower_hex(),
+                transaction_log_index: 0,
+                removed: false,
+                data: None,
+                topic_0: None,
+                topic_1: None,
+                topic_2: None,
+                topic_3: None,
+            })
+            .collect::<Vec<_>>();
+        index_blocks(db, 1..=5).await;
+        db.bulk_insert_into_shadow_log_table(&logs).await.unwrap();
+        logs
+    }
+
+    fn range(from_block: &str, to_block: &str) -> GetLogsParameters {
+        GetLogsParameters {
+            address: None,
+            block_hash: None,
+            from_block: Some(from_block.to_string()),
+            to_block: Some(to_block.to_string()),
+            topics: None,
+            commitment: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shadow_get_logs_limits() {
+        let (_, rx) = tokio::sync::broadcast::channel(1);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let logs = five_blocks(&db).await;
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx)
+            .with_limits(LogQueryLimits { max_block_range: 4, max_results: 3 });
+
+        let err = rpc.get_logs(range("0x1", "0x5")).await.unwrap_err();
+        assert_eq!(err.code(), LIMIT_EXCEEDED_CODE);
+        assert!(err.message().contains("block range of 5 blocks exceeds the maximum of 4"));
+
+        let err = rpc.get_logs(range("0x1", "0x4")).await.unwrap_err();
+        assert_eq!(err.code(), LIMIT_EXCEEDED_CODE);
+        assert!(err.message().contains("more than 3 logs"));
+
+        let resp = rpc.get_logs(range("0x2", "0x4")).await.unwrap();
+        assert_eq!(resp, logs[1..4].iter().cloned().map(standard).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_shadow_get_logs_page() {
+        let (_, rx) = tokio::sync::broadcast::channel(1);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let logs = five_blocks(&db).await;
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx)
+            .with_limits(LogQueryLimits { max_block_range: 1, max_results: 3 });
+
+        // Pages are capped at the maximum number of results, and not limited in block range.
+        let mut params =
+            GetLogsPageParameters { filter: range("0x1", "0x5"), cursor: None, limit: Some(10) };
+        let first = rpc.get_logs_page(params.clone()).await.unwrap();
+        assert_eq!(first.logs, logs[..3].iter().cloned().map(standard).collect::<Vec<_>>());
+        assert!(first.cursor.is_some());
+
+        params.cursor = first.cursor;
+        let second = rpc.get_logs_page(params.clone()).await.unwrap();
+        assert_eq!(second.logs, logs[3..].iter().cloned().map(standard).collect::<Vec<_>>());
+        assert_eq!(second.cursor, None);
+
+        params.cursor = Some("0x1234".to_string());
+        let err = rpc.get_logs_page(params).await.unwrap_err();
+        assert_eq!(err.code(), INVALID_PARAMS_CODE);
+    }
+
+    #[tokio::test]
+    async fn test_shadow_subscribe_resume() {
+        let (tx, rx) = tokio::sync::broadcast::channel(16);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let mut logs = five_blocks(&db).await;
+        let blocks = logs
+            .iter()
+            .map(|log| IndexedBlock::new(log.block_number, log.block_hash.parse().unwrap()))
+            .collect::<Vec<_>>();
+        db.apply_chain_update(&[], &blocks, &logs, None).await.unwrap();
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx)
+            .with_limits(LogQueryLimits { max_block_range: 1, max_results: 2 })
+            .into_rpc();
+
+        let params = SubscribeParameters {
+            from_block: Some("0x1".to_string()),
+            cursor: Some("0x1234".to_string()),
+            ..Default::default()
+        };
+        assert!(rpc.subscribe_unbounded("shadow_subscribe", rpc_params!(params)).await.is_err());
+
+        // Resume after the log of block 2.
+        let cursor = format!("0x{:016x}{:016x}", 2, 0);
+        let params = SubscribeParameters { cursor: Some(cursor), ..Default::default() };
+        let mut sub =
+            rpc.subscribe_unbounded("shadow_subscribe", rpc_params!(params)).await.unwrap();
+
+        // Block 5 has been backfilled, so it is not delivered again, unlike the new block 6.
+        let mut log = logs[4].clone();
+        log.block_number = 6;
+        log.block_hash = BlockHash::left_padding_from(&[6]).to_lower_hex();
+        let block = IndexedBlock::new(6, BlockHash::left_padding_from(&[6]));
+        db.apply_chain_update(&[], &[block], &[log.clone()], None).await.unwrap();
+        tx.send(IndexedBlockEvent::Committed(blocks[4].block_hash)).unwrap();
+        tx.send(IndexedBlockEvent::Committed(block.block_hash)).unwrap();
+        logs.push(log);
+
+        for expected in &logs[2..] {
+            let (result, _id) = sub.next::<FormattedRpcLog>().await.unwrap().unwrap();
+            assert_eq!(result, standard(expected.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shadow_subscribe_resume_above_head() {
+        let (tx, rx) = tokio::sync::broadcast::channel(16);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let logs = five_blocks(&db).await;
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx).into_rpc();
+
+        // Resume after the first log of block 6, which has not been indexed yet.
+        let cursor = format!("0x{:016x}{:016x}", 6, 0);
+        let params = SubscribeParameters { cursor: Some(cursor), ..Default::default() };
+        let mut sub =
+            rpc.subscribe_unbounded("shadow_subscribe", rpc_params!(params)).await.unwrap();
+
+        let block_hash = BlockHash::left_padding_from(&[6]);
+        let block_logs = [0, 1].map(|block_log_index| ShadowLog {
+            block_number: 6,
+            block_hash: block_hash.to_lower_hex(),
+            block_log_index,
+            ..logs[4].clone()
+        });
+        let checkpoint = Checkpoint { block_number: 6, block_hash };
+        let block = IndexedBlock::new(6, block_hash);
+        db.apply_chain_update(&[], &[block], &block_logs, Some(checkpoint)).await.unwrap();
+        tx.send(IndexedBlockEvent::Committed(block_hash)).unwrap();
+
+        let (result, _id) = sub.next::<FormattedRpcLog>().await.unwrap().unwrap();
+        assert_eq!(result, standard(block_logs[1].clone()));
+    }
+
+    #[tokio::test]
+    async fn test_commitment() {
+        let (tx, rx) = tokio::sync::broadcast::channel(16);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let mut logs = five_blocks(&db).await;
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx);
+
+        let mut params = range("0x1", "0x5");
+        params.commitment = Some(Commitment::Confirmations(2));
+        let resp = rpc.get_logs(params.clone()).await.unwrap();
+        assert_eq!(resp, logs[..3].iter().cloned().map(standard).collect::<Vec<_>>());
+        params.commitment = Some(Commitment::Confirmations(5));
+        assert_eq!(rpc.get_logs(params).await.unwrap(), Vec::new());
+
+        // With one confirmation, block 5 is held back until block 6 has been indexed.
+        let params = SubscribeParameters {
+            commitment: Some(Commitment::Confirmations(1)),
+            ..Default::default()
+        };
+        let mut sub = rpc
+            .into_rpc()
+            .subscribe_unbounded("shadow_subscribe", rpc_params!(params))
+            .await
+            .unwrap();
+        for block_number in [6u64, 7] {
+            let block_hash = BlockHash::left_padding_from(&[block_number as u8]);
+            let mut log = logs[4].clone();
+            log.block_number = block_number;
+            log.block_hash = block_hash.to_lower_hex();
+            let checkpoint = Checkpoint { block_number, block_hash };
+            let block = IndexedBlock::new(block_number, block_hash);
+            db.apply_chain_update(&[], &[block], &[log.clone()], Some(checkpoint)).await.unwrap();
+            tx.send(IndexedBlockEvent::Committed(block_hash)).unwrap();
+            logs.push(log);
+        }
+
+        for expected in &logs[4..6] {
+            let (result, _id) = sub.next::<FormattedRpcLog>().await.unwrap().unwrap();
+            assert_eq!(result, standard(expected.clone()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shadow_filters() {
+        let (tx, rx) = tokio::sync::broadcast::channel(16);
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let logs = five_blocks(&db).await;
+        let rpc = ShadowRpc::new(MockEthProvider::default(), db.clone(), rx);
+
+        let log_filter = rpc.new_filter(range("0x2", "0x3")).await.unwrap();
+        let reorg_filter = rpc.new_filter(range("0x5", "0x5")).await.unwrap();
+        let block_filter = rpc.new_block_filter().await.unwrap();
+
+        // Blocks 1 to 5 are indexed, and block 5 is reverted again.
+        let blocks = logs
+            .iter()
+            .map(|log| IndexedBlock::new(log.block_number, log.block_hash.parse().unwrap()))
+            .collect::<Vec<_>>();
+        db.apply_chain_update(&[], &blocks, &logs, None).await.unwrap();
+        db.apply_chain_update(&[blocks[4].block_hash], &[], &[], None).await.unwrap();
+        for block in &blocks {
+            tx.send(IndexedBlockEvent::Committed(block.block_hash)).unwrap();
+        }
+        tx.send(IndexedBlockEvent::Reverted(blocks[4].block_hash)).unwrap();
+
+        let expected = logs[1..3].iter().cloned().map(standard).collect::<Vec<_>>();
+        let changes = rpc.get_filter_changes(log_filter.clone()).await.unwrap();
+        assert_eq!(changes, FilterChanges::Logs(expected.clone()));
+        let changes = rpc.get_filter_changes(log_filter.clone()).await.unwrap();
+        assert_eq!(changes, FilterChanges::Logs(Vec::new()));
+
+        // The logs of the reverted block are reported again, with `removed: true`.
+        let removed = ShadowLog { removed: true, ..logs[4].clone() };
+        let changes = rpc.get_filter_changes(reorg_filter).await.unwrap();
+        let expected_reorg = vec![standard(logs[4].clone()), standard(removed)];
+        assert_eq!(changes, FilterChanges::Logs(expected_reorg));
+
+        // Block filters only report the hashes of committed blocks.
+        let changes = rpc.get_filter_changes(block_filter.clone()).await.unwrap();
+        let hashes = logs.iter().map(|log| log.block_hash.clone()).collect();
+        assert_eq!(changes, FilterChanges::Hashes(hashes));
+
+        // All logs matching a filter are returned, whether or not they have been polled.
+        assert_eq!(rpc.get_filter_logs(log_filter.clone()).await.unwrap(), expected);
+        let err = rpc.get_filter_logs(block_filter).await.unwrap_err();
+        assert_eq!(err.code(), INVALID_PARAMS_CODE);
+
+        assert!(rpc.uninstall_filter(log_filter.clone()).await.unwrap());
+        assert!(!rpc.uninstall_filter(log_filter.clone()).await.unwrap());
+        let err = rpc.get_filter_changes(log_filter).await.unwrap_err();
+        assert!(err.message().contains("filter not found"));
+    }
+
+    #[tokio::test]
+    async fn test_standalone_get_logs() {
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let blocks = [9u64, 10].map(|block_number| {
+            IndexedBlock::new(block_number, BlockHash::left_padding_from(&[block_number as u8]))
+        });
+        let logs = blocks
+            .iter()
+            .map(|block| ShadowLog {
+                address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+                block_hash: block.block_hash.to_lower_hex(),
+                block_log_index: 0,
+                block_number: block.block_number,
+                block_timestamp: 1703595275,
+                transaction_index: 0,
+                transaction_hash: block.block_hash.to_lower_hex(),
+                transaction_log_index: 0,
+                removed: false,
+                data: None,
+                topic_0: None,
+                topic_1: None,
+                topic_2: None,
+                topic_3: None,
+            })
+            .collect::<Vec<_>>();
+        index_blocks(&db, 0..=8).await;
+        let checkpoint = Checkpoint { block_number: 10, block_hash: blocks[1].block_hash };
+        db.apply_chain_update(&[], &blocks, &logs, Some(checkpoint)).await.unwrap();
+        let rpc = ShadowRpc::standalone(db);
+
+        let params = |block_hash: Option<String>, from_block: Option<&str>| GetLogsParameters {
+            address: None,
+            block_hash,
+            from_block: from_block.map(str::to_string),
+            to_block: None,
+            topics: None,
+            commitment: None,
+        };
+
+        // Block hashes are resolved from the indexed blocks, `latest` from the checkpoint.
+        let resp = rpc.get_logs(params(Some(blocks[0].block_hash.to_string()), None)).await;
+        assert_eq!(resp.unwrap(), vec![standard(logs[0].clone())]);
+        let resp = rpc.get_logs(params(None, None)).await.unwrap();
+        assert_eq!(resp, vec![standard(logs[1].clone())]);
+        let resp = rpc.get_logs(params(None, Some("earliest"))).await.unwrap();
+        assert_eq!(resp, logs.iter().cloned().map(standard).collect::<Vec<_>>());
+
+        let err = rpc.get_logs(params(Some(BlockHash::ZERO.to_string()), None)).await.unwrap_err();
+        assert!(err.message().contains("No block found for block hash"));
+        let err = rpc.get_logs(params(None, Some("finalized"))).await.unwrap_err();
+        assert_eq!(err.code(), INVALID_PARAMS_CODE);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_log_format() {
+        let db = Arc::new(ShadowSqliteDb::new(":memory:").await.unwrap());
+        let log = ShadowLog {
+            address: "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2".to_string(),
+            block_hash: BlockHash::repeat_byte(10).to_lower_hex(),
+            block_log_index: 12,
+            block_number: 10,
+            block_timestamp: 1703595275,
+            transaction_index: 3,
+            transaction_hash: BlockHash::repeat_byte(11).to_lower_hex(),
+            transaction_log_index: 1,
+            removed: false,
+            data: None,
+            topic_0: Some(BlockHash::repeat_byte(12).to_lower_hex()),
+            topic_1: None,
+            topic_2: None,
+            topic_3: None,
+        };
+        index_blocks(&db, 10..=10).await;
+        db.bulk_insert_into_shadow_log_table(&[log.clone()]).await.unwrap();
+        let rpc = ShadowRpc::standalone(db).with_log_format(LogFormat::Legacy);
+
+        let params = GetLogsParameters {
+            address: None,
+            block_hash: None,
+            from_block: Some("0xa".to_string()),
+            to_block: Some("0xa".to_string()),
+            topics: None,
+            commitment: None,
+        };
+        let resp = rpc.get_logs(params).await.unwrap();
+
+        let expected = LegacyRpcLog {
+            address: log.address,
+            block_hash: log.block_hash,
+            block_number: hex::encode(10u64.to_be_bytes()),
+            data: None,
+            log_index: "12".to_string(),
+            removed: false,
+            topics: [log.topic_0, None, None, None],
+            transaction_hash: log.transaction_hash,
+            transaction_index: "3".to_string(),
+        };
+        assert_eq!(resp, vec![FormattedRpcLog::Legacy(expected)]);
+    }
 }
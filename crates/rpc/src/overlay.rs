@@ -0,0 +1,525 @@
+//! An `eth` namespace overlay, which serves `eth_getLogs` and `eth_subscribe` with the canonical
+//! logs of shadowed contracts replaced by their shadow logs, so that unchanged tooling sees shadow
+//! events. All other methods are forwarded to the RPC of the node.
+
+use std::{collections::HashSet, fmt::Debug, future::Future, net::SocketAddr, pin::Pin, sync::Arc};
+
+use eyre::Result;
+use jsonrpsee::{
+    core::{async_trait, RpcResult, SubscriptionResult},
+    proc_macros::rpc,
+    server::{
+        middleware::rpc::{RpcServiceBuilder, RpcServiceT},
+        Server, ServerHandle,
+    },
+    types::{
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject, ErrorObjectOwned, Request, ResponsePayload,
+    },
+    MethodResponse, Methods, PendingSubscriptionSink, SubscriptionMessage, SubscriptionSink,
+};
+use reth_primitives::Address;
+use reth_provider::{BlockNumReader, BlockReaderIdExt};
+use reth_tracing::tracing::{info, warn};
+use serde::Deserialize;
+use shadow_reth_common::{BlockFilter, IndexedBlockEvent, LogFilter, ShadowLog, ToLowerHex};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    apis::{GetLogsParameters, RpcLog, SubscribeParameters},
+    shadow_logs_query::{
+        block_logs, ensure_committed, ensure_indexed, ensure_not_pruned, ensure_within_block_range,
+        ensure_within_max_results, exec_query, ValidatedQueryParams,
+    },
+    BlockResolver, ShadowRpc,
+};
+
+/// Methods served by the overlay itself. All other methods are forwarded to the upstream RPC.
+const OVERLAY_METHODS: [&str; 3] = ["eth_getLogs", "eth_subscribe", "eth_unsubscribe"];
+
+/// Maximum size of a response forwarded from the upstream RPC, the default of jsonrpsee servers.
+const MAX_RESPONSE_SIZE: usize = 10 * 1024 * 1024;
+
+#[rpc(server, namespace = "eth")]
+pub trait ShadowOverlayApi {
+    /// Returns the logs matching the filter, with the logs of shadowed contracts replaced by their
+    /// shadow logs.
+    #[method(name = "getLogs")]
+    async fn get_logs(&self, params: GetLogsParameters) -> RpcResult<Vec<RpcLog>>;
+
+    /// Creates a `logs` subscription, with the logs of shadowed contracts replaced by their
+    /// shadow logs.
+    #[subscription(name = "subscribe" => "subscription", unsubscribe = "unsubscribe", item = RpcLog)]
+    async fn subscribe(
+        &self,
+        kind: String,
+        params: Option<SubscribeParameters>,
+    ) -> SubscriptionResult;
+}
+
+/// Reads the logs emitted by canonical execution.
+///
+/// Implemented for reth providers, which read the logs from the stored receipts.
+#[async_trait]
+pub trait CanonicalLogs: Clone + Send + Sync + Unpin + 'static {
+    /// Returns the canonical logs matching `filter`, in the order in which they were emitted.
+    /// Blocks which are not canonical, or not known yet, have no logs.
+    async fn canonical_logs(&self, filter: &LogFilter) -> RpcResult<Vec<ShadowLog>>;
+}
+
+#[async_trait]
+impl<P> CanonicalLogs for P
+where
+    P: BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
+{
+    async fn canonical_logs(&self, filter: &LogFilter) -> RpcResult<Vec<ShadowLog>> {
+        let provider_error =
+            |e: &dyn std::fmt::Display| ErrorObject::owned::<()>(-1, e.to_string(), None);
+
+        let (from_block, to_block) = match filter.block {
+            BlockFilter::Hash(block_hash) => {
+                match self.block_number(block_hash).map_err(|e| provider_error(&e))? {
+                    Some(block_number) => (block_number, block_number),
+                    None => return Ok(Vec::new()),
+                }
+            }
+            BlockFilter::Range(from_block, to_block) => (from_block, to_block),
+        };
+
+        let mut logs = Vec::new();
+        for block_number in from_block..=to_block {
+            let Some(header) = self.sealed_header(block_number).map_err(|e| provider_error(&e))?
+            else {
+                break
+            };
+            if matches!(filter.block, BlockFilter::Hash(block_hash) if block_hash != header.hash())
+            {
+                continue
+            }
+            let receipts = self
+                .receipts_by_block(block_number.into())
+                .map_err(|e| provider_error(&e))?
+                .unwrap_or_default();
+            let transactions = self
+                .transactions_by_block(block_number.into())
+                .map_err(|e| provider_error(&e))?
+                .unwrap_or_default();
+
+            let mut block_log_index = 0;
+            for (transaction_index, (receipt, transaction)) in
+                receipts.iter().zip(&transactions).enumerate()
+            {
+                for (transaction_log_index, log) in receipt.logs.iter().enumerate() {
+                    if filter.matches(&log.address, log.topics()) {
+                        logs.push(ShadowLog {
+                            address: log.address.to_lower_hex(),
+                            block_hash: header.hash().to_lower_hex(),
+                            block_log_index,
+                            block_number,
+                            block_timestamp: header.timestamp,
+                            transaction_index: transaction_index as u64,
+                            transaction_hash: transaction.hash.to_lower_hex(),
+                            transaction_log_index: transaction_log_index as u64,
+                            removed: false,
+                            data: Some(log.data.data.to_lower_hex()),
+                            topic_0: log.topics().first().map(|t| t.to_lower_hex()),
+                            topic_1: log.topics().get(1).map(|t| t.to_lower_hex()),
+                            topic_2: log.topics().get(2).map(|t| t.to_lower_hex()),
+                            topic_3: log.topics().get(3).map(|t| t.to_lower_hex()),
+                        });
+                    }
+                    block_log_index += 1;
+                }
+            }
+        }
+
+        Ok(logs)
+    }
+}
+
+/// Serves the `eth` namespace with the logs of shadowed contracts replaced by their shadow logs.
+///
+/// The `logIndex` of a shadow log is its position in the shadow execution of the block, so it may
+/// coincide with the `logIndex` of a canonical log of another contract in the same block.
+///
+/// Subscriptions receive the logs of every indexed block. When a block is reverted its shadow logs
+/// are sent again with `removed: true`, but its canonical logs are not, as they are no longer
+/// available from the node.
+#[derive(Debug)]
+pub struct ShadowOverlay<P> {
+    /// Serves the shadow logs.
+    rpc: ShadowRpc<P>,
+    /// Lowercase hex addresses of the shadowed contracts, whose canonical logs are replaced.
+    shadowed: Arc<HashSet<String>>,
+}
+
+impl<P> ShadowOverlay<P>
+where
+    P: BlockResolver + CanonicalLogs,
+{
+    /// Creates an overlay serving the shadow logs of `rpc` in place of the canonical logs of the
+    /// `shadowed` contracts.
+    pub fn new(rpc: ShadowRpc<P>, shadowed: impl IntoIterator<Item = Address>) -> Self {
+        let shadowed = shadowed.into_iter().map(|address| address.to_lower_hex()).collect();
+        Self { rpc, shadowed: Arc::new(shadowed) }
+    }
+
+    /// Serves the overlay on `addr`, forwarding all methods other than `eth_getLogs`,
+    /// `eth_subscribe` and `eth_unsubscribe` to the HTTP RPC at `upstream`.
+    pub async fn serve(self, addr: SocketAddr, upstream: String) -> Result<ServerHandle> {
+        Ok(OverlayListener::bind(addr, upstream).await?.start(self))
+    }
+}
+
+/// The server of a [`ShadowOverlay`], bound to its address but not started yet.
+///
+/// The server can be bound before the node is launched, so that a failure to bind stops the node,
+/// and started once the shadow RPC is available.
+pub struct OverlayListener {
+    /// Address on which the server listens.
+    local_addr: SocketAddr,
+    /// URL of the upstream HTTP RPC.
+    upstream: Arc<str>,
+    /// Starts the server with the overlay methods.
+    start: Box<dyn FnOnce(Methods) -> ServerHandle + Send>,
+}
+
+impl Debug for OverlayListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OverlayListener")
+            .field("local_addr", &self.local_addr)
+            .field("upstream", &self.upstream)
+            .finish()
+    }
+}
+
+impl OverlayListener {
+    /// Binds the overlay server to `addr`, forwarding all methods other than `eth_getLogs`,
+    /// `eth_subscribe` and `eth_unsubscribe` to the HTTP RPC at `upstream`.
+    pub async fn bind(addr: SocketAddr, upstream: String) -> Result<Self> {
+        let client = reqwest::Client::new();
+        let upstream: Arc<str> = upstream.into();
+        let middleware = RpcServiceBuilder::new().layer_fn({
+            let upstream = upstream.clone();
+            move |service| ForwardToUpstream {
+                service,
+                client: client.clone(),
+                upstream: upstream.clone(),
+            }
+        });
+        let server = Server::builder().set_rpc_middleware(middleware).build(addr).await?;
+        let local_addr = server.local_addr()?;
+
+        Ok(Self { local_addr, upstream, start: Box::new(move |methods| server.start(methods)) })
+    }
+
+    /// Returns the address on which the server listens.
+    pub const fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Starts serving `overlay`.
+    pub fn start<P>(self, overlay: ShadowOverlay<P>) -> ServerHandle
+    where
+        P: BlockResolver + CanonicalLogs,
+    {
+        info!(addr = %self.local_addr, upstream = %self.upstream, "Serving the shadow overlay");
+        (self.start)(overlay.into_rpc().into())
+    }
+}
+
+/// Replaces the canonical logs of the `shadowed` contracts with `shadow` logs, ordered by block,
+/// transaction and position in the transaction.
+fn merge(
+    shadowed: &HashSet<String>,
+    canonical: Vec<ShadowLog>,
+    shadow: Vec<ShadowLog>,
+) -> Vec<ShadowLog> {
+    let mut logs = canonical
+        .into_iter()
+        .filter(|log| !shadowed.contains(&log.address))
+        .chain(shadow)
+        .collect::<Vec<_>>();
+    logs.sort_by_key(|log| (log.block_number, log.transaction_index, log.transaction_log_index));
+    logs
+}
+
+/// Returns the canonical and shadow logs of `query`, merged with [`merge`].
+async fn overlay_logs<P>(
+    rpc: &ShadowRpc<P>,
+    shadowed: &HashSet<String>,
+    query: ValidatedQueryParams,
+) -> RpcResult<Vec<ShadowLog>>
+where
+    P: BlockResolver + CanonicalLogs,
+{
+    let canonical = rpc.resolver.canonical_logs(&LogFilter::from(query.clone())).await?;
+    let shadow = exec_query(query, rpc.storage.as_ref()).await?;
+    Ok(merge(shadowed, canonical, shadow))
+}
+
+#[async_trait]
+impl<P> ShadowOverlayApiServer for ShadowOverlay<P>
+where
+    P: BlockResolver + CanonicalLogs,
+{
+    async fn get_logs(&self, params: GetLogsParameters) -> RpcResult<Vec<RpcLog>> {
+        let mut query =
+            ValidatedQueryParams::from_get_logs_parameters(&self.rpc.resolver, params.clone())
+                .await?;
+        query.block_id = ensure_indexed(self.rpc.storage.as_ref(), &params, query.block_id).await?;
+        let Some(block_id) = ensure_committed(
+            &self.rpc.resolver,
+            self.rpc.storage.as_ref(),
+            params.commitment.unwrap_or_default(),
+            query.block_id,
+        )
+        .await?
+        else {
+            return Ok(Vec::new())
+        };
+        query.block_id = block_id;
+        ensure_within_block_range(&self.rpc.limits, &query.block_id)?;
+        ensure_not_pruned(&self.rpc.resolver, self.rpc.storage.as_ref(), &query.block_id).await?;
+
+        // The shadow logs of reverted blocks are kept with `removed: true`, which `eth_getLogs`
+        // does not return.
+        let logs = overlay_logs(&self.rpc, &self.shadowed, query)
+            .await?
+            .into_iter()
+            .filter(|log| !log.removed)
+            .map(RpcLog::from)
+            .collect::<Vec<_>>();
+        ensure_within_max_results(&self.rpc.limits, logs.len())?;
+        Ok(logs)
+    }
+
+    async fn subscribe(
+        &self,
+        pending: PendingSubscriptionSink,
+        kind: String,
+        params: Option<SubscribeParameters>,
+    ) -> SubscriptionResult {
+        if kind != "logs" {
+            pending
+                .reject(ErrorObject::owned::<()>(
+                    INVALID_PARAMS_CODE,
+                    format!("unsupported subscription `{kind}`: the overlay only serves `logs`"),
+                    None,
+                ))
+                .await;
+            return Ok(())
+        }
+        let params = params.unwrap_or_default();
+        if params.from_block.is_some() || params.cursor.is_some() || params.commitment.is_some() {
+            pending
+                .reject(ErrorObject::owned::<()>(
+                    INVALID_PARAMS_CODE,
+                    "fromBlock, cursor and commitment are only supported by shadow_subscribe",
+                    None,
+                ))
+                .await;
+            return Ok(())
+        }
+        if let Err(err) = ValidatedQueryParams::validate_addresses(params.address.clone())
+            .and_then(|_| ValidatedQueryParams::validate_topics(params.topics.clone()))
+        {
+            pending.reject(err).await;
+            return Ok(())
+        }
+
+        let sink = pending.accept().await?;
+        tokio::spawn({
+            let rpc = ShadowRpc::new(
+                self.rpc.resolver.clone(),
+                self.rpc.storage.clone(),
+                self.rpc.indexed_block_receiver.resubscribe(),
+            );
+            let shadowed = self.shadowed.clone();
+            async move {
+                let _ = handle_accepted(rpc, &shadowed, sink, params).await;
+            }
+        });
+
+        Ok(())
+    }
+}
+
+/// Sends the merged logs of every block indexed by the ExEx to `sink`.
+async fn handle_accepted<P>(
+    mut rpc: ShadowRpc<P>,
+    shadowed: &HashSet<String>,
+    sink: SubscriptionSink,
+    params: SubscribeParameters,
+) -> Result<(), ErrorObject<'static>>
+where
+    P: BlockResolver + CanonicalLogs,
+{
+    let internal_error = |e: &dyn std::fmt::Display| {
+        ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
+    };
+    loop {
+        match rpc.indexed_block_receiver.recv().await {
+            Ok(event) => {
+                let shadow =
+                    block_logs(&rpc.resolver, rpc.storage.as_ref(), params.clone(), event).await?;
+                // The canonical logs of a reverted block are no longer available from the node.
+                let canonical = match event {
+                    IndexedBlockEvent::Committed(block_hash) => {
+                        let query = ValidatedQueryParams::from_subscribe_parameters(
+                            &rpc.resolver,
+                            params.clone(),
+                            block_hash,
+                        )
+                        .await?;
+                        rpc.resolver.canonical_logs(&LogFilter::from(query)).await?
+                    }
+                    IndexedBlockEvent::Reverted(_) => Vec::new(),
+                };
+                for log in merge(shadowed, canonical, shadow) {
+                    let message = SubscriptionMessage::from_json(&RpcLog::from(log))
+                        .map_err(|e| internal_error(&e))?;
+                    sink.send(message).await.map_err(|e| internal_error(&e))?;
+                }
+            }
+            Err(RecvError::Lagged(lag_count)) => {
+                warn!("lagged by {} messages; consider increasing buffer if syncing", lag_count);
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+
+    Ok(())
+}
+
+/// RPC middleware which forwards the methods not served by the overlay to the upstream RPC.
+#[derive(Debug, Clone)]
+struct ForwardToUpstream<S> {
+    /// The overlay methods.
+    service: S,
+    /// Client of the upstream RPC.
+    client: reqwest::Client,
+    /// URL of the upstream HTTP RPC.
+    upstream: Arc<str>,
+}
+
+/// Response of the upstream RPC to a single request.
+#[derive(Debug, Deserialize)]
+struct UpstreamResponse {
+    /// The result, which is `null` for errors.
+    #[serde(default)]
+    result: serde_json::Value,
+    /// The error, if the request failed.
+    error: Option<ErrorObjectOwned>,
+}
+
+impl<'a, S> RpcServiceT<'a> for ForwardToUpstream<S>
+where
+    S: RpcServiceT<'a> + Send + Sync,
+    S::Future: Send + 'a,
+{
+    type Future = Pin<Box<dyn Future<Output = MethodResponse> + Send + 'a>>;
+
+    fn call(&self, request: Request<'a>) -> Self::Future {
+        if OVERLAY_METHODS.contains(&request.method_name()) {
+            return Box::pin(self.service.call(request))
+        }
+
+        let client = self.client.clone();
+        let upstream = self.upstream.clone();
+        Box::pin(async move {
+            let response = async {
+                client
+                    .post(&*upstream)
+                    .json(&request)
+                    .send()
+                    .await?
+                    .json::<UpstreamResponse>()
+                    .await
+            };
+            match response.await {
+                Ok(UpstreamResponse { error: Some(error), .. }) => {
+                    MethodResponse::error(request.id(), error)
+                }
+                Ok(UpstreamResponse { result, .. }) => MethodResponse::response(
+                    request.id(),
+                    ResponsePayload::result(result),
+                    MAX_RESPONSE_SIZE,
+                ),
+                Err(e) => MethodResponse::error(
+                    request.id(),
+                    ErrorObject::owned::<()>(
+                        INTERNAL_ERROR_CODE,
+                        format!("upstream request failed: {e}"),
+                        None,
+                    ),
+                ),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use shadow_reth_common::ShadowLog;
+
+    use super::merge;
+
+    const SHADOWED: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+    const CANONICAL: &str = "0xc02aaa39b223fe8d0a0e5c4f27ead9083c756cc2";
+
+    fn log(address: &str, transaction_index: u64, transaction_log_index: u64) -> ShadowLog {
+        ShadowLog {
+            address: address.to_string(),
+            block_hash: "0x01".to_string(),
+            block_log_index: 0,
+            block_number: 1,
+            block_timestamp: 0,
+            transaction_index,
+            transaction_hash: format!("0x{transaction_index:02x}"),
+            transaction_log_index,
+            removed: false,
+            data: None,
+            topic_0: None,
+            topic_1: None,
+            topic_2: None,
+            topic_3: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_replaces_shadowed_logs() {
+        let shadowed = HashSet::from([SHADOWED.to_string()]);
+        let canonical = vec![
+            log(SHADOWED, 0, 0),
+            log(CANONICAL, 0, 1),
+            log(CANONICAL, 2, 0),
+            log(SHADOWED, 2, 1),
+        ];
+        // Shadow logs are told apart from canonical logs by their data.
+        let shadow = [(0, 0), (1, 0), (2, 1)]
+            .map(|(transaction_index, transaction_log_index)| ShadowLog {
+                data: Some("0x5a".to_string()),
+                ..log(SHADOWED, transaction_index, transaction_log_index)
+            })
+            .to_vec();
+
+        let merged = merge(&shadowed, canonical, shadow)
+            .into_iter()
+            .map(|log| (log.address, log.transaction_index, log.data))
+            .collect::<Vec<_>>();
+        let shadow_data = Some("0x5a".to_string());
+        assert_eq!(
+            merged,
+            vec![
+                (SHADOWED.to_string(), 0, shadow_data.clone()),
+                (CANONICAL.to_string(), 0, None),
+                (SHADOWED.to_string(), 1, shadow_data.clone()),
+                (CANONICAL.to_string(), 2, None),
+                (SHADOWED.to_string(), 2, shadow_data),
+            ]
+        );
+    }
+}
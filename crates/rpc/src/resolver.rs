@@ -0,0 +1,93 @@
+//! Resolution of block tags and block hashes to block numbers.
+
+use std::sync::Arc;
+
+use jsonrpsee::{
+    core::{async_trait, RpcResult},
+    types::{
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject,
+    },
+};
+use reth_primitives::{BlockNumberOrTag, B256};
+use reth_provider::{BlockNumReader, BlockReaderIdExt};
+use shadow_reth_common::ShadowStorage;
+
+/// Resolves the block tags and block hashes of RPC requests to block numbers.
+///
+/// Implemented for reth providers, and by [`IndexedBlockResolver`] for serving the shadow
+/// namespace without a node.
+#[async_trait]
+pub trait BlockResolver: Clone + Send + Sync + Unpin + 'static {
+    /// Returns the number of the block with the given tag, or `None` if there is no such block.
+    async fn block_number_by_tag(&self, tag: BlockNumberOrTag) -> RpcResult<Option<u64>>;
+
+    /// Returns the number of the block with the given hash, or `None` if the block is unknown.
+    async fn block_number_by_hash(&self, block_hash: B256) -> RpcResult<Option<u64>>;
+}
+
+#[async_trait]
+impl<P> BlockResolver for P
+where
+    P: BlockNumReader + BlockReaderIdExt + Clone + Unpin + 'static,
+{
+    async fn block_number_by_tag(&self, tag: BlockNumberOrTag) -> RpcResult<Option<u64>> {
+        if let BlockNumberOrTag::Number(number) = tag {
+            return Ok(Some(number))
+        }
+        self.block_by_number_or_tag(tag)
+            .map(|block| block.map(|block| block.number))
+            .map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))
+    }
+
+    async fn block_number_by_hash(&self, block_hash: B256) -> RpcResult<Option<u64>> {
+        self.block_number(block_hash).map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))
+    }
+}
+
+/// Resolves block tags and hashes from the blocks indexed by the ExEx, for serving the shadow
+/// namespace from a shadow database without a reth node.
+///
+/// `latest` and `pending` resolve to the last block processed by the ExEx and `earliest` to the
+/// genesis block. `safe` and `finalized` are not known to the shadow database, and are rejected.
+#[derive(Debug, Clone)]
+pub struct IndexedBlockResolver {
+    storage: Arc<dyn ShadowStorage>,
+}
+
+impl IndexedBlockResolver {
+    /// Creates a resolver reading indexed blocks from `storage`.
+    pub fn new(storage: Arc<dyn ShadowStorage>) -> Self {
+        Self { storage }
+    }
+}
+
+#[async_trait]
+impl BlockResolver for IndexedBlockResolver {
+    async fn block_number_by_tag(&self, tag: BlockNumberOrTag) -> RpcResult<Option<u64>> {
+        match tag {
+            BlockNumberOrTag::Number(number) => Ok(Some(number)),
+            BlockNumberOrTag::Earliest => Ok(Some(0)),
+            BlockNumberOrTag::Latest | BlockNumberOrTag::Pending => Ok(self
+                .storage
+                .checkpoint()
+                .await
+                .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+                .map(|checkpoint| checkpoint.block_number)),
+            BlockNumberOrTag::Safe | BlockNumberOrTag::Finalized => Err(ErrorObject::owned::<()>(
+                INVALID_PARAMS_CODE,
+                format!("block tag `{tag}` is not supported without a reth node"),
+                None,
+            )),
+        }
+    }
+
+    async fn block_number_by_hash(&self, block_hash: B256) -> RpcResult<Option<u64>> {
+        Ok(self
+            .storage
+            .indexed_block(block_hash)
+            .await
+            .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+            .map(|block| block.block_number))
+    }
+}
@@ -1,111 +1,293 @@
-use std::{num::ParseIntError, str::FromStr};
+use std::str::FromStr;
 
 use jsonrpsee::{
     core::RpcResult,
-    types::{error::INTERNAL_ERROR_CODE, ErrorObject},
+    types::{
+        error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+        ErrorObject,
+    },
+};
+use reth_primitives::{Address, BlockNumberOrTag, B256};
+use shadow_reth_common::{
+    BlockFilter, IndexedBlockEvent, LogFilter, LogPage, ShadowDbError, ShadowLog, ShadowStorage,
 };
-use reth_primitives::{hex, Address, BlockNumberOrTag, B256};
-use reth_provider::{BlockNumReader, BlockReaderIdExt};
-use shadow_reth_common::ShadowLog;
-use sqlx::{Pool, Sqlite};
 
-use crate::apis::{AddressRepresentation, GetLogsParameters, SubscribeParameters};
+use crate::{
+    apis::{
+        AddressRepresentation, Commitment, GetLogsParameters, SubscribeParameters,
+        TopicRepresentation,
+    },
+    BlockResolver,
+};
 
 pub(crate) async fn exec_query(
     query_params: ValidatedQueryParams,
-    pool: &Pool<Sqlite>,
+    storage: &dyn ShadowStorage,
+) -> RpcResult<Vec<ShadowLog>> {
+    storage
+        .get_logs(&query_params.into())
+        .await
+        .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))
+}
+
+/// Returns the shadow logs of the block of `event` matching `params`. The logs of a reverted block
+/// are returned with `removed: true`, and those of a committed block with `removed: false`.
+pub(crate) async fn block_logs(
+    resolver: &impl BlockResolver,
+    storage: &dyn ShadowStorage,
+    params: SubscribeParameters,
+    event: IndexedBlockEvent,
+) -> RpcResult<Vec<ShadowLog>> {
+    let query_params =
+        ValidatedQueryParams::from_subscribe_parameters(resolver, params, event.block_hash())
+            .await?;
+    let mut logs = exec_query(query_params, storage).await?;
+    for log in &mut logs {
+        log.removed = event.is_reverted();
+    }
+    Ok(logs)
+}
+
+/// Executes `query_params`, failing if more than `limits.max_results` logs match. At most one
+/// log more than the limit is read from `storage`.
+pub(crate) async fn exec_limited_query(
+    query_params: ValidatedQueryParams,
+    storage: &dyn ShadowStorage,
+    limits: &LogQueryLimits,
 ) -> RpcResult<Vec<ShadowLog>> {
-    let base_stmt = "
-        SELECT
-            address,
-            block_hash,
-            block_log_index,
-            block_number,
-            block_timestamp,
-            data,
-            removed,
-            topic_0,
-            topic_1,
-            topic_2,
-            topic_3,
-            transaction_hash,
-            transaction_index,
-            transaction_log_index
-        FROM shadow_logs";
-    let sql = format!("{base_stmt} {query_params}");
-    let raw_rows: Vec<RawGetLogsRow> = sqlx::query_as(&sql)
-        .fetch_all(pool)
+    let page =
+        LogPage { after: None, limit: limits.max_results.saturating_add(1), include_removed: true };
+    let logs = storage
+        .get_logs_page(&query_params.into(), &page)
         .await
         .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?;
+    ensure_within_max_results(limits, logs.len())?;
+    Ok(logs)
+}
 
-    raw_rows
-        .into_iter()
-        .map(ShadowLog::try_from)
-        .collect::<Result<Vec<ShadowLog>, ParseIntError>>()
-        .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))
+/// Error code of requests exceeding the [`LogQueryLimits`].
+pub(crate) const LIMIT_EXCEEDED_CODE: i32 = -32005;
+
+/// Limits on the logs selected by a single request, which keep wide queries on busy contracts
+/// from loading millions of logs into memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogQueryLimits {
+    /// Maximum number of blocks in the block range of a `shadow_getLogs` request.
+    pub max_block_range: u64,
+    /// Maximum number of logs returned by a `shadow_getLogs` request, and the maximum page size
+    /// of `shadow_getLogsPage`.
+    pub max_results: usize,
+}
+
+impl Default for LogQueryLimits {
+    fn default() -> Self {
+        Self { max_block_range: 100_000, max_results: 20_000 }
+    }
+}
+
+/// Fails if the block range of `block_id` spans more than `limits.max_block_range` blocks.
+pub(crate) fn ensure_within_block_range(
+    limits: &LogQueryLimits,
+    block_id: &ValidatedBlockIdParam,
+) -> RpcResult<()> {
+    let ValidatedBlockIdParam::BlockRange(from_block, to_block) = block_id else { return Ok(()) };
+    match to_block.saturating_sub(*from_block).saturating_add(1) {
+        blocks if blocks > limits.max_block_range => Err(ErrorObject::owned::<()>(
+            LIMIT_EXCEEDED_CODE,
+            format!(
+                "block range of {blocks} blocks exceeds the maximum of {}",
+                limits.max_block_range
+            ),
+            None,
+        )),
+        _ => Ok(()),
+    }
 }
 
-/// Helper type for ease of use in converting rows from the `shadow_getLogs`
-/// query into the `GetLogsResult` type which is used in `GetLogsResponse`.
-#[derive(Debug, sqlx::FromRow)]
-pub(crate) struct RawGetLogsRow {
-    /// Address from which a log originated.
-    pub(crate) address: Vec<u8>,
-    /// Hash of bock from which a log orignated.
-    pub(crate) block_hash: Vec<u8>,
-    /// Integer of the log index position in its containing block.
-    pub(crate) block_log_index: String,
-    /// Block number from which a log originated.
-    pub(crate) block_number: String,
-    /// Timestamp of block from which the log originated.
-    pub(crate) block_timestamp: String,
-    /// Contains one or more 32-byte non-indexed arguments of the log.
-    pub(crate) data: Option<Vec<u8>>,
-    /// Indicates whether a log was removed from the canonical chain.
-    pub(crate) removed: bool,
-    /// Hash of event signature.
-    pub(crate) topic_0: Option<Vec<u8>>,
-    /// Additional topic #1.
-    pub(crate) topic_1: Option<Vec<u8>>,
-    /// Additional topic #2.
-    pub(crate) topic_2: Option<Vec<u8>>,
-    /// Additional topic #3.
-    pub(crate) topic_3: Option<Vec<u8>>,
-    /// Hash of the transaction from which a log originated.
-    pub(crate) transaction_hash: Vec<u8>,
-    /// Integer of the transaction index position in a log's containing block.
-    pub(crate) transaction_index: String,
-    /// Integer of the log index position within a transaction.
-    pub(crate) transaction_log_index: String,
+/// Fails if `results` logs exceed `limits.max_results`.
+pub(crate) fn ensure_within_max_results(limits: &LogQueryLimits, results: usize) -> RpcResult<()> {
+    if results > limits.max_results {
+        return Err(ErrorObject::owned::<()>(
+            LIMIT_EXCEEDED_CODE,
+            format!(
+                "query returned more than {} logs: narrow the filter or use shadow_getLogsPage",
+                limits.max_results
+            ),
+            None,
+        ))
+    }
+    Ok(())
 }
 
-impl TryFrom<RawGetLogsRow> for ShadowLog {
-    type Error = ParseIntError;
-
-    fn try_from(value: RawGetLogsRow) -> Result<Self, Self::Error> {
-        Ok(Self {
-            address: format!("0x{}", hex::encode(value.address)),
-            block_hash: format!("0x{}", hex::encode(value.block_hash)),
-            block_log_index: u64::from_str(&value.block_log_index)?,
-            block_number: u64::from_str(&value.block_number)?,
-            block_timestamp: u64::from_str(&value.block_timestamp)?,
-            transaction_index: u64::from_str(&value.transaction_index)?,
-            transaction_hash: format!("0x{}", hex::encode(value.transaction_hash)),
-            transaction_log_index: u64::from_str(&value.transaction_log_index)?,
-            removed: value.removed,
-            data: value.data.map(|d| format!("0x{}", hex::encode(d))),
-            topic_0: value.topic_0.map(|t| format!("0x{}", hex::encode(t))),
-            topic_1: value.topic_1.map(|t| format!("0x{}", hex::encode(t))),
-            topic_2: value.topic_2.map(|t| format!("0x{}", hex::encode(t))),
-            topic_3: value.topic_3.map(|t| format!("0x{}", hex::encode(t))),
-        })
+/// Fails with an invalid params error if `block_id` reaches below the pruned horizon of the
+/// shadow database, as the logs of those blocks are no longer available.
+pub(crate) async fn ensure_not_pruned(
+    resolver: &impl BlockResolver,
+    storage: &dyn ShadowStorage,
+    block_id: &ValidatedBlockIdParam,
+) -> RpcResult<()> {
+    let Some(horizon) = storage
+        .pruned_horizon()
+        .await
+        .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+    else {
+        return Ok(())
+    };
+
+    let from_block = match block_id {
+        ValidatedBlockIdParam::BlockRange(from_block, _) => Some(*from_block),
+        ValidatedBlockIdParam::BlockHash(block_hash) => resolver
+            .block_number_by_hash(*block_hash)
+            .await
+            .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.message(), None))?,
+    };
+    match from_block {
+        Some(from_block) if from_block < horizon => Err(ErrorObject::owned::<()>(
+            INVALID_PARAMS_CODE,
+            format!(
+                "block {from_block} is below the pruned horizon: logs before block {horizon} have \
+                 been pruned"
+            ),
+            None,
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Error code of requests for blocks which have not been indexed by the ExEx yet.
+pub(crate) const NOT_INDEXED_CODE: i32 = -32000;
+
+/// Ensures that the logs selected by `params` can be served from the shadow database, so that an
+/// empty result means that the blocks did not emit any shadow logs, rather than that they have
+/// not been processed yet.
+///
+/// A block requested by hash must have been indexed, and so must every block of a block range
+/// which has not been pruned. Bounds given as tags, such as the default `latest`, are clamped to
+/// the last indexed block instead, as the ExEx may lag behind the node.
+pub(crate) async fn ensure_indexed(
+    storage: &dyn ShadowStorage,
+    params: &GetLogsParameters,
+    block_id: ValidatedBlockIdParam,
+) -> RpcResult<ValidatedBlockIdParam> {
+    let internal_error =
+        |e: ShadowDbError| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None);
+    let not_indexed = |message: String| ErrorObject::owned::<()>(NOT_INDEXED_CODE, message, None);
+
+    if let Some(block_hash) = &params.block_hash {
+        let block_hash = parse_b256("blockHash", block_hash)?;
+        if storage.indexed_block(block_hash).await.map_err(internal_error)?.is_none() {
+            return Err(not_indexed(format!("block {block_hash} has not been indexed")))
+        }
+        return Ok(block_id)
+    }
+
+    let ValidatedBlockIdParam::BlockRange(from_block, to_block) = block_id else {
+        return Ok(block_id)
+    };
+    let Some(checkpoint) = storage.checkpoint().await.map_err(internal_error)? else {
+        return Err(not_indexed("no blocks have been indexed yet".to_string()))
+    };
+    let last_indexed = checkpoint.block_number;
+    let clamp =
+        |param: &Option<String>, block: u64| match param.as_deref().map(BlockNumberOrTag::from_str)
+        {
+            Some(Ok(BlockNumberOrTag::Number(_))) => block,
+            _ => block.min(last_indexed),
+        };
+    let (from_block, to_block) =
+        (clamp(&params.from_block, from_block), clamp(&params.to_block, to_block));
+
+    let block = from_block.max(to_block);
+    if block > last_indexed {
+        return Err(not_indexed(format!(
+            "block {block} has not been indexed yet: the last indexed block is {last_indexed}"
+        )))
+    }
+
+    // The ExEx may have skipped blocks below the checkpoint, for example while it was disabled.
+    // Blocks below the pruned horizon are rejected by `ensure_not_pruned` instead.
+    let horizon = storage.pruned_horizon().await.map_err(internal_error)?.unwrap_or_default();
+    let first_block = from_block.max(horizon);
+    if first_block <= to_block {
+        let indexed =
+            storage.indexed_block_count(first_block, to_block).await.map_err(internal_error)?;
+        let missing = to_block - first_block + 1 - indexed;
+        if missing > 0 {
+            return Err(not_indexed(format!(
+                "{missing} of the blocks {first_block} to {to_block} have not been indexed"
+            )))
+        }
+    }
+
+    Ok(ValidatedBlockIdParam::BlockRange(from_block, to_block))
+}
+
+/// Returns the last block which has reached `commitment`, or `None` if no block has yet.
+///
+/// Confirmations are counted on top of the last indexed block. The `safe` and `finalized` blocks
+/// are resolved like the tags of `fromBlock` and `toBlock`, and capped at the last indexed block,
+/// as the ExEx may lag behind the node.
+pub(crate) async fn committed_head(
+    resolver: &impl BlockResolver,
+    storage: &dyn ShadowStorage,
+    commitment: Commitment,
+) -> RpcResult<Option<u64>> {
+    let Some(checkpoint) = storage
+        .checkpoint()
+        .await
+        .map_err(|e| ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None))?
+    else {
+        return Ok(None)
+    };
+    let last_indexed = checkpoint.block_number;
+
+    Ok(match commitment {
+        Commitment::Latest => Some(last_indexed),
+        Commitment::Confirmations(confirmations) => last_indexed.checked_sub(confirmations),
+        Commitment::Safe => resolver
+            .block_number_by_tag(BlockNumberOrTag::Safe)
+            .await?
+            .map(|block| block.min(last_indexed)),
+        Commitment::Finalized => resolver
+            .block_number_by_tag(BlockNumberOrTag::Finalized)
+            .await?
+            .map(|block| block.min(last_indexed)),
+    })
+}
+
+/// Limits `block_id` to the blocks which have reached `commitment`, see [`committed_head`].
+/// Returns `None` if none of them has, in which case no logs are selected.
+pub(crate) async fn ensure_committed(
+    resolver: &impl BlockResolver,
+    storage: &dyn ShadowStorage,
+    commitment: Commitment,
+    block_id: ValidatedBlockIdParam,
+) -> RpcResult<Option<ValidatedBlockIdParam>> {
+    if commitment == Commitment::Latest {
+        return Ok(Some(block_id))
+    }
+
+    let (from_block, to_block) = match block_id {
+        ValidatedBlockIdParam::BlockRange(from_block, to_block) => (from_block, to_block),
+        ValidatedBlockIdParam::BlockHash(block_hash) => {
+            match resolver.block_number_by_hash(block_hash).await? {
+                Some(block_number) => (block_number, block_number),
+                None => return Ok(None),
+            }
+        }
+    };
+    match committed_head(resolver, storage, commitment).await? {
+        Some(head) if from_block <= head => {
+            Ok(Some(ValidatedBlockIdParam::BlockRange(from_block, to_block.min(head))))
+        }
+        _ => Ok(None),
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) enum ValidatedBlockIdParam {
     /// Block hash from which logs will be filtered.
-    BlockHash(String),
+    BlockHash(B256),
     /// Start and end of block range from which logs will be filtered.
     BlockRange(u64, u64),
 }
@@ -116,38 +298,39 @@ pub(crate) enum ValidatedBlockIdParam {
 pub(crate) struct ValidatedQueryParams {
     pub(crate) block_id: ValidatedBlockIdParam,
     /// Set of addresses from which logs will be filtered.
-    pub(crate) addresses: Vec<String>,
-    /// Set of log topics.
-    pub(crate) topics: [Option<String>; 4],
+    pub(crate) addresses: Vec<Address>,
+    /// Alternatives for each log topic position. An empty list matches any topic.
+    pub(crate) topics: [Vec<B256>; 4],
+}
+
+/// Returns an invalid params error for the given parameter.
+fn invalid_params(param: &str, message: impl std::fmt::Display) -> ErrorObject<'static> {
+    ErrorObject::owned::<()>(INVALID_PARAMS_CODE, format!("invalid {param}: {message}"), None)
+}
+
+/// Parses a `0x`-prefixed, 32-byte hex string, such as a block hash or a topic.
+fn parse_b256(param: &str, value: &str) -> RpcResult<B256> {
+    value
+        .strip_prefix("0x")
+        .filter(|hex| hex.len() == 64)
+        .and_then(|hex| B256::from_str(hex).ok())
+        .ok_or_else(|| invalid_params(param, format!("expected 32-byte hex string, got {value}")))
 }
 
 impl ValidatedQueryParams {
-    fn validate_addresses(address: Option<AddressRepresentation>) -> RpcResult<Vec<String>> {
+    pub(crate) fn validate_addresses(
+        address: Option<AddressRepresentation>,
+    ) -> RpcResult<Vec<Address>> {
         let v = if let Some(addr_repr) = address {
             match addr_repr {
                 AddressRepresentation::String(addr) => {
-                    let parsed = addr
-                        .parse::<Address>()
-                        .map_err(|e| {
-                            ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
-                        })?
-                        .to_string();
-                    vec![parsed]
+                    vec![addr.parse::<Address>().map_err(|e| invalid_params("address", e))?]
                 }
                 AddressRepresentation::ArrayOfStrings(array) => array
                     .into_iter()
-                    .map(|addr| {
-                        addr.parse::<Address>().map_err(|e| {
-                            ErrorObject::owned::<()>(INTERNAL_ERROR_CODE, e.to_string(), None)
-                        })
-                    })
-                    .collect::<RpcResult<Vec<Address>>>()?
-                    .into_iter()
-                    .map(|a| a.to_string())
-                    .collect::<Vec<String>>(),
-                AddressRepresentation::Bytes(bytes) => {
-                    vec![Address::from_slice(&bytes).to_string()]
-                }
+                    .map(|addr| addr.parse::<Address>().map_err(|e| invalid_params("address", e)))
+                    .collect::<RpcResult<Vec<Address>>>()?,
+                AddressRepresentation::Bytes(bytes) => vec![Address::from(bytes)],
             }
         } else {
             vec![]
@@ -156,322 +339,218 @@ impl ValidatedQueryParams {
         Ok(v)
     }
 
-    fn validate_topics(topics: Option<Vec<String>>) -> RpcResult<[Option<String>; 4]> {
-        let v = if let Some(t_list) = topics {
-            if t_list.len() > 4 {
-                return Err(ErrorObject::owned::<()>(
-                    32002,
-                    "Only up to four topics are allowed",
-                    None,
-                ));
-            } else {
-                let mut topics: [Option<String>; 4] = [None, None, None, None];
-
-                for (idx, topic) in t_list.into_iter().enumerate() {
-                    topics[idx] = Some(topic);
-                }
+    pub(crate) fn validate_topics(
+        topics: Option<Vec<Option<TopicRepresentation>>>,
+    ) -> RpcResult<[Vec<B256>; 4]> {
+        let t_list = topics.unwrap_or_default();
+        if t_list.len() > 4 {
+            return Err(invalid_params("topics", "only up to four topics are allowed"));
+        }
 
-                topics
-            }
-        } else {
-            [None, None, None, None]
-        };
+        let mut topics: [Vec<B256>; 4] = Default::default();
+        for (idx, topic) in t_list.into_iter().enumerate() {
+            topics[idx] = match topic {
+                None => vec![],
+                Some(TopicRepresentation::String(topic)) => vec![parse_b256("topic", &topic)?],
+                Some(TopicRepresentation::ArrayOfStrings(alternatives)) => alternatives
+                    .iter()
+                    .map(|topic| parse_b256("topic", topic))
+                    .collect::<RpcResult<_>>()?,
+            };
+        }
 
-        Ok(v)
+        Ok(topics)
     }
 
-    fn validate_block_id(
-        provider: &(impl BlockNumReader + BlockReaderIdExt),
-        block_hash: Option<String>,
+    async fn validate_block_id(
+        resolver: &impl BlockResolver,
+        block_hash: Option<B256>,
         from_block: Option<String>,
         to_block: Option<String>,
         resolve_block_hash: bool,
     ) -> RpcResult<ValidatedBlockIdParam> {
         let v = match (block_hash, from_block, to_block) {
-            (None, None, None) => {
-                let num = match provider.block_by_number_or_tag(BlockNumberOrTag::Latest) {
-                    Ok(Some(b)) => b.number,
-                    Ok(None) => {
-                        return Err(ErrorObject::owned::<()>(
-                            -1,
-                            "No block found for block number or tag: latest",
-                            None,
-                        ))
-                    }
-                    Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                };
-                ValidatedBlockIdParam::BlockRange(num, num)
-            }
-            (None, None, Some(to_block)) => {
-                let from = match provider.block_by_number_or_tag(BlockNumberOrTag::Latest) {
-                    Ok(Some(b)) => b.number,
-                    Ok(None) => {
-                        return Err(ErrorObject::owned::<()>(
-                            -1,
-                            "No block found for block number or tag: latest",
-                            None,
-                        ))
-                    }
-                    Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                };
-                let to_tag = BlockNumberOrTag::from_str(&to_block)
-                    .map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))?;
-                let to = if let BlockNumberOrTag::Number(n) = to_tag {
-                    n
-                } else {
-                    match provider.block_by_number_or_tag(to_tag) {
-                        Ok(Some(b)) => b.number,
-                        Ok(None) => {
-                            return Err(ErrorObject::owned::<()>(
-                                -1,
-                                format!("No block found for block number or tag: {to_tag}"),
-                                None,
-                            ))
-                        }
-                        Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                    }
-                };
-                ValidatedBlockIdParam::BlockRange(from, to)
-            }
-            (None, Some(from_block), None) => {
-                let from_tag = BlockNumberOrTag::from_str(&from_block)
-                    .map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))?;
-                let from = if let BlockNumberOrTag::Number(n) = from_tag {
-                    n
-                } else {
-                    match provider.block_by_number_or_tag(from_tag) {
-                        Ok(Some(b)) => b.number,
-                        Ok(None) => {
-                            return Err(ErrorObject::owned::<()>(
-                                -1,
-                                format!("No block found for block number or tag: {from_tag}"),
-                                None,
-                            ))
-                        }
-                        Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                    }
-                };
-                let to = match provider.block_by_number_or_tag(BlockNumberOrTag::Latest) {
-                    Ok(Some(b)) => b.number,
-                    Ok(None) => {
-                        return Err(ErrorObject::owned::<()>(
-                            -1,
-                            "No block found for block number or tag: latest",
-                            None,
-                        ))
-                    }
-                    Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                };
-                ValidatedBlockIdParam::BlockRange(from, to)
-            }
-            (None, Some(from_block), Some(to_block)) => {
-                let from_tag = BlockNumberOrTag::from_str(&from_block)
-                    .map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))?;
-                let from = if let BlockNumberOrTag::Number(n) = from_tag {
-                    n
-                } else {
-                    match provider.block_by_number_or_tag(from_tag) {
-                        Ok(Some(b)) => b.number,
-                        Ok(None) => {
-                            return Err(ErrorObject::owned::<()>(
-                                -1,
-                                format!("No block found for block number or tag: {from_tag}"),
-                                None,
-                            ))
-                        }
-                        Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                    }
-                };
-                let to_tag = BlockNumberOrTag::from_str(&to_block)
-                    .map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))?;
-                let to = if let BlockNumberOrTag::Number(n) = to_tag {
-                    n
-                } else {
-                    match provider.block_by_number_or_tag(to_tag) {
-                        Ok(Some(b)) => b.number,
-                        Ok(None) => {
-                            return Err(ErrorObject::owned::<()>(
-                                -1,
-                                format!("No block found for block number or tag: {to_tag}"),
-                                None,
-                            ))
-                        }
-                        Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                    }
-                };
-
-                ValidatedBlockIdParam::BlockRange(from, to)
-            }
-            (Some(block_hash), None, None) if resolve_block_hash => {
-                let num = match provider.block_by_hash(
-                    B256::from_str(&block_hash)
-                        .map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))?,
-                ) {
-                    Ok(Some(b)) => b.number,
-                    Ok(None) => {
-                        return Err(ErrorObject::owned::<()>(
-                            -1,
-                            format!("No block found for block hash: {block_hash}"),
-                            None,
-                        ))
-                    }
-                    Err(e) => return Err(ErrorObject::owned::<()>(-1, e.to_string(), None)),
-                };
-                ValidatedBlockIdParam::BlockRange(num, num)
-            }
-            (Some(block_hash), None, None) => ValidatedBlockIdParam::BlockHash(block_hash),
             (Some(_), Some(_), _) | (Some(_), _, Some(_)) => return Err(ErrorObject::owned::<()>(
                 -32001,
                 "Parameters fromBlock and toBlock cannot be used if blockHash parameter is present",
                 None,
             )),
+            (Some(block_hash), None, None) if resolve_block_hash => {
+                let num = resolver.block_number_by_hash(block_hash).await?.ok_or_else(|| {
+                    ErrorObject::owned::<()>(
+                        -1,
+                        format!("No block found for block hash: {block_hash}"),
+                        None,
+                    )
+                })?;
+                ValidatedBlockIdParam::BlockRange(num, num)
+            }
+            (Some(block_hash), None, None) => ValidatedBlockIdParam::BlockHash(block_hash),
+            // Missing bounds default to the latest block.
+            (None, from_block, to_block) => {
+                let from = resolve_block_tag(resolver, from_block.as_deref()).await?;
+                let to = resolve_block_tag(resolver, to_block.as_deref()).await?;
+                ValidatedBlockIdParam::BlockRange(from, to)
+            }
         };
 
         Ok(v)
     }
 
-    pub(crate) fn from_get_logs_parameters(
-        provider: &(impl BlockNumReader + BlockReaderIdExt),
+    pub(crate) async fn from_get_logs_parameters(
+        resolver: &impl BlockResolver,
         params: GetLogsParameters,
     ) -> RpcResult<Self> {
         let addresses = Self::validate_addresses(params.address)?;
-        let block_id = Self::validate_block_id(
-            provider,
-            params.block_hash,
-            params.from_block,
-            params.to_block,
-            true,
-        )?;
+        let block_hash =
+            params.block_hash.map(|hash| parse_b256("blockHash", &hash)).transpose()?;
+        let block_id =
+            Self::validate_block_id(resolver, block_hash, params.from_block, params.to_block, true)
+                .await?;
         let topics = Self::validate_topics(params.topics)?;
 
         Ok(ValidatedQueryParams { block_id, addresses, topics })
     }
 
-    pub(crate) fn from_subscribe_parameters(
-        provider: &(impl BlockNumReader + BlockReaderIdExt),
+    pub(crate) async fn from_subscribe_parameters(
+        resolver: &impl BlockResolver,
         params: SubscribeParameters,
-        block_hash: String,
+        block_hash: B256,
     ) -> RpcResult<Self> {
         let addresses = Self::validate_addresses(params.address)?;
         let topics = Self::validate_topics(params.topics)?;
-        let block_id = Self::validate_block_id(provider, Some(block_hash), None, None, false)?;
+        let block_id =
+            Self::validate_block_id(resolver, Some(block_hash), None, None, false).await?;
 
         Ok(ValidatedQueryParams { block_id, addresses, topics })
     }
 }
 
-impl std::fmt::Display for ValidatedQueryParams {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let address_clause = if !self.addresses.is_empty() {
-            Some(format!(
-                "address IN ({})",
-                self.addresses
-                    .iter()
-                    .map(|addr| format!("X'{}'", &addr[2..]))
-                    .collect::<Vec<String>>()
-                    .join(", ")
-            ))
-        } else {
-            None
-        };
+/// Resolves a block number or tag parameter to a block number, defaulting to `latest`.
+pub(crate) async fn resolve_block_tag(
+    resolver: &impl BlockResolver,
+    block: Option<&str>,
+) -> RpcResult<u64> {
+    let tag = BlockNumberOrTag::from_str(block.unwrap_or("latest"))
+        .map_err(|e| ErrorObject::owned::<()>(-1, e.to_string(), None))?;
+    resolver.block_number_by_tag(tag).await?.ok_or_else(|| {
+        ErrorObject::owned::<()>(-1, format!("No block found for block number or tag: {tag}"), None)
+    })
+}
 
-        let block_range_clause = match &self.block_id {
-            ValidatedBlockIdParam::BlockHash(block_hash) => {
-                Some(format!("block_hash = X'{}'", &block_hash[2..]))
-            }
-            ValidatedBlockIdParam::BlockRange(from_block, to_block) => {
-                Some(format!("block_number BETWEEN {} AND {}", from_block, to_block))
-            }
+impl From<ValidatedQueryParams> for LogFilter {
+    fn from(value: ValidatedQueryParams) -> Self {
+        let block = match value.block_id {
+            ValidatedBlockIdParam::BlockHash(block_hash) => BlockFilter::Hash(block_hash),
+            ValidatedBlockIdParam::BlockRange(from, to) => BlockFilter::Range(from, to),
         };
-
-        let topic_0_clause = self.topics[0].as_ref().map(|t| format!("topic_0 = X'{}'", &t[2..]));
-
-        let topic_1_clause = self.topics[1].as_ref().map(|t| format!("topic_1 = X'{}'", &t[2..]));
-
-        let topic_2_clause = self.topics[2].as_ref().map(|t| format!("topic_2 = X'{}'", &t[2..]));
-
-        let topic_3_clause = self.topics[3].as_ref().map(|t| format!("topic_3 = X'{}'", &t[2..]));
-
-        let clauses = [
-            address_clause,
-            block_range_clause,
-            topic_0_clause,
-            topic_1_clause,
-            topic_2_clause,
-            topic_3_clause,
-        ];
-
-        let filtered_clauses = clauses.into_iter().flatten().collect::<Vec<String>>();
-
-        if !filtered_clauses.is_empty() {
-            write!(f, "WHERE {}", filtered_clauses.join(" AND "))
-        } else {
-            write!(f, "")
-        }
+        Self { block, addresses: value.addresses, topics: value.topics }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use reth_primitives::{Address, Block, BlockHash, Header};
+    use jsonrpsee::{
+        core::{async_trait, RpcResult},
+        types::{
+            error::{INTERNAL_ERROR_CODE, INVALID_PARAMS_CODE},
+            ErrorObject,
+        },
+    };
+    use reth_primitives::{Address, Block, BlockHash, BlockNumberOrTag, Header, B256};
     use reth_provider::test_utils::MockEthProvider;
+    use shadow_reth_common::{ShadowSqliteDb, ShadowStorage};
+
+    use super::{ensure_not_pruned, ValidatedBlockIdParam, ValidatedQueryParams};
+    use crate::{
+        apis::{
+            AddressRepresentation, GetLogsParameters, SubscribeParameters, TopicRepresentation,
+        },
+        BlockResolver,
+    };
+
+    /// A resolver whose provider fails every lookup.
+    #[derive(Debug, Clone)]
+    struct FailingResolver;
+
+    #[async_trait]
+    impl BlockResolver for FailingResolver {
+        async fn block_number_by_tag(&self, _tag: BlockNumberOrTag) -> RpcResult<Option<u64>> {
+            Err(ErrorObject::owned::<()>(-1, "provider unavailable", None))
+        }
 
-    use super::{ValidatedBlockIdParam, ValidatedQueryParams};
-    use crate::apis::{AddressRepresentation, GetLogsParameters, SubscribeParameters};
-
-    #[test]
-    fn test_display() {
-        let mock_provider = MockEthProvider::default();
-
-        let first_block =
-            Block { header: Header { number: 0, ..Default::default() }, ..Default::default() };
-        let first_block_hash = first_block.hash_slow();
-
-        let last_block =
-            Block { header: Header { number: 10, ..Default::default() }, ..Default::default() };
-        let last_block_hash = last_block.hash_slow();
+        async fn block_number_by_hash(&self, _block_hash: B256) -> RpcResult<Option<u64>> {
+            Err(ErrorObject::owned::<()>(-1, "provider unavailable", None))
+        }
+    }
 
-        mock_provider
-            .extend_blocks([(first_block_hash, first_block), (last_block_hash, last_block)]);
+    const TOPIC: &str = "0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c";
 
-        let subscribe_params = SubscribeParameters {
-            address: Some(AddressRepresentation::ArrayOfStrings(vec![Address::ZERO.to_string()])),
-            topics: Some(vec!["0xfoo".to_string()]),
-        };
+    #[tokio::test]
+    async fn test_rejects_malformed_hashes() {
+        let mock_provider = MockEthProvider::default();
 
-        assert_eq!(
-            format!(
-                "{}",
-                ValidatedQueryParams::from_subscribe_parameters(
-                    &mock_provider,
-                    subscribe_params,
-                    BlockHash::ZERO.to_string(),
-                )
-                .unwrap()
-            ),
-            "WHERE address IN (X'0000000000000000000000000000000000000000') AND block_hash = X'0000000000000000000000000000000000000000000000000000000000000000' AND topic_0 = X'foo'"
-        );
+        for topic in ["0xfoo", "0x", "", "0') OR 1=1 --", &TOPIC[2..], &TOPIC[..64]] {
+            let params = GetLogsParameters {
+                address: None,
+                block_hash: None,
+                from_block: Some("0x0".to_string()),
+                to_block: Some("0x0".to_string()),
+                topics: Some(vec![Some(TopicRepresentation::String(topic.to_string()))]),
+                commitment: None,
+            };
+            let err = ValidatedQueryParams::from_get_logs_parameters(&mock_provider, params)
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), INVALID_PARAMS_CODE);
+
+            let params = GetLogsParameters {
+                address: None,
+                block_hash: Some(topic.to_string()),
+                from_block: None,
+                to_block: None,
+                topics: None,
+                commitment: None,
+            };
+            let err = ValidatedQueryParams::from_get_logs_parameters(&mock_provider, params)
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), INVALID_PARAMS_CODE);
+        }
+    }
 
-        let get_logs_params = GetLogsParameters {
-            address: Some(AddressRepresentation::ArrayOfStrings(vec![Address::ZERO.to_string()])),
-            block_hash: Some(last_block_hash.to_string()),
-            from_block: None,
-            to_block: None,
-            topics: Some(vec!["0xfoo".to_string()]),
-        };
+    #[tokio::test]
+    async fn test_ensure_not_pruned_propagates_resolver_errors() {
+        let db = ShadowSqliteDb::new(":memory:").await.unwrap();
+        db.prune(Some(10), None).await.unwrap();
 
-        assert_eq!(
-            format!(
-                "{}",
-                ValidatedQueryParams::from_get_logs_parameters(&mock_provider, get_logs_params,)
-                    .unwrap()
-            ),
-            "WHERE address IN (X'0000000000000000000000000000000000000000') AND block_number BETWEEN 10 AND 10 AND topic_0 = X'foo'"
-        );
+        let block_id = ValidatedBlockIdParam::BlockHash(BlockHash::ZERO);
+        let err = ensure_not_pruned(&FailingResolver, &db, &block_id).await.unwrap_err();
+        assert_eq!(err.code(), INTERNAL_ERROR_CODE);
+        assert_eq!(err.message(), "provider unavailable");
     }
 
     #[test]
-    fn test_from_subscribe_parameters() {
+    fn test_validate_topics() {
+        let other = format!("0x{}", "00".repeat(32));
+        let topics =
+            serde_json::from_value(serde_json::json!([[TOPIC, other], null, TOPIC])).unwrap();
+        let [first, second, third, fourth] =
+            ValidatedQueryParams::validate_topics(Some(topics)).unwrap();
+        assert_eq!(first, vec![TOPIC.parse().unwrap(), B256::ZERO]);
+        assert!(second.is_empty());
+        assert_eq!(third, vec![TOPIC.parse::<B256>().unwrap()]);
+        assert!(fourth.is_empty());
+
+        let topics = vec![Some(TopicRepresentation::ArrayOfStrings(vec!["0xfoo".to_string()]))];
+        let err = ValidatedQueryParams::validate_topics(Some(topics)).unwrap_err();
+        assert_eq!(err.code(), INVALID_PARAMS_CODE);
+        let err = ValidatedQueryParams::validate_topics(Some(vec![None; 5])).unwrap_err();
+        assert_eq!(err.code(), INVALID_PARAMS_CODE);
+    }
+
+    #[tokio::test]
+    async fn test_from_subscribe_parameters() {
         let mock_provider = MockEthProvider::default();
 
         let first_block =
@@ -488,25 +567,27 @@ mod tests {
         let params = SubscribeParameters {
             address: Some(AddressRepresentation::ArrayOfStrings(vec![Address::ZERO.to_string()])),
             topics: None,
+            ..Default::default()
         };
 
         assert_eq!(
             ValidatedQueryParams::from_subscribe_parameters(
                 &mock_provider,
                 params,
-                BlockHash::ZERO.to_string(),
+                BlockHash::ZERO,
             )
+            .await
             .unwrap(),
             ValidatedQueryParams {
-                addresses: vec![Address::ZERO.to_string()],
-                block_id: ValidatedBlockIdParam::BlockHash(BlockHash::ZERO.to_string()),
-                topics: [None, None, None, None]
+                addresses: vec![Address::ZERO],
+                block_id: ValidatedBlockIdParam::BlockHash(BlockHash::ZERO),
+                topics: Default::default()
             }
         )
     }
 
-    #[test]
-    fn test_from_get_logs_parameters() {
+    #[tokio::test]
+    async fn test_from_get_logs_parameters() {
         let mock_provider = MockEthProvider::default();
 
         let first_block =
@@ -528,12 +609,14 @@ mod tests {
             from_block: None,
             to_block: None,
             topics: None,
+            commitment: None,
         };
 
         assert!(ValidatedQueryParams::from_get_logs_parameters(
             &mock_provider,
             params_with_block_hash
         )
+        .await
         .is_ok());
 
         let params_with_defaults = GetLogsParameters {
@@ -544,17 +627,19 @@ mod tests {
             from_block: None,
             to_block: None,
             topics: None,
+            commitment: None,
         };
 
         let validated =
-            ValidatedQueryParams::from_get_logs_parameters(&mock_provider, params_with_defaults);
+            ValidatedQueryParams::from_get_logs_parameters(&mock_provider, params_with_defaults)
+                .await;
 
         assert_eq!(
             validated.unwrap(),
             ValidatedQueryParams {
-                addresses: vec!["0x0000000000000000000000000000000000000000".to_string()],
+                addresses: vec![Address::ZERO],
                 block_id: ValidatedBlockIdParam::BlockRange(10, 10),
-                topics: [None, None, None, None]
+                topics: Default::default()
             }
         );
 
@@ -566,16 +651,18 @@ mod tests {
             from_block: Some("earliest".to_string()),
             to_block: Some("latest".to_string()),
             topics: None,
+            commitment: None,
         };
         let validated =
-            ValidatedQueryParams::from_get_logs_parameters(&mock_provider, params_with_block_tags);
+            ValidatedQueryParams::from_get_logs_parameters(&mock_provider, params_with_block_tags)
+                .await;
 
         assert_eq!(
             validated.unwrap(),
             ValidatedQueryParams {
-                addresses: vec!["0x0000000000000000000000000000000000000000".to_string()],
+                addresses: vec![Address::ZERO],
                 block_id: ValidatedBlockIdParam::BlockRange(0, 10),
-                topics: [None, None, None, None]
+                topics: Default::default()
             }
         );
 
@@ -587,18 +674,20 @@ mod tests {
             from_block: Some("earliest".to_string()),
             to_block: Some("latest".to_string()),
             topics: None,
+            commitment: None,
         };
         let validated = ValidatedQueryParams::from_get_logs_parameters(
             &mock_provider,
             params_with_non_array_address,
-        );
+        )
+        .await;
 
         assert_eq!(
             validated.unwrap(),
             ValidatedQueryParams {
-                addresses: vec!["0x0000000000000000000000000000000000000000".to_string()],
+                addresses: vec![Address::ZERO],
                 block_id: ValidatedBlockIdParam::BlockRange(0, 10),
-                topics: [None, None, None, None]
+                topics: Default::default()
             }
         );
 
@@ -611,18 +700,20 @@ mod tests {
             from_block: Some("earliest".to_string()),
             to_block: Some("latest".to_string()),
             topics: None,
+            commitment: None,
         };
         let validated = ValidatedQueryParams::from_get_logs_parameters(
             &mock_provider,
             params_with_bytes_as_address,
-        );
+        )
+        .await;
 
         assert_eq!(
             validated.unwrap(),
             ValidatedQueryParams {
-                addresses: vec!["0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".to_string()],
+                addresses: vec!["0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".parse().unwrap()],
                 block_id: ValidatedBlockIdParam::BlockRange(0, 10),
-                topics: [None, None, None, None]
+                topics: Default::default()
             }
         );
 
@@ -634,11 +725,13 @@ mod tests {
             from_block: Some(first_block_hash.to_string()),
             to_block: Some(last_block_hash.to_string()),
             topics: None,
+            commitment: None,
         };
         assert!(ValidatedQueryParams::from_get_logs_parameters(
             &mock_provider,
             params_with_invalid_address
         )
+        .await
         .is_err());
 
         let params_with_block_hash_and_range = GetLogsParameters {
@@ -649,11 +742,13 @@ mod tests {
             from_block: Some(first_block_hash.to_string()),
             to_block: Some(last_block_hash.to_string()),
             topics: None,
+            commitment: None,
         };
         assert!(ValidatedQueryParams::from_get_logs_parameters(
             &mock_provider,
             params_with_block_hash_and_range
         )
+        .await
         .is_err());
     }
 }